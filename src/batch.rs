@@ -0,0 +1,89 @@
+/*
+
+What does this file do?
+
+    Collects the events for the virtual device, so that everything caused by one
+    lot of joypad input (or one tick of mouse motion) is written at once, as one
+    report. Diagonal mouse motion then arrives as one movement instead of a move
+    across and then a move down.
+    One report can only say one thing about each key or axis though. If a key is
+    pressed and released in the same report, the press is lost (this is why mouse
+    clicks used to need two separate writes), so a new report is started whenever
+    an event would change something that the current report already changes.
+
+*/
+
+use evdev::{EventType, InputEvent, Synchronization};
+
+#[derive(Debug,Default)]
+pub struct Batch{
+    events: Vec<InputEvent>,
+    // where the current report starts in events
+    report_start: usize,
+}
+
+impl Batch{
+    pub fn new() -> Self {
+        Batch::default()
+    }
+
+    pub fn push(&mut self, ev: InputEvent){
+        if self.events[self.report_start..].iter().any(|e| e.event_type() == ev.event_type() && e.code() == ev.code()){
+            self.events.push(InputEvent::new(EventType::SYNCHRONIZATION, Synchronization::SYN_REPORT.0, 0));
+            self.report_start = self.events.len();
+        }
+        self.events.push(ev);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // Everything since the last take, the caller still has to finish off the last report
+    pub fn take(&mut self) -> Vec<InputEvent> {
+        self.report_start = 0;
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::batch::Batch;
+    use evdev::{EventType, InputEvent, Key, RelativeAxisType};
+
+    fn key(k: Key, v: i32) -> InputEvent { InputEvent::new(EventType::KEY, k.code(), v) }
+    fn rel(a: RelativeAxisType, v: i32) -> InputEvent { InputEvent::new(EventType::RELATIVE, a.0, v) }
+    fn syn() -> InputEvent { InputEvent::new(EventType::SYNCHRONIZATION, 0, 0) }
+
+    fn types_codes_values(events: Vec<InputEvent>) -> Vec<(EventType, u16, i32)> {
+        events.iter().map(|e| (e.event_type(), e.code(), e.value())).collect()
+    }
+
+    #[test]
+    fn test_batch(){
+        let mut b = Batch::new();
+        assert!(b.is_empty());
+        // motion on both axes is one report
+        b.push(rel(RelativeAxisType::REL_X, 3));
+        b.push(rel(RelativeAxisType::REL_Y, -2));
+        // a click needs the press and the release in separate reports
+        b.push(key(Key::BTN_LEFT, 1));
+        b.push(key(Key::BTN_LEFT, 0));
+        b.push(key(Key::KEY_A, 1));
+        b.push(rel(RelativeAxisType::REL_X, 3));
+        assert!(!b.is_empty());
+        assert_eq!(types_codes_values(b.take()), types_codes_values(vec![
+            rel(RelativeAxisType::REL_X, 3),
+            rel(RelativeAxisType::REL_Y, -2),
+            key(Key::BTN_LEFT, 1),
+            syn(),
+            key(Key::BTN_LEFT, 0),
+            key(Key::KEY_A, 1),
+            rel(RelativeAxisType::REL_X, 3),
+        ]));
+        assert!(b.is_empty());
+        // a new batch starts a new report
+        b.push(key(Key::KEY_A, 0));
+        assert_eq!(types_codes_values(b.take()), types_codes_values(vec![key(Key::KEY_A, 0)]));
+    }
+}
@@ -0,0 +1,139 @@
+/*
+
+What does this file do?
+
+    Reads the battery level of wireless joypads. The kernel puts the battery of a
+    joypad in sysfs as a power_supply belonging to the same device as the joypad's
+    input device, so this looks for it near /sys/class/input/jsN and reads its
+    capacity and status files.
+
+*/
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+// how many levels above the input device to look for a power_supply, the first one is usually the HID device
+const SEARCH_DEPTH: usize = 3;
+
+// Finds the power_supply directory for a joydev node, e.g. /dev/input/js0 -> /sys/class/power_supply/sony_controller_battery_...
+pub fn power_supply(sysfs: &Path, js: &Path) -> Option<PathBuf> {
+    let mut dir = sysfs.join("class/input");
+    dir.push(js.file_name()?);
+    dir.push("device");
+    let dir = dir.canonicalize().ok()?;
+    for d in dir.ancestors().skip(1).take(SEARCH_DEPTH){
+        if let Ok(entries) = std::fs::read_dir(d.join("power_supply")){
+            let mut supplies: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+            supplies.sort();
+            if let Some(s) = supplies.into_iter().next(){
+                return Some(s);
+            }
+        }
+    }
+    None
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Battery{
+    // percent, not all drivers give this
+    pub capacity: Option<u8>,
+    // e.g. Charging, Discharging, Full
+    pub status: Option<String>,
+}
+
+impl Battery{
+    pub fn read(power_supply: &Path) -> std::io::Result<Battery> {
+        let read = |name: &str| std::fs::read_to_string(power_supply.join(name)).map(|s| s.trim().to_string());
+        let capacity = match read("capacity"){
+            Ok(c) => Some(c.parse().map_err(|_| std::io::Error::other(format!("battery capacity is not a number: {}", c)))?),
+            Err(_) => None,
+        };
+        let status = read("status").ok();
+        if capacity.is_none() && status.is_none(){
+            return Err(std::io::Error::other(format!("{} has no capacity or status", power_supply.display())));
+        }
+        Ok(Battery{capacity, status})
+    }
+
+    // below the threshold, and not being charged
+    pub fn is_low(&self, threshold: u8) -> bool {
+        let charging = matches!(self.status.as_deref(), Some("Charging") | Some("Full"));
+        !charging && self.capacity.is_some_and(|c| c < threshold)
+    }
+}
+
+impl Display for Battery{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match (&self.capacity, &self.status){
+            (Some(c), Some(s)) => write!(f, "{}% ({})", c, s),
+            (Some(c), None) => write!(f, "{}%", c),
+            (None, Some(s)) => write!(f, "{}", s),
+            (None, None) => write!(f, "unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::battery::{power_supply, Battery};
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    // Makes a sysfs tree that looks like a bluetooth joypad: the input device and the battery are both under the HID device
+    fn fake_sysfs(capacity: Option<&str>, status: Option<&str>) -> TempDir {
+        let sysfs = TempDir::new("sysfs").unwrap();
+        let hid = sysfs.path().join("devices/virtual/0005:054C:09CC.0001");
+        let input = hid.join("input/input20");
+        std::fs::create_dir_all(input.join("js0")).unwrap();
+        let battery = hid.join("power_supply/sony_controller_battery_00:11:22:33:44:55");
+        std::fs::create_dir_all(&battery).unwrap();
+        if let Some(c) = capacity{
+            std::fs::write(battery.join("capacity"), format!("{}\n", c)).unwrap();
+        }
+        if let Some(s) = status{
+            std::fs::write(battery.join("status"), format!("{}\n", s)).unwrap();
+        }
+        std::fs::create_dir_all(sysfs.path().join("class/input/js0")).unwrap();
+        std::os::unix::fs::symlink(&input, sysfs.path().join("class/input/js0/device")).unwrap();
+        // a joypad with no battery
+        let wired = sysfs.path().join("devices/virtual/0003:045E:028E.0002/input/input21");
+        std::fs::create_dir_all(&wired).unwrap();
+        std::fs::create_dir_all(sysfs.path().join("class/input/js1")).unwrap();
+        std::os::unix::fs::symlink(&wired, sysfs.path().join("class/input/js1/device")).unwrap();
+        sysfs
+    }
+
+    #[test]
+    fn test_power_supply(){
+        let sysfs = fake_sysfs(Some("75"), Some("Discharging"));
+        let ps = power_supply(sysfs.path(), Path::new("/dev/input/js0")).unwrap();
+        assert!(ps.ends_with("power_supply/sony_controller_battery_00:11:22:33:44:55"));
+        assert_eq!(power_supply(sysfs.path(), Path::new("/dev/input/js1")), None);
+        assert_eq!(power_supply(sysfs.path(), Path::new("/dev/input/js2")), None);
+    }
+
+    #[test]
+    fn test_read(){
+        let sysfs = fake_sysfs(Some("15"), Some("Discharging"));
+        let b = Battery::read(&power_supply(sysfs.path(), Path::new("/dev/input/js0")).unwrap()).unwrap();
+        assert_eq!(b, Battery{capacity: Some(15), status: Some("Discharging".to_string())});
+        assert_eq!(format!("{}", b), "15% (Discharging)");
+        assert!(b.is_low(20));
+        assert!(!b.is_low(15));
+
+        let sysfs = fake_sysfs(Some("5"), Some("Charging"));
+        let b = Battery::read(&power_supply(sysfs.path(), Path::new("/dev/input/js0")).unwrap()).unwrap();
+        assert!(!b.is_low(20));
+
+        let sysfs = fake_sysfs(None, Some("Full"));
+        let b = Battery::read(&power_supply(sysfs.path(), Path::new("/dev/input/js0")).unwrap()).unwrap();
+        assert_eq!(format!("{}", b), "Full");
+        assert!(!b.is_low(20));
+
+        let sysfs = fake_sysfs(Some("lots"), None);
+        assert!(Battery::read(&power_supply(sysfs.path(), Path::new("/dev/input/js0")).unwrap()).is_err());
+
+        let sysfs = fake_sysfs(None, None);
+        assert!(Battery::read(&power_supply(sysfs.path(), Path::new("/dev/input/js0")).unwrap()).is_err());
+    }
+}
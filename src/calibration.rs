@@ -0,0 +1,115 @@
+/*
+
+What does this file do?
+
+    Works out where each axis of a joypad rests, and how much it wobbles about
+    while it is resting, from what the axes do while nobody is touching them.
+    Without this, a trigger that rests at one end looks like it is being held,
+    and a stick that is never quite still looks like it is being moved. It is
+    also how triggers are told apart from sticks.
+
+*/
+
+use std::collections::{BTreeMap, HashMap};
+
+// how long the axes are watched for while they are left alone
+pub const calibration_time_ms: u64 = 1000;
+
+// an axis that rests at least this close to one end is a trigger
+const trigger_margin: i32 = 8192;
+
+// an axis that wobbles by more than this while resting is too noisy to be told apart from one that is being moved
+const noisy_limit: u16 = 8000;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+struct Rest{
+    // the range of values seen while resting
+    min: i16,
+    max: i16,
+}
+
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Calibration{
+    axes: BTreeMap<u8, Rest>,
+}
+
+impl Calibration{
+    // starting from where each axis was last seen
+    pub fn new(values: &HashMap<u8, i16>) -> Self {
+        Calibration{axes: values.iter().map(|(n, v)| (*n, Rest{min: *v, max: *v})).collect()}
+    }
+
+    pub fn sample(&mut self, axis: u8, value: i16){
+        let rest = self.axes.entry(axis).or_insert(Rest{min: value, max: value});
+        rest.min = rest.min.min(value);
+        rest.max = rest.max.max(value);
+    }
+
+    // Where the axis rests. Axes that have never been seen are assumed to rest at 0
+    pub fn centre(&self, axis: u8) -> i16 {
+        self.axes.get(&axis).map(|r| ((r.min as i32 + r.max as i32) / 2) as i16).unwrap_or(0)
+    }
+
+    // Double the wobble that was seen, to leave some room for wobbling a bit more than that
+    pub fn deadzone(&self, axis: u8) -> u16 {
+        self.axes.get(&axis).map(|r| (r.max as i32 - r.min as i32) as u16).unwrap_or(0)
+    }
+
+    pub fn is_noisy(&self, axis: u8) -> bool {
+        self.deadzone(axis) > noisy_limit
+    }
+
+    // Whether a value is far enough away from the centre to be something other than wobble
+    pub fn is_moved(&self, axis: u8, value: i16) -> bool {
+        (value as i32 - self.centre(axis) as i32).unsigned_abs() > (self.deadzone(axis) as u32)
+    }
+
+    // Triggers rest at one end, and are pressed towards the other
+    pub fn is_trigger(&self, axis: u8) -> bool {
+        (self.centre(axis) as i32).abs() >= i16::MAX as i32 - trigger_margin
+    }
+
+    // The axes that are noisy, for warning about
+    pub fn noisy_axes(&self) -> Vec<u8> {
+        self.axes.keys().copied().filter(|a| self.is_noisy(*a)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::calibration::Calibration;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_calibration(){
+        let mut c = Calibration::new(&HashMap::from([(0, 0), (2, -32767), (3, 100)]));
+        for v in [120, 80, 110, 90]{
+            c.sample(3, v);
+        }
+        for v in [-10000, 5000]{
+            c.sample(4, v);
+        }
+
+        // a still stick
+        assert_eq!((c.centre(0), c.deadzone(0)), (0, 0));
+        assert!(c.is_moved(0, 1));
+        // a trigger resting at one end
+        assert_eq!((c.centre(2), c.deadzone(2)), (-32767, 0));
+        assert!(!c.is_moved(2, -32767));
+        assert!(c.is_moved(2, 0));
+        assert!(c.is_trigger(2));
+        assert!(!c.is_trigger(0));
+        // a wobbly stick
+        assert_eq!((c.centre(3), c.deadzone(3)), (100, 40));
+        assert!(!c.is_moved(3, 60));
+        assert!(!c.is_moved(3, 140));
+        assert!(c.is_moved(3, 141));
+        assert!(!c.is_noisy(3));
+        // a very wobbly stick
+        assert_eq!((c.centre(4), c.deadzone(4)), (-2500, 15000));
+        assert_eq!(c.noisy_axes(), vec![4]);
+        assert!(!c.is_trigger(4));
+        // never seen
+        assert_eq!((c.centre(9), c.deadzone(9)), (0, 0));
+    }
+}
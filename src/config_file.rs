@@ -0,0 +1,228 @@
+/*
+
+What does this file do?
+
+    Finds the config directories, and reads whole config files (joy2uinput.conf
+    and the .j2umap files). This is shared by joy2uinput, joy2u-mapgen, and
+    anything else that uses the joy2uinput library. Errors say which line and
+    column they are on, so that they can be shown next to the problem.
+
+*/
+
+use crate::map_config::{ConfigLine, ErrorKind, Hook, JoyInput, LineError, Mapping, Settings, Target};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+pub const conf_dir_env_var: &str = "JOY2UINPUT_CONFDIR";
+// used for anything that isn't in the user config dir
+pub const default_conf_dir: &str = "/etc/joy2uinput/";
+pub const conf_file_name: &str = "joy2uinput.conf";
+pub const mapping_file_extension: &str = "j2umap";
+
+// The places the user config dir can be, most preferred first
+fn user_conf_dir_candidates() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(d) = std::env::var_os("XDG_CONFIG_HOME"){
+        if !d.is_empty(){
+            dirs.push(PathBuf::from(&d).join("joy2uinput"));
+        }
+    }
+    if let Some(home) = dirs::home_dir(){
+        dirs.push(home.join(".config/joy2uinput/"));
+    }
+    dirs.push(PathBuf::from("/opt/joy2uinput/"));
+    dirs
+}
+
+// Where to read the user config from: the first of the usual places that exists, or JOY2UINPUT_CONFDIR if it is set
+// It is an error for JOY2UINPUT_CONFDIR to be set to something that isn't a directory
+pub fn find_user_conf_dir() -> Result<Option<PathBuf>, String> {
+    if let Some(d) = std::env::var_os(conf_dir_env_var){
+        let dir = PathBuf::from(&d);
+        if !dir.is_dir(){
+            return Err(format!("{} does not point to a directory", conf_dir_env_var));
+        }
+        return Ok(Some(dir));
+    }
+    Ok(user_conf_dir_candidates().into_iter().find(|d| d.is_dir()))
+}
+
+// Where to write the user config to, which might not exist yet. The bool is true if this came from JOY2UINPUT_CONFDIR
+pub fn user_conf_dir() -> (PathBuf, bool) {
+    if let Some(d) = std::env::var_os(conf_dir_env_var){
+        return (PathBuf::from(&d), true);
+    }
+    // there is always at least /opt/joy2uinput/
+    (user_conf_dir_candidates().remove(0), false)
+}
+
+// The mapping file that joy2uinput would use for a joypad, if there is one: the one in the user config dir, otherwise the one in the default config dir
+pub fn find_mapping_file(filename: &OsStr) -> Option<PathBuf> {
+    let user = find_user_conf_dir().ok().flatten().map(|d| d.join(filename));
+    let default = PathBuf::from(default_conf_dir).join(filename);
+    user.into_iter().chain([default]).find(|f| f.is_file())
+}
+
+// The joy2uinput.conf that joy2uinput would use, if there is one, found in the same way as mapping files
+pub fn find_config_file() -> Option<PathBuf> {
+    find_mapping_file(OsStr::new(conf_file_name))
+}
+
+// line and column both start at 1, the column counts characters, not bytes.
+// kind says what sort of problem it is, message is for people
+#[derive(Debug,Clone,PartialEq)]
+pub struct ParseError{
+    pub line: usize,
+    pub column: usize,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Display for ParseError{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError{}
+
+// Parses every line that isn't blank or a comment, and reports the problems with all of them, not just the first
+pub fn parse_lines<T>(text: &str, parse: impl Fn(&str) -> Result<T, Vec<LineError>>) -> Result<Vec<T>, Vec<ParseError>> {
+    let mut parsed = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate(){
+        let t = line.trim();
+        if t.is_empty() || t.starts_with('#'){
+            continue;
+        }
+        match parse(line){
+            Ok(p) => parsed.push(p),
+            Err(es) => errors.extend(es.into_iter().map(|e| ParseError{line: i + 1, column: line[..e.offset].chars().count() + 1, kind: e.kind, message: e.message})),
+        }
+    }
+    if !errors.is_empty(){
+        return Err(errors);
+    }
+    Ok(parsed)
+}
+
+// Parses a .j2umap file, the mappings are in the order they are in the file
+pub fn parse_mapping_file(text: &str) -> Result<Vec<Mapping>, Vec<ParseError>> {
+    parse_lines(text, Mapping::parse_located)
+}
+
+// Parses joy2uinput.conf line by line, for anything that wants to change the file and write it back out
+pub fn parse_config_lines(text: &str) -> Result<Vec<ConfigLine>, Vec<ParseError>> {
+    parse_lines(text, ConfigLine::parse_located)
+}
+
+// Everything in joy2uinput.conf. When there is more than one line for the same thing, the last one wins
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Config{
+    pub mappings: HashMap<JoyInput, Vec<Target>>,
+    pub hooks: HashMap<Hook, Vec<Target>>,
+    pub settings: Settings,
+}
+
+pub fn parse_config_file(text: &str) -> Result<Config, Vec<ParseError>> {
+    let mut config = Config::default();
+    for line in parse_config_lines(text)?{
+        match line{
+            ConfigLine::Mapping(m) => {config.mappings.insert(m.from, m.to);},
+            // parse_config_lines has already checked that the setting is valid
+            ConfigLine::Setting(name, value) => {let _ = config.settings.set(&name, &value);},
+            ConfigLine::Hook(hook, targets) => {config.hooks.insert(hook, targets);},
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test{
+    use crate::config_file::{conf_file_name, find_config_file, find_mapping_file, find_user_conf_dir, parse_config_file, parse_mapping_file, user_conf_dir, ParseError, conf_dir_env_var};
+    use crate::map_config::{Axis, Button, ErrorKind, Hook, JDEv, JoyInput, KeyTarget, Mapping, Target};
+    use serial_test::serial;
+    use std::ffi::OsStr;
+    use tempdir::TempDir;
+
+    fn err(line: usize, column: usize) -> (usize, usize) { (line, column) }
+
+    fn locations(errors: Vec<ParseError>) -> Vec<(usize, usize)> {
+        errors.iter().map(|e| (e.line, e.column)).collect()
+    }
+
+    fn kinds(errors: &[ParseError]) -> Vec<ErrorKind> {
+        errors.iter().map(|e| e.kind).collect()
+    }
+
+    #[test]
+    fn test_parse_mapping_file(){
+        let text = "# a comment\n\nbutton(0) = a\n  axis(1,-32767,32767) = lefty\n";
+        assert_eq!(parse_mapping_file(text), Ok(vec![
+            Mapping{from: JDEv::Button(0), to: JoyInput::Button(Button::A())},
+            Mapping{from: JDEv::Axis(1, -32767, 32767, 0, 0), to: JoyInput::Axis(Axis::LeftY())},
+        ]));
+
+        let text = "button(0) = a\nbutton(x) = b\n\t\tbutton(1) = nope\nbutton(y) = nope\nbutton(2) a\n";
+        let errors = parse_mapping_file(text).unwrap_err();
+        assert_eq!(kinds(&errors), vec![ErrorKind::BadArgument(), ErrorKind::UnknownInput(), ErrorKind::BadArgument(), ErrorKind::UnknownInput(), ErrorKind::Syntax()]);
+        assert_eq!(locations(errors.clone()), vec![err(2, 1), err(3, 15), err(4, 1), err(4, 13), err(5, 1)]);
+        assert!(errors[1].to_string().starts_with("line 3, column 15: "), "{}", errors[1]);
+    }
+
+    #[test]
+    fn test_parse_config_file(){
+        let text = "a = key(a), key(b)\non disabled = rumble(0.5,200)\nset battery_low = 10\nb = key(c)\na = key(d)\n";
+        let config = parse_config_file(text).unwrap();
+        assert_eq!(config.mappings.len(), 2);
+        assert_eq!(config.mappings[&JoyInput::Button(Button::A())], vec![Target::Key(KeyTarget::AlphaNum('d'))]);
+        assert_eq!(config.hooks[&Hook::Disabled()], vec![Target::Rumble(0.5, 200)]);
+        assert_eq!(config.settings.battery_low, 10);
+
+        // columns count characters, the é is two bytes
+        let text = "a = key(a), key(nope)\n\"é\" = key(a)\nb = key(a),\nset battery_low = lots\nset nope = 1\non nope = rumble(1,1)\non enabled = rumble(1,1), key(a)\nb key(a)\nb = type(\"é\"), type(é)\n";
+        let errors = parse_config_file(text).unwrap_err();
+        assert_eq!(kinds(&errors), vec![
+            ErrorKind::BadArgument(), ErrorKind::UnknownInput(), ErrorKind::Syntax(), ErrorKind::BadSettingValue(), ErrorKind::UnknownSetting(),
+            ErrorKind::UnknownHook(), ErrorKind::HookTarget(), ErrorKind::Syntax(), ErrorKind::BadArgument(),
+        ]);
+        assert_eq!(kinds(&parse_config_file("a = nope(1)\ncustom_button(b) = key(a)\n").unwrap_err()), vec![ErrorKind::UnknownTarget(), ErrorKind::BadArgument()]);
+        assert_eq!(kinds(&parse_mapping_file("nope(1) = a\n").unwrap_err()), vec![ErrorKind::UnknownJoydevEvent()]);
+        assert_eq!(locations(errors), vec![err(1, 13), err(2, 1), err(3, 5), err(4, 19), err(5, 5), err(6, 4), err(7, 27), err(8, 1), err(9, 16)]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_conf_dirs(){
+        let dir = TempDir::new("conf").unwrap();
+        std::env::set_var(conf_dir_env_var, dir.path());
+        assert_eq!(find_user_conf_dir(), Ok(Some(dir.path().to_path_buf())));
+        assert_eq!(user_conf_dir(), (dir.path().to_path_buf(), true));
+        let name = OsStr::new("not a real joypad.j2umap");
+        assert_eq!(find_mapping_file(name), None);
+        std::fs::write(dir.path().join(name), "").unwrap();
+        assert_eq!(find_mapping_file(name), Some(dir.path().join(name)));
+        std::fs::write(dir.path().join(conf_file_name), "").unwrap();
+        assert_eq!(find_config_file(), Some(dir.path().join(conf_file_name)));
+
+        let missing = dir.path().join("missing");
+        std::env::set_var(conf_dir_env_var, &missing);
+        assert!(find_user_conf_dir().is_err());
+        assert_eq!(user_conf_dir(), (missing.clone(), true));
+
+        std::env::remove_var(conf_dir_env_var);
+        let old_xdg = std::env::var_os("XDG_CONFIG_HOME");
+        std::env::set_var("XDG_CONFIG_HOME", dir.path());
+        // it doesn't exist yet, so it is only where config would be written
+        assert_eq!(user_conf_dir(), (dir.path().join("joy2uinput"), false));
+        assert_ne!(find_user_conf_dir(), Ok(Some(dir.path().join("joy2uinput"))));
+        std::fs::create_dir(dir.path().join("joy2uinput")).unwrap();
+        assert_eq!(find_user_conf_dir(), Ok(Some(dir.path().join("joy2uinput"))));
+        match old_xdg{
+            Some(x) => std::env::set_var("XDG_CONFIG_HOME", x),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+    }
+}
@@ -0,0 +1,717 @@
+/*
+
+What does this file do?
+
+    This is the mapping engine. It follows the config to work out what should happen
+    because of each bit of joypad input: which keys go up and down, how fast the mouse
+    moves, which programs to launch, and so on. It does no I/O at all, and it never
+    looks at the clock (the time comes in with each input), so the same input always
+    gives the same actions, and all of it can be tested without any joypads or
+    /dev/uinput. joy2uinput.rs does whatever the returned actions say.
+
+*/
+
+use crate::key_state::KeyState;
+use crate::level::Level;
+use crate::map_config::{jpname_to_filename, axis_position, Hook, JDEv, JoyInput, LaunchTarget, Mapping, Target};
+use crate::xkb::{self, Keymap};
+use evdev::{AbsoluteAxisType, Key, RelativeAxisType};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// input from a joypad that has just been connected is ignored for this long, because joydev sends the state of every button and axis first
+pub const listen_delay: Duration = Duration::from_millis(200);
+
+// joydev control id (the number of a button or axis)
+#[derive(Debug,Clone,Copy,Eq,Hash,PartialEq,PartialOrd,Ord)]
+pub enum JDCId{
+    Button(u8),
+    AxisAsButton(u8,i16),
+    Axis(u8),
+}
+
+impl From<&JDEv> for JDCId{
+    fn from(e: &JDEv) -> Self {
+        match e {
+            JDEv::Button(n) => JDCId::Button(*n),
+            JDEv::AxisAsButton(n,v) => JDCId::AxisAsButton(*n,*v),
+            JDEv::Axis(n,..) => JDCId::Axis(*n),
+        }
+    }
+}
+
+// what each button or axis of a joypad is, straight from its control id. One control can be
+// several inputs, each with its own targets, in the order they are in the mapping file
+pub type PadMapping = HashMap<JDCId, (JDEv, Vec<(JoyInput, Vec<Target>)>)>;
+
+// the PadMapping for each mapping file name
+pub type Mappings = HashMap<OsString, Rc<PadMapping>>;
+
+// what happens for each of the "on <event> = ..." lines
+pub type Hooks = HashMap<Hook, Vec<Target>>;
+
+// Joins a joypad's mapping file to the main config. Inputs that the config doesn't have any targets for are
+// kept (with no targets), so that tools can still see what they are
+pub fn expand_mapping(mapping: &[Mapping], targets: &HashMap<JoyInput, Vec<Target>>) -> PadMapping {
+    let mut expanded: PadMapping = HashMap::new();
+    for Mapping{from, to} in mapping.iter(){
+        let targets = targets.get(to).cloned().unwrap_or_default();
+        expanded.entry(from.into()).or_insert_with(|| (from.clone(), Vec::new())).1.push((to.clone(), targets));
+    }
+    expanded
+}
+
+// One input that a joydev event is
+#[derive(Debug,Clone,PartialEq)]
+pub struct Resolved<'a>{
+    pub id: JDCId,
+    pub from: &'a JDEv,
+    pub input: &'a JoyInput,
+    pub targets: &'a [Target],
+    // 0 or 1 for a button, from -1 to 1 for an axis (0 to 1 for a trigger)
+    pub position: f32,
+}
+
+// Every input that a joydev event is, and where each one is now. A button is everything mapped from it.
+// An axis is everything mapped from the whole axis, then every position on it that is mapped as a button,
+// which is 1 while the axis is at that position. This is how Engine::input sees joypad input, so tools that
+// show what joy2uinput would do should use it too
+pub fn resolve(mapping: &PadMapping, ev: JoyEv) -> Vec<Resolved<'_>> {
+    let mut ids = match ev{
+        JoyEv::Button(number, _) => vec![JDCId::Button(number)],
+        JoyEv::Axis(number, _) => {
+            let mut positions: Vec<JDCId> = mapping.keys().filter(|id| matches!(id, JDCId::AxisAsButton(n, _) if *n == number)).copied().collect();
+            positions.sort();
+            positions.insert(0, JDCId::Axis(number));
+            positions
+        },
+    };
+    let mut resolved = Vec::new();
+    for id in ids.drain(..){
+        let (from, inputs) = match mapping.get(&id){
+            Some((from, inputs)) => (from, inputs),
+            None => continue,
+        };
+        let position = match (ev, from){
+            (JoyEv::Button(_, value), _) => if value != 0 {1.0} else {0.0},
+            (JoyEv::Axis(_, value), JDEv::Axis(_, min, max, centre, deadzone)) => axis_position(value, *min, *max, *centre, *deadzone),
+            (JoyEv::Axis(_, value), JDEv::AxisAsButton(_, v)) => if value == *v {1.0} else {0.0},
+            (JoyEv::Axis(..), JDEv::Button(_)) => continue,
+        };
+        for (input, targets) in inputs{
+            resolved.push(Resolved{id, from, input, targets, position});
+        }
+    }
+    resolved
+}
+
+// The parts of a joydev event that the engine needs
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum JoyEv{
+    // number, value
+    Button(u8, i16),
+    Axis(u8, i16),
+}
+
+impl From<joydev::Event> for JoyEv{
+    fn from(ev: joydev::Event) -> Self {
+        use joydev::GenericEvent;
+        match ev.type_(){
+            joydev::EventType::Button | joydev::EventType::ButtonSynthetic => JoyEv::Button(ev.number(), ev.value()),
+            joydev::EventType::Axis | joydev::EventType::AxisSynthetic => JoyEv::Axis(ev.number(), ev.value()),
+        }
+    }
+}
+
+// the pad and the input on it that launched a program
+pub type Owner = (u32, JDCId);
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Action{
+    KeyDown(Key),
+    KeyUp(Key),
+    Rel(RelativeAxisType, i32),
+    Abs(AbsoluteAxisType, i32),
+    // the placeholders are filled in from device, input and player
+    Launch{target: LaunchTarget, owner: Option<Owner>, device: String, input: String, player: usize},
+    // stop the kill_on_release programs that this input launched
+    StopLaunched(Owner),
+    // stop the kill_on_release programs launched by any input on this pad
+    StopPad(u32),
+    KillLaunched(),
+    // pad, strength, duration in ms
+    Rumble(u32, f32, u16),
+    Log(Level, String),
+    Bug(String),
+    // only sent when trace is on: message and fields
+    Trace(&'static str, Vec<(&'static str, String)>),
+}
+
+#[derive(Debug)]
+struct Pad{
+    name: String,
+    mapping: Option<Rc<PadMapping>>,
+    // every key or mouse button this pad is currently holding down on the virtual device, and which input is holding it
+    held_keys: HashSet<(JDCId, Key)>,
+}
+
+// a speed of zero is the same as not moving at all, so it is not stored
+fn set_speed<K: Ord>(speeds: &mut BTreeMap<K, i32>, key: K, delta: i32){
+    if delta == 0{
+        speeds.remove(&key);
+    }
+    else{
+        speeds.insert(key, delta);
+    }
+}
+
+// Where a raw_abs axis goes for a joypad axis value, scaled from jmin..jmax to min..max.
+// Done in f64 because min..max can be the whole range of an i32. An axis with no range sits in the middle
+fn raw_abs_value(value: i16, jmin: i16, jmax: i16, min: i32, max: i32) -> i32 {
+    let pos = if jmin == jmax {0.5} else {(value as f64 - jmin as f64) / (jmax as f64 - jmin as f64)};
+    let out = min as f64 + pos * (max as f64 - min as f64);
+    out.round().clamp(min.min(max) as f64, min.max(max) as f64) as i32
+}
+
+#[derive(Debug)]
+pub struct Engine{
+    mappings: Mappings,
+    hooks: Hooks,
+    keymap: Keymap,
+    pads: HashMap<u32, Pad>,
+    // Several inputs can share a key, so the virtual device only sees the first press and the last release
+    key_state: KeyState,
+    // speeds are per source pad, so that one pad going away only stops its own motion
+    axis_speeds: BTreeMap<(u32, u16), i32>,
+    fake_axis_speeds: BTreeMap<(u32, (u16, u16)), i32>,
+    enabled: bool,
+    // input is ignored until then, after a joypad has connected
+    listen_at: Option<Instant>,
+    // whether to send Trace actions
+    pub trace: bool,
+}
+
+impl Engine{
+    pub fn new(mappings: Mappings, hooks: Hooks, keymap: Keymap) -> Engine {
+        Engine{
+            mappings,
+            hooks,
+            keymap,
+            pads: HashMap::new(),
+            key_state: KeyState::new(),
+            axis_speeds: BTreeMap::new(),
+            fake_axis_speeds: BTreeMap::new(),
+            enabled: true,
+            listen_at: None,
+            trace: false,
+        }
+    }
+
+    // For a reload, the pads that are already connected switch to their new mappings
+    pub fn set_config(&mut self, mappings: Mappings, hooks: Hooks, keymap: Keymap) -> Vec<Action> {
+        let mut actions = Vec::new();
+        self.mappings = mappings;
+        self.hooks = hooks;
+        self.keymap = keymap;
+        for pad in self.pads.values_mut(){
+            pad.mapping = self.mappings.get(&jpname_to_filename(&pad.name)).cloned();
+            if pad.mapping.is_none(){
+                actions.push(Action::Log(Level::Warn, format!("There is no mapping file for the joypad: {}", pad.name)));
+            }
+        }
+        actions
+    }
+
+    pub fn connect(&mut self, id: u32, name: String, now: Instant) -> Vec<Action> {
+        let mut actions = Vec::new();
+        let file_name = jpname_to_filename(&name);
+        let mapping = self.mappings.get(&file_name).cloned();
+        match &mapping{
+            None => {
+                actions.push(Action::Log(Level::Warn, format!("There is no mapping file for the joypad: {}", name)));
+                actions.push(Action::Log(Level::Warn, "No inputs will be handled for this joypad.".to_string()));
+            },
+            Some(_) => {
+                actions.push(Action::Log(Level::Debug, format!("Using mapping {:?} for joypad {}", file_name, id)));
+            },
+        }
+        self.pads.insert(id, Pad{name, mapping, held_keys: HashSet::new()});
+        self.listen_at = Some(now + listen_delay);
+        actions
+    }
+
+    pub fn disconnect(&mut self, id: u32) -> Vec<Action> {
+        let mut actions = Vec::new();
+        self.release_pad(id, &mut actions);
+        self.pads.remove(&id);
+        actions
+    }
+
+    // players are numbered in the order of their joystick devices
+    pub fn player(&self, id: u32) -> usize {
+        self.pads.keys().filter(|other| **other < id).count() + 1
+    }
+
+    // whether anything needs motion sending regularly
+    pub fn is_moving(&self) -> bool {
+        !self.axis_speeds.is_empty() || !self.fake_axis_speeds.is_empty()
+    }
+
+    // Sends one lot of motion for the axes that are being held
+    pub fn motion(&mut self) -> Vec<Action> {
+        let mut actions: Vec<Action> = self.axis_speeds.iter().map(|((_pad, axis), speed)| Action::Rel(RelativeAxisType(*axis), *speed)).collect();
+        // axes that are mapped to keys tap the key for the direction they are held in
+        for ((_pad, (neg, pos)), speed) in self.fake_axis_speeds.iter(){
+            self.tap(Key::new(if *speed < 0 {*neg} else {*pos}), &mut actions);
+        }
+        actions
+    }
+
+    pub fn release_all(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        self.release_all_into(&mut actions);
+        actions
+    }
+
+    // Runs the targets for an "on <event>" line, for the pad that caused it
+    pub fn hook(&mut self, id: u32, hook: Hook) -> Vec<Action> {
+        let mut actions = Vec::new();
+        self.run_hooks(id, hook, &mut actions);
+        actions
+    }
+
+    pub fn input(&mut self, dev: u32, ev: JoyEv, now: Instant) -> Vec<Action> {
+        let mut actions = Vec::new();
+        if self.listen_at.is_some_and(|l| now < l){
+            return actions;
+        }
+        let player = self.player(dev);
+        let (name, mapping) = match self.pads.get(&dev){
+            Some(Pad{name, mapping: Some(mapping), ..}) => (name.clone(), mapping.clone()),
+            _ => return actions,
+        };
+        let was_enabled = self.enabled;
+        for Resolved{id, from, input, targets, position} in resolve(&mapping, ev){
+            match (id, from){
+                (JDCId::Button(_), _) => {
+                    self.trace_input(dev, input, targets, &mut actions);
+                    let pressed = position != 0.0;
+                    for target in targets.iter(){
+                        match target {
+                            Target::Key(k) => {
+                                if self.enabled{
+                                    if pressed{
+                                        self.press(dev, id, k.uinput_key(), &mut actions);
+                                    }
+                                    else{
+                                        self.release(dev, id, k.uinput_key(), &mut actions);
+                                    }
+                                }
+                            },
+                            Target::Axis(a) => {
+                                if self.enabled{
+                                    let delta = (position * a.multiplier()).round() as i32;
+                                    if let Some(code) = a.uinput_axis(){
+                                        set_speed(&mut self.axis_speeds, (dev, code.0), delta);
+                                    }
+                                }
+                            },
+                            Target::RawAbs(axis, min, max) => {
+                                if self.enabled{
+                                    actions.push(Action::Abs(*axis, if pressed {*max} else {*min}));
+                                }
+                            },
+                            Target::Type(text) => {
+                                if self.enabled && pressed{
+                                    self.type_text(text, &mut actions);
+                                }
+                            },
+                            Target::ToggleEnabled() => {
+                                if pressed{
+                                    self.toggle_enabled(&mut actions);
+                                }
+                            },
+                            Target::Launch(l) => {
+                                if pressed{
+                                    actions.push(Action::Launch{target: l.clone(), owner: Some((dev, id)), device: name.clone(), input: input.to_string(), player});
+                                }
+                                else if l.kill_on_release{
+                                    actions.push(Action::StopLaunched((dev, id)));
+                                }
+                            },
+                            Target::KillLaunched() => {
+                                if pressed{
+                                    actions.push(Action::KillLaunched());
+                                }
+                            },
+                            Target::Rumble(strength, duration) => {
+                                if pressed{
+                                    actions.push(Action::Rumble(dev, *strength, *duration));
+                                }
+                            },
+                        }
+                    }
+                },
+                (JDCId::Axis(_), JDEv::Axis(_n,jmin,jmax,..)) => {
+                    let value = match ev { JoyEv::Axis(_, v) | JoyEv::Button(_, v) => v };
+                    self.trace_input(dev, input, targets, &mut actions);
+                    for target in targets.iter(){
+                        match target {
+                            Target::Axis(a) => {
+                                if self.enabled{
+                                    let delta = (position * a.multiplier()).round() as i32;
+                                    if let Some(code) = a.uinput_axis(){
+                                        set_speed(&mut self.axis_speeds, (dev, code.0), delta);
+                                    }
+                                    else{
+                                        let keys = a.uinput_keys();
+                                        set_speed(&mut self.fake_axis_speeds, (dev, (keys[0].code(), keys[1].code())), delta);
+                                    }
+                                }
+                            },
+                            Target::RawAbs(axis, min, max) => {
+                                if self.enabled{
+                                    actions.push(Action::Abs(*axis, raw_abs_value(value, *jmin, *jmax, *min, *max)));
+                                }
+                            },
+                            Target::Key(a) => {
+                                actions.push(Action::Log(Level::Warn, format!("This axis is mapped to a button? Not sure what that means. Target event dropped: {:?}", a)));
+                            },
+                            Target::Type(_) => {
+                                actions.push(Action::Log(Level::Warn, "This axis is mapped to type some text? Not sure what that means.".to_string()));
+                            },
+                            Target::ToggleEnabled() => {
+                                actions.push(Action::Log(Level::Warn, "This axis is mapped to toggle enabled? Not sure what that means.".to_string()));
+                            },
+                            Target::Launch(_) | Target::KillLaunched() => {
+                                actions.push(Action::Log(Level::Warn, "This axis is mapped to launch or stop a program? Not sure what that means.".to_string()));
+                            },
+                            Target::Rumble(..) => {
+                                actions.push(Action::Log(Level::Warn, "This axis is mapped to rumble? Not sure what that means.".to_string()));
+                            },
+                        }
+                    }
+                },
+                // a position on an axis only does something at the moment the axis gets there
+                (JDCId::AxisAsButton(..), _) if position != 0.0 => {
+                    self.trace_input(dev, input, targets, &mut actions);
+                    for target in targets.iter(){
+                        match target {
+                            Target::Key(k) => {
+                                if self.enabled{
+                                    self.tap(k.uinput_key(), &mut actions);
+                                }
+                            },
+                            Target::RawAbs(axis, ..) => {
+                                actions.push(Action::Log(Level::Warn, format!("Unable to map this button to its axis target because the device models the button as an axis. Target event dropped: {:?}\nFor an explanation of why this happens, see the github issue here: https://github.com/lexbailey/joy2uinput/issues/2", axis)));
+                            },
+                            Target::Axis(a) => {
+                                actions.push(Action::Log(Level::Warn, format!("Unable to map this button to its axis target because the device models the button as an axis. Target event dropped: {:?}\nFor an explanation of why this happens, see the github issue here: https://github.com/lexbailey/joy2uinput/issues/2", a)));
+                            },
+                            Target::Type(text) => {
+                                if self.enabled{
+                                    self.type_text(text, &mut actions);
+                                }
+                            },
+                            Target::ToggleEnabled() => {
+                                self.toggle_enabled(&mut actions);
+                            },
+                            Target::Launch(l) => {
+                                actions.push(Action::Launch{target: l.clone(), owner: Some((dev, id)), device: name.clone(), input: input.to_string(), player});
+                            },
+                            Target::KillLaunched() => {
+                                actions.push(Action::KillLaunched());
+                            },
+                            Target::Rumble(strength, duration) => {
+                                actions.push(Action::Rumble(dev, *strength, *duration));
+                            },
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        if was_enabled && !self.enabled{
+            self.release_all_into(&mut actions);
+        }
+        if was_enabled != self.enabled{
+            self.run_hooks(dev, if self.enabled {Hook::Enabled()} else {Hook::Disabled()}, &mut actions);
+        }
+        actions
+    }
+
+    // inputs that the config has no targets for aren't worth mentioning
+    fn trace_input(&self, dev: u32, input: &JoyInput, targets: &[Target], actions: &mut Vec<Action>){
+        if self.trace && !targets.is_empty(){
+            actions.push(Action::Trace("input", vec![("pad", dev.to_string()), ("input", input.to_string()), ("targets", format!("{:?}", targets))]));
+        }
+    }
+
+    fn toggle_enabled(&mut self, actions: &mut Vec<Action>){
+        self.enabled = !self.enabled;
+        actions.push(Action::Log(Level::Info, format!("Mapping {}", if self.enabled {"enabled"} else {"disabled"})));
+    }
+
+    fn press(&mut self, dev: u32, input: JDCId, key: Key, actions: &mut Vec<Action>){
+        if let Some(pad) = self.pads.get_mut(&dev){
+            if pad.held_keys.insert((input, key)) && self.key_state.press(key){
+                actions.push(Action::KeyDown(key));
+            }
+        }
+    }
+
+    fn release(&mut self, dev: u32, input: JDCId, key: Key, actions: &mut Vec<Action>){
+        if let Some(pad) = self.pads.get_mut(&dev){
+            if pad.held_keys.remove(&(input, key)) && self.key_state.release(key){
+                actions.push(Action::KeyUp(key));
+            }
+        }
+    }
+
+    fn tap(&self, key: Key, actions: &mut Vec<Action>){
+        if self.key_state.tap(key){
+            actions.push(Action::KeyDown(key));
+            actions.push(Action::KeyUp(key));
+        }
+    }
+
+    // Types each character with a tap of its key, holding shift and AltGr around it as the layout needs
+    fn type_text(&self, text: &str, actions: &mut Vec<Action>){
+        for c in text.chars(){
+            let stroke = match self.keymap.stroke(c){
+                None => continue, // already warned about when the config was loaded
+                Some(stroke) => stroke,
+            };
+            let mods: Vec<Key> = [(stroke.shift, xkb::SHIFT_KEY), (stroke.altgr, xkb::ALTGR_KEY)].iter()
+                .filter(|(needed, key)| *needed && !self.key_state.is_down(*key))
+                .map(|(_, key)| *key).collect();
+            actions.extend(mods.iter().map(|m| Action::KeyDown(*m)));
+            self.tap(stroke.key, actions);
+            actions.extend(mods.iter().rev().map(|m| Action::KeyUp(*m)));
+        }
+    }
+
+    // Lets go of everything a pad is holding down, and stops any motion it caused
+    fn release_pad(&mut self, id: u32, actions: &mut Vec<Action>){
+        if let Some(pad) = self.pads.get_mut(&id){
+            let mut held: Vec<_> = pad.held_keys.drain().collect();
+            held.sort();
+            for (_input, k) in held{
+                actions.push(Action::Log(Level::Debug, format!("Releasing {:?} held by pad {}", k, id)));
+                if self.key_state.release(k){
+                    actions.push(Action::KeyUp(k));
+                }
+            }
+        }
+        self.axis_speeds.retain(|(pad, _), _| *pad != id);
+        self.fake_axis_speeds.retain(|(pad, _), _| *pad != id);
+        actions.push(Action::StopPad(id));
+    }
+
+    fn release_all_into(&mut self, actions: &mut Vec<Action>){
+        let mut ids: Vec<u32> = self.pads.keys().copied().collect();
+        ids.sort();
+        for id in ids{
+            self.release_pad(id, actions);
+        }
+    }
+
+    fn run_hooks(&mut self, id: u32, hook: Hook, actions: &mut Vec<Action>){
+        let player = self.player(id);
+        let name = match self.pads.get(&id){
+            Some(pad) => pad.name.clone(),
+            None => return,
+        };
+        let targets = match self.hooks.get(&hook){
+            Some(targets) => targets.clone(),
+            None => return,
+        };
+        if self.trace{
+            actions.push(Action::Trace("hook", vec![("pad", id.to_string()), ("hook", hook.to_string()), ("targets", format!("{:?}", targets))]));
+        }
+        for target in targets.iter(){
+            match target{
+                Target::Rumble(strength, duration) => actions.push(Action::Rumble(id, *strength, *duration)),
+                Target::Launch(l) => actions.push(Action::Launch{target: l.clone(), owner: None, device: name.clone(), input: hook.to_string(), player}),
+                Target::KillLaunched() => actions.push(Action::KillLaunched()),
+                Target::Type(text) => self.type_text(text, actions),
+                _ => actions.push(Action::Bug(format!("the target {} isn't allowed in 'on' lines, but it was loaded anyway", target))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::engine::{expand_mapping, listen_delay, raw_abs_value, resolve, Action, Engine, JDCId, JoyEv};
+    use crate::config_file::{parse_config_file, parse_mapping_file};
+    use crate::level::Level;
+    use crate::map_config::{jpname_to_filename, LaunchTarget};
+    use crate::xkb::Keymap;
+    use evdev::{Key, RelativeAxisType};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    // An engine with one mapping file for the joypad "pad", and a pad connected on js0 that is ready for input at the returned time
+    fn engine(map: &str, conf: &str) -> (Engine, Instant) {
+        let mapping = parse_mapping_file(map).unwrap();
+        let config = parse_config_file(conf).unwrap();
+        let mappings = HashMap::from([(jpname_to_filename("pad"), Rc::new(expand_mapping(&mapping, &config.mappings)))]);
+        let mut e = Engine::new(mappings, config.hooks, Keymap::default());
+        let start = Instant::now();
+        e.connect(0, "pad".to_string(), start);
+        (e, start + listen_delay)
+    }
+
+    // without the log messages
+    fn effects(actions: Vec<Action>) -> Vec<Action> {
+        actions.into_iter().filter(|a| !matches!(a, Action::Log(..))).collect()
+    }
+
+    #[test]
+    fn test_keys(){
+        let (mut e, t) = engine("button(0) = a\nbutton(1) = b\n", "a = key(return)\nb = key(return), key(x)\n");
+        // the state joydev sends when a pad connects is ignored
+        assert_eq!(e.input(0, JoyEv::Button(0, 1), t - Duration::from_millis(1)), vec![]);
+        assert_eq!(e.input(0, JoyEv::Button(0, 1), t), vec![Action::KeyDown(Key::KEY_ENTER)]);
+        assert_eq!(e.input(0, JoyEv::Button(1, 1), t), vec![Action::KeyDown(Key::KEY_X)]);
+        // return is still held by button 1
+        assert_eq!(e.input(0, JoyEv::Button(0, 0), t), vec![]);
+        assert_eq!(e.input(0, JoyEv::Button(1, 0), t), vec![Action::KeyUp(Key::KEY_ENTER), Action::KeyUp(Key::KEY_X)]);
+        // releasing something that isn't held does nothing
+        assert_eq!(e.input(0, JoyEv::Button(1, 0), t), vec![]);
+        // unmapped inputs and pads do nothing
+        assert_eq!(e.input(0, JoyEv::Button(7, 1), t), vec![]);
+        assert_eq!(e.input(3, JoyEv::Button(0, 1), t), vec![]);
+    }
+
+    #[test]
+    fn test_axes(){
+        let (mut e, t) = engine("axis(0,-100,100) = leftx\naxis(1,-100,100) = lefty\naxis_as_button(2,-100) = left\n", "leftx = axis(mousex,10)\nlefty = axis(updown,1)\nleft = key(left)\n");
+        assert!(!e.is_moving());
+        assert_eq!(e.input(0, JoyEv::Axis(0, 50), t), vec![]);
+        assert_eq!(e.input(0, JoyEv::Axis(1, -100), t), vec![]);
+        assert!(e.is_moving());
+        assert_eq!(e.motion(), vec![Action::Rel(RelativeAxisType::REL_X, 5), Action::KeyDown(Key::KEY_DOWN), Action::KeyUp(Key::KEY_DOWN)]);
+        assert_eq!(e.input(0, JoyEv::Axis(0, 0), t), vec![]);
+        assert_eq!(e.motion(), vec![Action::KeyDown(Key::KEY_DOWN), Action::KeyUp(Key::KEY_DOWN)]);
+        // axis_as_button taps
+        assert_eq!(e.input(0, JoyEv::Axis(2, -100), t), vec![Action::KeyDown(Key::KEY_LEFT), Action::KeyUp(Key::KEY_LEFT)]);
+        assert_eq!(e.input(0, JoyEv::Axis(2, 0), t), vec![]);
+        // going away stops the motion
+        assert_eq!(effects(e.disconnect(0)), vec![Action::StopPad(0)]);
+        assert!(!e.is_moving());
+    }
+
+    #[test]
+    fn test_triggers(){
+        // a trigger only ever scrolls or moves the mouse one way, and does nothing while it is released
+        let (mut e, t) = engine("axis(2,-100,100,-100,10) = leftz\naxis(5,100,-100,100,0) = rightz\n", "leftz = axis(scrolly,10)\nrightz = axis(mousex,10)\n");
+        assert_eq!(e.input(0, JoyEv::Axis(2, -100), t), vec![]);
+        assert_eq!(e.input(0, JoyEv::Axis(5, 100), t), vec![]);
+        assert!(!e.is_moving());
+        assert_eq!(e.input(0, JoyEv::Axis(2, -95), t), vec![]);
+        assert!(!e.is_moving());
+        assert_eq!(e.input(0, JoyEv::Axis(2, 100), t), vec![]);
+        assert_eq!(e.input(0, JoyEv::Axis(5, 0), t), vec![]);
+        assert_eq!(e.motion(), vec![Action::Rel(RelativeAxisType::REL_X, 5), Action::Rel(RelativeAxisType::REL_WHEEL, 10)]);
+    }
+
+    #[test]
+    fn test_raw_abs_value(){
+        assert_eq!(raw_abs_value(-100, -100, 100, 0, 255), 0);
+        assert_eq!(raw_abs_value(0, -100, 100, 0, 255), 128);
+        assert_eq!(raw_abs_value(100, -100, 100, 0, 255), 255);
+        // the whole range of an i32
+        assert_eq!(raw_abs_value(-32767, -32767, 32767, i32::MIN, i32::MAX), i32::MIN);
+        assert_eq!(raw_abs_value(32767, -32767, 32767, i32::MIN, i32::MAX), i32::MAX);
+        assert_eq!(raw_abs_value(32767, -32767, 32767, i32::MAX, i32::MIN), i32::MIN);
+        // past the ends of the joypad axis
+        assert_eq!(raw_abs_value(32767, -100, 100, 0, 255), 255);
+        assert_eq!(raw_abs_value(-32767, -100, 100, 0, 255), 0);
+        // an axis with no range
+        assert_eq!(raw_abs_value(5, 0, 0, 0, 100), 50);
+
+        let (mut e, t) = engine("axis(0,-32767,32767) = throttle\n", "throttle = raw_abs(ABS_THROTTLE,-2147483648,2147483647)\n");
+        assert_eq!(effects(e.input(0, JoyEv::Axis(0, 32767), t)), vec![Action::Abs(evdev::AbsoluteAxisType::ABS_THROTTLE, i32::MAX)]);
+    }
+
+    #[test]
+    fn test_shared_controls(){
+        // one button mapped to two inputs does both, and an axis can also have a position mapped as a button
+        let (mut e, t) = engine("button(0) = start
+button(0) = plus
+axis(1,-100,100) = righty
+axis_as_button(1,100) = rtrigger
+",
+            "start = key(return)
+plus = key(x)
+righty = axis(mousey,10)
+rtrigger = key(r)
+");
+        assert_eq!(e.input(0, JoyEv::Button(0, 1), t), vec![Action::KeyDown(Key::KEY_ENTER), Action::KeyDown(Key::KEY_X)]);
+        assert_eq!(e.input(0, JoyEv::Axis(1, 50), t), vec![]);
+        assert_eq!(e.motion(), vec![Action::Rel(RelativeAxisType::REL_Y, 5)]);
+        assert_eq!(e.input(0, JoyEv::Axis(1, 100), t), vec![Action::KeyDown(Key::KEY_R), Action::KeyUp(Key::KEY_R)]);
+        assert_eq!(e.motion(), vec![Action::Rel(RelativeAxisType::REL_Y, 10)]);
+
+        // each of them is traced, and launches, as itself
+        let (mut e, t) = engine("button(0) = start\nbutton(0) = plus\n", "start = key(return)\nplus = launch foo\n");
+        e.trace = true;
+        let actions = e.input(0, JoyEv::Button(0, 1), t);
+        let traced: Vec<String> = actions.iter().filter_map(|a| match a{
+            Action::Trace("input", fields) => fields.iter().find(|(k, _)| *k == "input").map(|(_, v)| v.clone()),
+            _ => None,
+        }).collect();
+        assert_eq!(traced, vec!["start", "plus"]);
+        assert!(actions.iter().any(|a| matches!(a, Action::Launch{input, ..} if input == "plus")));
+    }
+
+    #[test]
+    fn test_resolve(){
+        let mapping = expand_mapping(&parse_mapping_file("button(0) = a
+button(0) = start
+axis(1,-100,100) = righty
+axis_as_button(1,100) = rtrigger
+axis_as_button(1,-100) = ltrigger
+axis_as_button(2,5) = up
+").unwrap(), &parse_config_file("a = key(a)\n").unwrap().mappings);
+        let positions = |ev| resolve(&mapping, ev).iter().map(|r| (r.input.to_string(), r.position)).collect::<Vec<_>>();
+        assert_eq!(positions(JoyEv::Button(0, 1)), vec![("a".to_string(), 1.0), ("start".to_string(), 1.0)]);
+        assert_eq!(positions(JoyEv::Button(0, 0)), vec![("a".to_string(), 0.0), ("start".to_string(), 0.0)]);
+        assert_eq!(positions(JoyEv::Button(1, 1)), vec![]);
+        assert_eq!(positions(JoyEv::Axis(1, -50)), vec![("righty".to_string(), -0.5), ("ltrigger".to_string(), 0.0), ("rtrigger".to_string(), 0.0)]);
+        assert_eq!(positions(JoyEv::Axis(1, 100)), vec![("righty".to_string(), 1.0), ("ltrigger".to_string(), 0.0), ("rtrigger".to_string(), 1.0)]);
+        assert_eq!(positions(JoyEv::Axis(2, 5)), vec![("up".to_string(), 1.0)]);
+        // only a has any targets, but everything is there
+        assert_eq!(resolve(&mapping, JoyEv::Button(0, 1))[1].targets, &[]);
+    }
+
+    #[test]
+    fn test_toggle_enabled(){
+        let (mut e, t) = engine("button(0) = a\nbutton(1) = select\n", "a = key(a)\nselect = toggle_enabled\non disabled = rumble(0.5,100)\non enabled = kill_launched\n");
+        assert_eq!(e.input(0, JoyEv::Button(0, 1), t), vec![Action::KeyDown(Key::KEY_A)]);
+        let actions = e.input(0, JoyEv::Button(1, 1), t);
+        assert!(actions.contains(&Action::Log(Level::Info, "Mapping disabled".to_string())));
+        assert_eq!(effects(actions), vec![Action::KeyUp(Key::KEY_A), Action::StopPad(0), Action::Rumble(0, 0.5, 100)]);
+        assert_eq!(e.input(0, JoyEv::Button(1, 0), t), vec![]);
+        assert_eq!(e.input(0, JoyEv::Button(0, 0), t), vec![]);
+        assert_eq!(e.input(0, JoyEv::Button(0, 1), t), vec![]);
+        assert_eq!(effects(e.input(0, JoyEv::Button(1, 1), t)), vec![Action::KillLaunched()]);
+        assert_eq!(e.input(0, JoyEv::Button(0, 1), t), vec![Action::KeyDown(Key::KEY_A)]);
+    }
+
+    #[test]
+    fn test_launch(){
+        let (mut e, t) = engine("button(0) = a\n", "a = launch(kill_on_release) foo %player%\n");
+        let target: LaunchTarget = "launch(kill_on_release) foo %player%".parse::<crate::Target>().map(|t| match t { crate::Target::Launch(l) => l, _ => panic!() }).unwrap();
+        assert_eq!(e.input(0, JoyEv::Button(0, 1), t), vec![Action::Launch{target, owner: Some((0, JDCId::Button(0))), device: "pad".to_string(), input: "a".to_string(), player: 1}]);
+        assert_eq!(e.input(0, JoyEv::Button(0, 0), t), vec![Action::StopLaunched((0, JDCId::Button(0)))]);
+        // players are numbered in the order of their joystick devices
+        e.connect(1, "other".to_string(), t);
+        assert_eq!(e.player(1), 2);
+    }
+}
@@ -0,0 +1,152 @@
+/*
+
+What does this file do?
+
+    Waits for anything that joy2uinput needs to react to: input from the joypads,
+    joypads being plugged in or removed, signals, and the timer that keeps the mouse
+    moving while a stick is held over. Everything is waited for in one place, with
+    one epoll, so that joy2uinput needs no extra threads and uses no CPU at all while
+    nothing is happening.
+
+*/
+
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+use std::os::fd::{AsFd, AsRawFd};
+use std::time::Duration;
+
+// how often to send motion while an axis is held
+pub const motion_interval: Duration = Duration::from_millis(20);
+
+// epoll data for the things that aren't joypads, joypads use their joystick number
+const HOTPLUG: u64 = 1 << 32;
+const SIGNAL: u64 = HOTPLUG + 1;
+const MOTION: u64 = HOTPLUG + 2;
+
+#[derive(Debug,PartialEq)]
+pub enum Wake{
+    // this joypad has input to read
+    Pad(u32),
+    Hotplug(),
+    Signal(),
+    // time to send some more motion
+    Motion(),
+}
+
+pub struct EventLoop{
+    epoll: Epoll,
+    motion: TimerFd,
+    moving: bool,
+}
+
+impl EventLoop{
+    pub fn new() -> std::io::Result<EventLoop> {
+        let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let motion = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC)?;
+        epoll.add(&motion, EpollEvent::new(EpollFlags::EPOLLIN, MOTION))?;
+        Ok(EventLoop{epoll, motion, moving: false})
+    }
+
+    pub fn add_pad<F: AsFd>(&self, id: u32, fd: F) -> std::io::Result<()> {
+        Ok(self.epoll.add(fd, EpollEvent::new(EpollFlags::EPOLLIN, id as u64))?)
+    }
+
+    pub fn remove_pad<F: AsFd>(&self, fd: F) -> std::io::Result<()> {
+        Ok(self.epoll.delete(fd)?)
+    }
+
+    pub fn add_hotplug<F: AsFd>(&self, fd: F) -> std::io::Result<()> {
+        Ok(self.epoll.add(fd, EpollEvent::new(EpollFlags::EPOLLIN, HOTPLUG))?)
+    }
+
+    pub fn add_signals<F: AsFd>(&self, fd: F) -> std::io::Result<()> {
+        Ok(self.epoll.add(fd, EpollEvent::new(EpollFlags::EPOLLIN, SIGNAL))?)
+    }
+
+    // The motion timer only runs while something is moving
+    pub fn set_moving(&mut self, moving: bool) -> std::io::Result<()> {
+        if moving == self.moving{
+            return Ok(());
+        }
+        if moving{
+            // start straight away (a zero delay would turn the timer off), then keep going
+            let start = TimeSpec::from(Duration::from_nanos(1));
+            self.motion.set(Expiration::IntervalDelayed(start, TimeSpec::from(motion_interval)), TimerSetTimeFlags::empty())?;
+        }
+        else{
+            self.motion.unset()?;
+        }
+        self.moving = moving;
+        Ok(())
+    }
+
+    // Waits until something happens, or until the timeout if there is one.
+    // Without a timeout there is always at least one wake
+    pub fn wait(&mut self, timeout: Option<Duration>) -> std::io::Result<Vec<Wake>> {
+        loop{
+            let wakes = self.wait_once(timeout)?;
+            if !wakes.is_empty() || timeout.is_some(){
+                return Ok(wakes);
+            }
+        }
+    }
+
+    // This can come back empty early, if a signal interrupts it or the motion timer went off just as it was stopped
+    fn wait_once(&mut self, timeout: Option<Duration>) -> std::io::Result<Vec<Wake>> {
+        let mut events = [EpollEvent::empty(); 16];
+        // round up, so that the timeout has definitely passed when this returns
+        let timeout = timeout.map(|t| t.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as isize).unwrap_or(-1);
+        let n = match self.epoll.wait(&mut events, timeout){
+            Ok(n) => n,
+            // a signal arrived, the signal pipe will say which
+            Err(nix::errno::Errno::EINTR) => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let mut wakes = Vec::new();
+        for e in events[..n].iter(){
+            wakes.push(match e.data(){
+                HOTPLUG => Wake::Hotplug(),
+                SIGNAL => Wake::Signal(),
+                MOTION => {
+                    // reading the timer resets it, the count of ticks is not needed, one lot of motion is enough to catch up
+                    let _ = nix::unistd::read(self.motion.as_fd().as_raw_fd(), &mut [0u8; 8]);
+                    if !self.moving{
+                        continue;
+                    }
+                    Wake::Motion()
+                },
+                id => Wake::Pad(id as u32),
+            });
+        }
+        Ok(wakes)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::event_loop::{EventLoop, Wake};
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_event_loop(){
+        let mut l = EventLoop::new().unwrap();
+        assert_eq!(l.wait(Some(Duration::from_millis(1))).unwrap(), vec![]);
+
+        let (read, mut write) = std::os::unix::net::UnixStream::pair().unwrap();
+        l.add_pad(3, &read).unwrap();
+        write.write_all(b"x").unwrap();
+        assert_eq!(l.wait(None).unwrap(), vec![Wake::Pad(3)]);
+        l.remove_pad(&read).unwrap();
+        assert_eq!(l.wait(Some(Duration::from_millis(1))).unwrap(), vec![]);
+
+        l.set_moving(true).unwrap();
+        let start = Instant::now();
+        assert_eq!(l.wait(None).unwrap(), vec![Wake::Motion()]);
+        assert_eq!(l.wait(None).unwrap(), vec![Wake::Motion()]);
+        assert!(start.elapsed() >= crate::event_loop::motion_interval);
+        l.set_moving(false).unwrap();
+        assert_eq!(l.wait(Some(Duration::from_millis(50))).unwrap(), vec![]);
+    }
+}
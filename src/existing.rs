@@ -0,0 +1,109 @@
+/*
+
+What does this file do?
+
+    Keeps hold of a mapping file that was already there, so that joy2u-mapgen can
+    edit it instead of starting again. When the edited mappings are written back
+    out, every line that wasn't changed stays exactly as it was, including comments
+    and any lines that joy2u-mapgen doesn't understand.
+
+*/
+
+use joy2uinput::map_config::Mapping;
+
+pub struct MappingFile{
+    lines: Vec<String>,
+    // the mapping on each line, if it is one
+    mappings: Vec<Option<Mapping>>,
+}
+
+impl MappingFile{
+    pub fn parse(text: &str) -> Self {
+        let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+        let mappings = lines.iter().map(|l| {
+            let t = l.trim();
+            if t.is_empty() || t.starts_with('#') {None} else {l.parse::<Mapping>().ok()}
+        }).collect();
+        MappingFile{lines, mappings}
+    }
+
+    pub fn mappings(&self) -> Vec<&Mapping> {
+        self.mappings.iter().flatten().collect()
+    }
+
+    // Lines that aren't blank, comments, or mappings. They are kept, but joy2uinput won't like them
+    pub fn unknown_lines(&self) -> Vec<(usize, &str)> {
+        self.lines.iter().zip(&self.mappings).enumerate().filter(|(_, (l, m))| {
+            let t = l.trim();
+            m.is_none() && !t.is_empty() && !t.starts_with('#')
+        }).map(|(i, (l, _))| (i + 1, l.as_str())).collect()
+    }
+
+    // The file again, with mappings. A mapping that is still there keeps its line, one that is
+    // now mapped from something else is changed where it is, one that is gone loses its line,
+    // and new ones go at the end
+    pub fn rewrite(&self, mappings: &[Mapping]) -> String {
+        let mut remaining: Vec<&Mapping> = mappings.iter().collect();
+        let was_there = |r: &Mapping| self.mappings.iter().flatten().any(|m| m == r);
+        let mut out = String::new();
+        for (line, m) in self.lines.iter().zip(&self.mappings){
+            let m = match m{
+                None => {
+                    out += line;
+                    out.push('\n');
+                    continue;
+                },
+                Some(m) => m,
+            };
+            let same = remaining.iter().position(|r| *r == m);
+            let moved = || remaining.iter().position(|r| r.to == m.to && !was_there(r));
+            if let Some(i) = same{
+                remaining.remove(i);
+                out += line;
+                out.push('\n');
+            }
+            else if let Some(i) = moved(){
+                out += &format!("{}\n", remaining.remove(i));
+            }
+        }
+        for r in remaining{
+            out += &format!("{}\n", r);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::existing::MappingFile;
+    use joy2uinput::config_file::parse_mapping_file;
+
+    #[test]
+    fn test_rewrite(){
+        let text = "# my pad
+button(0) = a
+button(1)   =   b
+
+axis(0,-32767,32767) = leftx
+something new = x
+button(2) = x
+";
+        let f = MappingFile::parse(text);
+        assert_eq!(f.mappings().len(), 4);
+        assert_eq!(f.unknown_lines(), vec![(6, "something new = x")]);
+
+        // nothing changed
+        assert_eq!(f.rewrite(&parse_mapping_file(text.replace("something new = x", "").as_str()).unwrap()), text);
+
+        // b is now button 3, x is gone, and y is new
+        let edited = parse_mapping_file("button(0) = a\nbutton(3) = b\naxis(0,-32767,32767) = leftx\nbutton(4) = y\n").unwrap();
+        assert_eq!(f.rewrite(&edited), "# my pad
+button(0) = a
+button(3) = b
+
+axis(0,-32767,32767) = leftx
+something new = x
+button(4) = y
+");
+    }
+}
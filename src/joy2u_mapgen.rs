@@ -11,7 +11,7 @@ use std::fmt::Debug;
 use inotify::{ Inotify, WatchMask, EventMask };
 use std::sync::mpsc::Sender;
 use std::ffi::OsString;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::thread::JoinHandle;
 use std::path::Path;
 use std::fs::OpenOptions;
@@ -21,54 +21,19 @@ use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::io::Read;
 use std::os::fd::AsFd;
-pub mod map_config;
-use map_config::JDEv;
-use map_config::{Button, Axis, JoyInput};
-
-const conf_dir_env_var: &'static str = "JOY2UINPUT_CONFDIR";
-
-const to_map: [JoyInput; 18] = [
-        JoyInput::Button(Button::Up()),
-        JoyInput::Button(Button::Down()),
-        JoyInput::Button(Button::Left()),
-        JoyInput::Button(Button::Right()),
-        
-        JoyInput::Button(Button::A()),
-        JoyInput::Button(Button::B()),
-        JoyInput::Button(Button::X()),
-        JoyInput::Button(Button::Y()),
-
-        JoyInput::Button(Button::Start()),
-        JoyInput::Button(Button::Select()),
-    
-        JoyInput::Button(Button::RShoulder()),
-        JoyInput::Button(Button::LShoulder()),
-        JoyInput::Button(Button::RTrigger()),
-        JoyInput::Button(Button::LTrigger()),
-
-        JoyInput::Axis(Axis::LeftX()),
-        JoyInput::Axis(Axis::LeftY()),
-        JoyInput::Axis(Axis::RightX()),
-        JoyInput::Axis(Axis::RightY()),
-    ];
-
-fn get_user_conf_dir() -> (PathBuf, bool){
-    if let Some(d) = std::env::var_os(conf_dir_env_var){
-        return (PathBuf::from(&d), true);
-    }
-    if let Some(d) = std::env::var_os("XDG_CONFIG_HOME"){
-        if d != ""{
-            let mut buf = PathBuf::from(&d);
-            buf.push("joy2uinput");
-            return (buf, false);
-        }
-    }
-    if let Some(mut home) = dirs::home_dir(){
-        home.push(".config/joy2uinput/");
-        return (home, false)
-    }
-    (PathBuf::from("/opt/joy2uinput/"), false)
-}
+use joy2uinput::map_config;
+use joy2uinput::config_file::{self, conf_dir_env_var};
+use joy2uinput::engine::{self, JoyEv, PadMapping};
+pub mod logging;
+use logging::{Logger, Level};
+use map_config::{JDEv, JoyInput, Mapping, Target, TargetMapping};
+mod sequence;
+mod calibration;
+use calibration::Calibration;
+mod existing;
+mod wizard;
+mod live;
+use existing::MappingFile;
 
 enum Fatal{
     Msg(String)
@@ -89,6 +54,41 @@ struct AxisMotion{
     n_events: u64,
 }
 
+// What the keyboard is being used for
+enum Stage{
+    // waiting for the axes to be left alone for long enough to see where they rest
+    Calibrating(),
+    Mapping(),
+    // something was pressed that is already mapped, waiting to be told what to do about it
+    Clash(JDEv),
+    // the buttons that were left over at the end, waiting for y or n
+    Leftovers(Vec<(u8, JoyInput)>),
+    // typing the number of an earlier input to go back to
+    Choosing(String),
+    // typing a number on the review screen
+    Review(String),
+    // there is already a mapping file for the joypad, waiting to be told whether to edit it or start again
+    Existing(MappingFile),
+    // the conf wizard, waiting for something on the joypad to set up
+    ConfInput(),
+    // waiting for the key that a button should press, and what the terminal has sent for it so far
+    ConfKey(JoyInput, Vec<u8>),
+    // waiting for the number of what an axis should do
+    ConfAxis(JoyInput),
+    // typing how fast it should go
+    ConfMultiplier(JoyInput, usize, String),
+    // showing what everything on the joypad is, until q or Esc
+    Testing(),
+}
+
+// Where the mapping file goes
+enum Output{
+    // named after the joypad, in the user config dir
+    ConfDir(),
+    File(PathBuf),
+    Stdout(),
+}
+
 enum Ev{
     Joy(OsString, joydev::Event),
     JoyAxisSettled(),
@@ -96,7 +96,11 @@ enum Ev{
     Connect(OsString),
     Disconnect(OsString),
     Listen(),
+    Calibrated(),
+    // if the escape key was pressed this long ago with nothing after it, it was just the escape key
+    EscapeTimeout(),
     Println(String),
+    Log(Level, String),
 }
 
 fn hotplug_thread(evs: Sender<Ev>) -> Option<std::thread::JoinHandle<()>> {
@@ -106,11 +110,14 @@ fn hotplug_thread(evs: Sender<Ev>) -> Option<std::thread::JoinHandle<()>> {
                 Ok(i)
             })() {
         Ok(a) => { Some(a)},
-        Err(_e) => { println!("Warning: failed to start inotify, hotplugging is unavailable"); None},
+        Err(_e) => {
+            let _ = evs.send(Ev::Log(Level::Warn, "failed to start inotify, hotplugging is unavailable".to_string()));
+            None
+        },
     };
 	
-    if let Some(mut inotify) = inotify{
-        Some(std::thread::spawn(move || {
+    inotify.map(|mut inotify|{
+        std::thread::spawn(move || {
             let mut buffer = [0; 1024];
             loop{
                 if let Ok(events) = inotify.read_events_blocking(&mut buffer){
@@ -125,18 +132,16 @@ fn hotplug_thread(evs: Sender<Ev>) -> Option<std::thread::JoinHandle<()>> {
                                 EventMask::DELETE => { evs.send(Ev::Disconnect(path.into())) },
                                 _ => unreachable!()
                             };
-                            if let Err(_) = res {
-                                println!("Internal error during joypad hotplug handler. This is a bug!");
+                            if res.is_err() {
+                                // main loop has gone away, nobody left to tell
+                                return;
                             }
                         }
                     }
                 }
             }
-        }))
-    }
-    else{
-        None
-    }
+        })
+    })
 }
 
 fn pad_thread(evs: Sender<Ev>, s: &Path) -> joydev::Result<(String, std::fs::File, JoinHandle<()>)> {
@@ -146,44 +151,131 @@ fn pad_thread(evs: Sender<Ev>, s: &Path) -> joydev::Result<(String, std::fs::Fil
     let p: OsString = PathBuf::from(s).as_os_str().into();
     let _ = evs.send(Ev::Println(format!("Device connected: {}", name)));
     Ok((name.clone(), fd, std::thread::spawn(move ||{
-        loop{
-            match joydev::io_control::get_event(rfd){
-                Ok(ev) => {
-                    if let Err(_) = evs.send(Ev::Joy(p.clone(), ev)){
-                        println!("Internal error in joypad handler thread. This is a bug!");
-                    }
-                },
-                _ => {break;}
+        while let Ok(ev) = joydev::io_control::get_event(rfd){
+            if evs.send(Ev::Joy(p.clone(), ev)).is_err(){
+                return;
             }
         }
         let _ = evs.send(Ev::Println(format!("Device disconnected: {}", name)));
     })))
 }
 
-fn listen_after(evs: Sender<Ev>, msecs: u64) -> JoinHandle<()> {
+fn send_after(evs: Sender<Ev>, msecs: u64, ev: Ev) -> JoinHandle<()> {
     std::thread::spawn(move ||{
         std::thread::sleep(Duration::from_millis(msecs));
-        if let Err(_) = evs.send(Ev::Listen()){
-            println!("Internal error while waiting to start. This is a bug!");
-        }
+        let _ = evs.send(ev);
     })
 }
 
-fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where A: std::io::Write {
+// Whether a joypad is the one that was asked for with --device, by its name or its device path
+fn is_chosen(device: &Option<String>, path: &OsString, name: &str) -> bool {
+    match device{
+        None => true,
+        Some(d) => d == name || Path::new(d) == Path::new(path),
+    }
+}
+
+// Every connected joypad, with the file its mapping would be in, and where that mapping is now (if anywhere)
+fn list_pads<A>(logger: &mut Logger<A>) -> Result<(), Fatal> where A: Write {
+    let mut devices: Vec<PathBuf> = std::fs::read_dir("/dev/input").map_err(|_| Fatal::Msg("Unable to read from /dev/input".to_string()))?
+        .flatten().map(|j| j.path()).filter(|n| n.to_string_lossy().starts_with("/dev/input/js")).collect();
+    devices.sort();
+    for dev in devices{
+        let name = match OpenOptions::new().read(true).open(&dev){
+            Ok(f) => joydev::io_control::get_identifier(f.as_raw_fd()).unwrap_or("unknown".to_string()),
+            Err(e) => {
+                logger.log(Level::Warn, &format!("Unable to open {}: {}", dev.display(), e));
+                continue;
+            },
+        };
+        let filename = map_config::jpname_to_filename(&name);
+        let mapped = match config_file::find_mapping_file(&filename){
+            Some(f) if f.starts_with(config_file::default_conf_dir) => "system",
+            Some(_) => "user",
+            None => "none",
+        };
+        let _res = writeln!(logger.out, "{}\t{}\t{}\t{}", dev.display(), name, filename.to_string_lossy(), mapped);
+    }
+    Ok(())
+}
+
+// The mapping file that joy2uinput would use for a joypad, and what is in it
+fn load_pad_mapping(name: &str) -> Result<(PathBuf, Vec<Mapping>), String> {
+    let path = config_file::find_mapping_file(&map_config::jpname_to_filename(name))
+        .ok_or(format!("There is no mapping file for {}, run joy2u-mapgen to make one first", name))?;
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("Unable to read the mapping file '{}': {}", path.display(), e))?;
+    let mappings = config_file::parse_mapping_file(&text).map_err(|errors|
+        errors.iter().map(|e| format!("'{}' {}", path.display(), e)).collect::<Vec<_>>().join("\n")
+    )?;
+    Ok((path, mappings))
+}
 
+// A built in template, or a template file
+fn load_template(name: &str) -> Result<Vec<sequence::Prompt>, Fatal> {
+    let text = match sequence::builtin_template(name){
+        Some(t) => t.to_string(),
+        None => std::fs::read_to_string(name).map_err(|e| {
+            let builtins: Vec<_> = sequence::builtin_templates.iter().map(|(n, _)| *n).collect();
+            Fatal::Msg(format!("Unable to read template '{}': {} (the built in templates are: {})", name, e, builtins.join(", ")))
+        })?,
+    };
+    let seq = sequence::parse_template(&text).map_err(|errors|
+        Fatal::Msg(errors.iter().map(|e| format!("Invalid template '{}' {}", name, e)).collect::<Vec<_>>().join("\n"))
+    )?;
+    if seq.is_empty(){
+        return Err(Fatal::Msg(format!("Template '{}' doesn't ask for any buttons or axes", name)));
+    }
+    Ok(seq)
+}
+
+fn wrapped_main<K, A>(keys: K, stdout: A, args: &[String]) -> Result<(),Fatal> where K: Read + Send + 'static, A: std::io::Write {
+
+    let mut logger = Logger::new(stdout);
+
+    // println is for the interactive interface, everything else goes through the logger
     macro_rules! println {
-        () => { write!(stdout, "\n"); };
-        ($fstr:literal) => {{ let _res = write!(stdout, concat!($fstr, "\n")); }};
-        ($fstr:literal, $($arg:tt)*) => {{ let _res = write!(stdout, concat!($fstr, "\n"), $($arg)*); }};
+        ($fstr:literal) => {{ let _res = writeln!(logger.out, $fstr); }};
+        ($fstr:literal, $($arg:tt)*) => {{ let _res = writeln!(logger.out, $fstr, $($arg)*); }};
     }
+    macro_rules! error { ($($arg:tt)*) => { logger.log(Level::Error, &format!($($arg)*)) }; }
+    macro_rules! warn { ($($arg:tt)*) => { logger.log(Level::Warn, &format!($($arg)*)) }; }
+    macro_rules! bug { ($($arg:tt)*) => { logger.bug(&format!($($arg)*)) }; }
 
     let mut debug_mode = false;
-    for arg in &args[1..]{
-        if arg == "--debug" {
-            debug_mode = true;
-        }
-        else{
-            println!("Warning: ignored arugment: {}", arg);
+    let mut conf_mode = false;
+    let mut test_mode = false;
+    let mut to_map = sequence::default_sequence();
+    let mut device = None;
+    let mut output = Output::ConfDir();
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next(){
+        match arg.as_ref(){
+            "--debug" => {
+                debug_mode = true;
+            },
+            "--template" => {
+                let template = args_iter.next().ok_or(Fatal::Msg("--template requires an argument".to_string()))?;
+                to_map = load_template(template)?;
+            },
+            "--device" => {
+                device = Some(args_iter.next().ok_or(Fatal::Msg("--device requires an argument".to_string()))?.clone());
+            },
+            "--output" => {
+                let path = args_iter.next().ok_or(Fatal::Msg("--output requires an argument".to_string()))?;
+                output = if path == "-" {Output::Stdout()} else {Output::File(PathBuf::from(path))};
+            },
+            "--conf" => {
+                conf_mode = true;
+            },
+            "--test" => {
+                test_mode = true;
+            },
+            "--list" => {
+                return list_pads(&mut logger);
+            },
+            _ => {
+                warn!("ignored argument: {}", arg);
+            },
         }
     }
 
@@ -192,14 +284,24 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
         println!("dumping all events for connected joypads...");
         println!("");
     }
+    else if test_mode{
+        println!("joy2u-mapgen - test a mapping file");
+        println!("");
+    }
+    else if conf_mode{
+        println!("joy2u-mapgen - set up joy2uinput.conf");
+        println!("");
+    }
     else{
         println!("joy2u-mapgen - generate a mapping file for joy2uinput");
         println!("");
     }
 
-    let (user_conf_dir, is_from_env) = get_user_conf_dir();
+    let (user_conf_dir, is_from_env) = config_file::user_conf_dir();
 
-    if !debug_mode{
+    // nothing is written in test mode
+    let writes = !debug_mode && !test_mode;
+    if writes && matches!(output, Output::ConfDir()){
         if !user_conf_dir.is_dir() {
             let r = std::fs::create_dir_all(&user_conf_dir);
             match r{
@@ -219,6 +321,13 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
             println!("Note: config path was set by environment variable: {}", conf_dir_env_var);
         }
     }
+    else if writes{
+        match &output{
+            Output::File(path) => {println!("Generated configuration will be output to `{}`", path.display());},
+            Output::Stdout() => {println!("Generated configuration will be output to standard output");},
+            Output::ConfDir() => {},
+        }
+    }
 
     let (send, recv) = std::sync::mpsc::channel::<Ev>();
     let _hp_thread = hotplug_thread(send.clone());
@@ -227,15 +336,13 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
     match std::fs::read_dir("/dev/input"){
         Err(_) => return Err(Fatal::Msg("Unable to read from /dev/input".to_string())),
         Ok(d) => {
-            for f in d{
-                if let Ok(j) = f{
-                    let n = j.path();
-                    if n.to_string_lossy().starts_with("/dev/input/js"){
-                        if let Err(_) = send.send(Ev::Connect(n.into())) {
-                            println!("Internal error while enumerating joypad devices. This is a bug!");
-                        }
-                        n_pads += 1;
+            for j in d.flatten(){
+                let n = j.path();
+                if n.to_string_lossy().starts_with("/dev/input/js"){
+                    if send.send(Ev::Connect(n.into())).is_err() {
+                        bug!("event channel closed while enumerating joypad devices");
                     }
+                    n_pads += 1;
                 }
             }
         }
@@ -243,22 +350,11 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
 
     let key_sender = send.clone();
     let _keyboard_thread = std::thread::spawn(move||{
-        let stdin = std::io::stdin();
-        use nix::sys::termios::{self, LocalFlags};
-        let attrs = termios::tcgetattr(stdin.as_fd());
-        // Make best effort to get terminal into the right mode
-        match attrs{
-            Err(_) => {return;}
-            Ok(mut attrs) => {
-                attrs.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO);
-                let _ = termios::tcsetattr(stdin.as_fd(), nix::sys::termios::SetArg::TCSANOW, &attrs);
-            }
-        }
-        for b in stdin.bytes(){
+        for b in std::io::BufReader::new(keys).bytes(){
             match b {
                 Err(_) => break,
                 Ok(b) => {
-                    if let Err(_a) = key_sender.send(Ev::Key(b)){
+                    if key_sender.send(Ev::Key(b)).is_err(){
                         break;
                     }
                 }
@@ -272,7 +368,10 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
         println!("");
         println!("{} devices currently connected", n_pads);
         println!("");
-        println!("To start generating a config, press any button on the joypad to configure.");
+        match &device{
+            Some(d) => {println!("To start generating a config, press any button on {}.", d);},
+            None => {println!("To start generating a config, press any button on the joypad to configure.");},
+        }
         println!("");
     }
 
@@ -283,12 +382,48 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
     let mut mapping_path: Option<PathBuf> = None;
 
 
-    let mut next_map = 0;
-    
-
-    let mut config: HashMap<JDEv,&JoyInput> = HashMap::new();
+    let mut session = sequence::Session::new(to_map);
+    let mut stage = Stage::Mapping();
+    // how many buttons the joypad being mapped has
+    let mut n_buttons = 0;
+    let mut leftovers_offered = false;
+    // the last value of every axis of every joypad, so that it is known where they were before calibrating
+    let mut axis_values: HashMap<OsString, HashMap<u8, i16>> = HashMap::new();
+    let mut calibration = Calibration::default();
+    // the mapping file that was already there for the joypad being mapped, if there was one
+    let mut found: Option<MappingFile> = None;
+    // the same, if it is being edited
+    let mut existing: Option<MappingFile> = None;
+    // the conf wizard: the config file being changed, the mapping file of the joypad, and what has been set up so far
+    let mut conf_file = wizard::ConfFile::parse("");
+    let mut pad_mapping: PadMapping = HashMap::new();
+    let mut conf_set: Vec<TargetMapping> = Vec::new();
+    // axes that have already been seen moved, so that holding one still doesn't keep choosing it
+    let mut axes_held: HashSet<u8> = HashSet::new();
+    // test mode: what is happening on the joypad, and how many lines of the table are on the screen
+    let mut live = live::Live::new(HashMap::new());
+    let mut drawn = 0;
+    // joypads that can't be set up or tested, so that they are only mentioned once
+    let mut unmapped_pads: HashSet<OsString> = HashSet::new();
+    if conf_mode{
+        mapping_path = match &output{
+            Output::ConfDir() => Some(user_conf_dir.join(config_file::conf_file_name)),
+            Output::File(path) => Some(path.clone()),
+            Output::Stdout() => None,
+        };
+        // the file that is about to be replaced, otherwise the one joy2uinput uses now
+        if let Some(path) = mapping_path.clone().filter(|p| p.is_file()).or_else(config_file::find_config_file){
+            match std::fs::read_to_string(&path){
+                Ok(text) => {
+                    println!("Changing the config from '{}'", path.display());
+                    conf_file = wizard::ConfFile::parse(&text);
+                },
+                Err(e) => { warn!("Unable to read the config file '{}': {}", path.display(), e); },
+            }
+        }
+    }
 
-    let mut recent_axes = HashMap::new();
+    let mut recent_axes: HashMap<u8, AxisMotion> = HashMap::new();
 
     let next_motion_check: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
 
@@ -305,7 +440,7 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
                 let t_next_check = next_motion_check.clone();
                 motion_check_thread = Some(std::thread::spawn(move || {
                     loop {
-                        let t = { t_next_check.lock().unwrap().clone() };
+                        let t = { *t_next_check.lock().unwrap() };
                         let now = Instant::now();
                         if now > t {
                             let _ = t_send.send(Ev::JoyAxisSettled());
@@ -324,47 +459,221 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
         () => { recent_axes.clear(); }
     }
 
-    macro_rules! next {
+    // Writes the text to mapping_path, or to stdout if that is None
+    macro_rules! write_out {
+        ($text:expr)=>{
+            let text: String = $text;
+            let destination = mapping_path.as_ref().map(|p| p.display().to_string()).unwrap_or("standard output".to_string());
+            let outfile: std::io::Result<Box<dyn Write>> = match mapping_path.as_ref(){
+                Some(filename) => OpenOptions::new().write(true).create(true).truncate(true).open(filename).map(|f| Box::new(f) as Box<dyn Write>),
+                None => Ok(Box::new(std::io::stdout())),
+            };
+            match outfile {
+                Ok(mut f) => {
+                    let mut success = true;
+                    if let Err(e) = f.write_all(text.as_bytes()){
+                        error!("Failed to write to file: {}", e);
+                        success = false;
+                    }
+                    if success {
+                        println!("Config file written!");
+                        return Ok(())
+                    }
+                    return Err(Fatal::Msg("Failed".to_string()));
+                },
+                Err(e) => { error!("Failed to write to config file: {}, {}", destination, e); },
+            }
+        }
+    }
+
+    macro_rules! write_config {
         ()=>{
-            next_map += 1;
-            reset_axes!();
-            if next_map >= to_map.len(){
-                println!("Complete!");
-                let filename = mapping_path.as_ref().unwrap();
-                let outfile = OpenOptions::new().write(true).create(true).truncate(true).open(filename);
-                match outfile {
-                    Ok(mut f) => {
-                        let mut success = true;
-                        if let Err(_) = writeln!(f, "# this joy2uinput mapping file was auto generated by joy2u-mapgen",){
-                            success = false;
-                        }
-                        else{
-                            let mut vconf: Vec<_> = config.iter().map(|(f,t)|(t,f)).collect();
-                            vconf.sort();
-                            for (&to, from) in vconf{
-                                if let Err(e) = writeln!(f, "{}", map_config::Mapping{from:from.clone(),to:to.clone()}){
-                                    println!("Failed to write to file: {}", e);
-                                    success = false;
-                                    break;
-                                }
+            println!("Complete!");
+            // an edited file keeps everything that wasn't changed
+            let text = match &existing{
+                Some(file) => file.rewrite(&session.mappings()),
+                None => {
+                    let mut text = "# this joy2uinput mapping file was auto generated by joy2u-mapgen\n".to_string();
+                    for m in session.mappings(){
+                        text += &format!("{}\n", m);
+                    }
+                    text
+                },
+            };
+            write_out!(text);
+        }
+    }
+
+    // Draws the test mode table over the last one
+    macro_rules! redraw {
+        ()=>{
+            let table = live.table();
+            if drawn > 0{
+                echo!(format!("\x1b[{}F", drawn));
+            }
+            for row in &table{
+                println!("{}\x1b[K", row);
+            }
+            drawn = table.len();
+        }
+    }
+
+    macro_rules! conf_ask {
+        ()=>{
+            println!("\nPress a button or move an axis on the joypad to choose what it does, or press enter to write the config file");
+            stage = Stage::ConfInput();
+        }
+    }
+
+    // Sets up what an input does, instead of whatever it did before
+    macro_rules! conf_record {
+        ($m:expr)=>{
+            let m: TargetMapping = $m;
+            println!("{}", m);
+            conf_set.retain(|c| c.from != m.from);
+            conf_set.push(m);
+            conf_ask!();
+        }
+    }
+
+    // Something on the joypad was pressed or moved in the conf wizard
+    macro_rules! conf_touched {
+        ($input:expr)=>{
+            let input: JoyInput = $input;
+            match std::mem::replace(&mut stage, Stage::ConfInput()){
+                Stage::ConfInput() => {
+                    let now = conf_set.iter().find(|m| m.from == input).map(|m| m.to_string()).or(conf_file.current(&input).map(|l| l.to_string()));
+                    match now{
+                        Some(now) => {println!("\n{} is currently: {}", input, now);},
+                        None => {println!("\n{} doesn't do anything yet", input);},
+                    }
+                    match input{
+                        JoyInput::Button(_) => {
+                            println!("Press the key that {} should press, or press {} again to leave it as it is", input, input);
+                            stage = Stage::ConfKey(input, Vec::new());
+                        },
+                        JoyInput::Axis(_) => {
+                            println!("What should {} do? Type the number, or move {} again to leave it as it is", input, input);
+                            for (i, (name, _)) in wizard::axis_targets.iter().enumerate(){
+                                println!("{:>4}. {}", i + 1, name);
                             }
-                        }
-                        if success {
-                            println!("Config file written!");
-                            return Ok(())
-                        }
-                        return Err(Fatal::Msg("Failed".to_string()));
-                    },
-                    Err(e) => { println!("Failed to write to config file: {}, {}", filename.display(), e); },
+                            stage = Stage::ConfAxis(input);
+                        },
+                    }
+                },
+                Stage::ConfKey(i, _) | Stage::ConfAxis(i) | Stage::ConfMultiplier(i, ..) if i == input => {
+                    println!("\nLeft {} as it is", input);
+                    conf_ask!();
+                },
+                other => { stage = other; },
+            }
+        }
+    }
+
+    // like print, but shows up straight away, for echoing what is typed
+    macro_rules! echo {
+        ($s:expr) => {{ let _res = write!(logger.out, "{}", $s); let _res = logger.out.flush(); }};
+    }
+
+    macro_rules! prompt {
+        ($p:expr)=>{
+            let p = $p;
+            if !p.group.is_empty() && (session.next == 0 || session.seq[session.next-1].group != p.group){
+                println!("\nNext: {} (press s to skip all of these)", p.group);
+            }
+            let what = match &p.text{
+                Some(text) => format!("{} ({})", p.input, text),
+                None => p.input.to_string(),
+            };
+            match p.input {
+                JoyInput::Button(_) => {println!("\nPress {}", what);},
+                JoyInput::Axis(_) => {println!("\nMove axis {} quickly to both extremes, then wait", what);},
+            }
+        }
+    }
+
+    macro_rules! list {
+        ()=>{
+            for (i, (p, m)) in session.seq.iter().zip(&session.mapped).enumerate(){
+                match m{
+                    Some(m) => {println!("{:>4}. {} is {}", i + 1, p.input, m);},
+                    None => {println!("{:>4}. {} is not mapped", i + 1, p.input);},
+                }
+            }
+        }
+    }
+
+    macro_rules! review {
+        ()=>{
+            println!("\nReview:");
+            list!();
+            println!("Press enter to write the mapping file, type a number and press enter to map that one again, or press backspace to undo the last change");
+            stage = Stage::Review(String::new());
+        }
+    }
+
+    // Asks for whatever is next, or finishes off if that was everything
+    macro_rules! ask {
+        ()=>{
+            reset_axes!();
+            if let Some(p) = session.current(){
+                stage = Stage::Mapping();
+                prompt!(p);
+            }
+            else if !leftovers_offered{
+                leftovers_offered = true;
+                let leftover = sequence::leftover_buttons(n_buttons, &session.mappings());
+                if leftover.is_empty(){
+                    review!();
+                }
+                else{
+                    println!("\nThese buttons have not been mapped:");
+                    for (b, to) in &leftover{
+                        println!("    {}", map_config::Mapping{from: JDEv::Button(*b), to: to.clone()});
+                    }
+                    println!("Press y to keep these as custom buttons, or n to leave them out");
+                    stage = Stage::Leftovers(leftover);
                 }
             }
-            else {
-                let n = &to_map[next_map];
-                match n {
-                    JoyInput::Button(_) => {println!("\nPress {}", n);},
-                    JoyInput::Axis(_) => {println!("\nMove axis {} quickly to both extremes, then wait", n);},
+            else{
+                review!();
+            }
+        }
+    }
+
+    // Maps the current prompt from something, unless that is already mapped to something else
+    macro_rules! assign {
+        ($from:expr)=>{
+            let from = $from;
+            let clashes = session.clashes(&from);
+            if clashes.is_empty(){
+                let i = session.next;
+                session.assign(from);
+                if let Some(warning) = session.hat_warning(i){
+                    println!("Warning: {}", warning);
                 }
+                ask!();
             }
+            else{
+                for i in clashes{
+                    println!("{} is already mapped to {}", from, session.seq[i].input);
+                }
+                println!("Press m to move it to {}, b to keep both, or r to try again", session.seq[session.next].input);
+                stage = Stage::Clash(from);
+            }
+        }
+    }
+
+    macro_rules! undo {
+        ()=>{
+            if session.undo(){
+                leftovers_offered = false;
+                println!("Undone");
+            }
+            else{
+                println!("Nothing to undo");
+            }
+            ask!();
         }
     }
 
@@ -372,12 +681,17 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
         ($ev:ident, $timeout:expr) => {
             let num = $ev.number();
             let val = $ev.value();
-            let mut axis = recent_axes.get(&num).map(|a|*a).or(Some(AxisMotion::default())).unwrap();
-            axis.min = axis.min.min(val);
-            axis.max = axis.max.max(val);
-            axis.n_events += 1;
-            recent_axes.insert(num, axis);
-            wait_to_settle!($timeout);
+            // ignore axes that are just wobbling about
+            if !calibration.is_noisy(num) && calibration.is_moved(num, val){
+                // the range always includes where the axis rests, a trigger that rests at one end only moves away from it
+                let centre = calibration.centre(num);
+                let mut axis = recent_axes.get(&num).copied().unwrap_or(AxisMotion{min: centre, max: centre, n_events: 0});
+                axis.min = axis.min.min(val);
+                axis.max = axis.max.max(val);
+                axis.n_events += 1;
+                recent_axes.insert(num, axis);
+                wait_to_settle!($timeout);
+            }
         }
     }
 
@@ -400,152 +714,493 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
         }
     }
 
-    loop{
-        match recv.recv(){
-            Ok(msg) => match msg {
-                Ev::Connect(s) => {
-                    listening = false;
-                    if !pads.contains_key(&s){
-                        let t = pad_thread(send.clone(), &Path::new(&s));
-                        match t{
-                            Ok(t) => {pads.insert(s,t);}
-                            Err(e) => {println!("Error connecting to joypad {:?}", e);}
-                        }
+    // Typing in a number, either to jump back to it or (when reviewing) to write the file
+    macro_rules! choose {
+        ($key:expr, $typed:expr, $review:expr)=>{
+            let b: u8 = $key;
+            let mut typed = $typed;
+            let review = $review;
+            macro_rules! keep_typing { () => { stage = if review {Stage::Review(typed)} else {Stage::Choosing(typed)}; } }
+            match b {
+                b'0'..=b'9' => {
+                    echo!(b as char);
+                    typed.push(b as char);
+                    keep_typing!();
+                },
+                b'\x7f' | b'\x08' => {
+                    if typed.pop().is_some(){
+                        echo!("\x08 \x08");
+                        keep_typing!();
+                    }
+                    else if review{
+                        undo!();
+                    }
+                    else{
+                        keep_typing!();
                     }
-                    _wait_thread = Some(listen_after(send.clone(), 200));
                 },
-                Ev::Disconnect(s) => {
-                    if let Some((_n, _fd, join)) = pads.remove(&s){
-                        let _ = join.join();
+                b'\n' | b'\r' => {
+                    println!("");
+                    if typed.is_empty(){
+                        if review{
+                            keep_typing!();
+                            write_config!();
+                        }
+                        else{
+                            ask!();
+                        }
+                    }
+                    else{
+                        match typed.parse::<usize>(){
+                            Ok(n) if n >= 1 && n <= session.seq.len() => {
+                                session.jump(n - 1);
+                                ask!();
+                            },
+                            _ => {
+                                println!("There is no number {}", typed);
+                                typed = String::new();
+                                keep_typing!();
+                            },
+                        }
                     }
                 },
-                Ev::Key(b) => {
-                    if debug_mode {continue;}
-                    match b {
-                        b' ' => {
-                            // Skip this button
-                            next!();
-                        },
-                        b'q' | b'\x1b' => {
-                            // Quit
-                            break;
-                        },
-                        _ => {},
+                b'\x1b' => {
+                    if review{
+                        break;
                     }
+                    println!("");
+                    ask!();
                 },
-                Ev::Joy(dev, ev) => {
-                    use joydev::GenericEvent;
-                    if listening {
-                        let pad = pads.get(&dev);
-                        if pad.is_none(){
-                            continue;
+                _ => { keep_typing!(); },
+            }
+        }
+    }
+
+    while let Ok(msg) = recv.recv(){
+        match msg {
+            Ev::Connect(s) => {
+                listening = false;
+                if let std::collections::hash_map::Entry::Vacant(slot) = pads.entry(s){
+                    let t = pad_thread(send.clone(), Path::new(slot.key()));
+                    match t{
+                        Ok(t) => {slot.insert(t);}
+                        Err(e) => {error!("Unable to connect to joypad {:?}", e);}
+                    }
+                }
+                _wait_thread = Some(send_after(send.clone(), 200, Ev::Listen()));
+            },
+            Ev::Disconnect(s) => {
+                if let Some((_n, _fd, join)) = pads.remove(&s){
+                    let _ = join.join();
+                }
+            },
+            Ev::Key(b) => {
+                if debug_mode {continue;}
+                if cur_dev.is_none(){
+                    if b == b'q' || b == b'\x1b'{
+                        break;
+                    }
+                    continue;
+                }
+                if b == b'q' && !matches!(stage, Stage::ConfKey(..)){
+                    // Quit
+                    break;
+                }
+                match std::mem::replace(&mut stage, Stage::Mapping()){
+                    Stage::Calibrating() => {
+                        if b == b'\x1b'{
+                            break;
                         }
-                        if debug_mode {
-                            println!("{}: {}", pad.unwrap().0,
-                                match ev.type_(){
-                                    joydev::EventType::Button | joydev::EventType::ButtonSynthetic => {
-                                        format!("button({}) value {}", ev.number(), ev.value())
-                                    },
-                                    joydev::EventType::Axis | joydev::EventType::AxisSynthetic => {
-                                        format!("axis({}, _, _) value {}", ev.number(), ev.value())
-                                    },
+                        stage = Stage::Calibrating();
+                    },
+                    Stage::Mapping() => {
+                        match b {
+                            b' ' => {
+                                // Skip this button
+                                session.skip();
+                                ask!();
+                            },
+                            b's' => {
+                                // Skip the rest of this group
+                                session.skip_group();
+                                ask!();
+                            },
+                            b'\x7f' | b'\x08' => {
+                                undo!();
+                            },
+                            b'j' => {
+                                println!("");
+                                list!();
+                                println!("Type the number of the one to map again and press enter, or press Esc to carry on");
+                                stage = Stage::Choosing(String::new());
+                            },
+                            b'\x1b' => {
+                                // Quit
+                                break;
+                            },
+                            _ => {},
+                        }
+                    },
+                    Stage::Clash(from) => {
+                        match b {
+                            b'm' | b'M' => {
+                                session.reassign(from);
+                                ask!();
+                            },
+                            b'b' | b'B' => {
+                                session.assign(from);
+                                ask!();
+                            },
+                            b'r' | b'R' => { ask!(); },
+                            b'\x7f' | b'\x08' => { undo!(); },
+                            b'\x1b' => { break; },
+                            _ => { stage = Stage::Clash(from); },
+                        }
+                    },
+                    Stage::Leftovers(leftover) => {
+                        match b {
+                            b'y' | b'Y' => {
+                                session.add_leftovers(leftover);
+                                ask!();
+                            },
+                            b'n' | b'N' => { ask!(); },
+                            b'\x7f' | b'\x08' => { undo!(); },
+                            b'\x1b' => { break; },
+                            _ => { stage = Stage::Leftovers(leftover); },
+                        }
+                    },
+                    Stage::Existing(file) => {
+                        match b {
+                            b'e' | b'E' => {
+                                session.load(&file.mappings());
+                                existing = Some(file);
+                                leftovers_offered = true;
+                                review!();
+                            },
+                            b'n' | b'N' => { ask!(); },
+                            b'\x1b' => { break; },
+                            _ => { stage = Stage::Existing(file); },
+                        }
+                    },
+                    Stage::ConfInput() => {
+                        match b {
+                            b'\n' | b'\r' => {
+                                stage = Stage::ConfInput();
+                                write_out!(conf_file.rewrite(&conf_set));
+                            },
+                            b'\x1b' => { break; },
+                            _ => { stage = Stage::ConfInput(); },
+                        }
+                    },
+                    Stage::ConfKey(input, mut typed) => {
+                        typed.push(b);
+                        match wizard::decode_key(&typed){
+                            wizard::KeyPress::Key(k) => {
+                                conf_record!(TargetMapping{from: input, to: vec![Target::Key(k)]});
+                            },
+                            wizard::KeyPress::Partial() => {
+                                if typed == [b'\x1b']{
+                                    _wait_thread = Some(send_after(send.clone(), 50, Ev::EscapeTimeout()));
                                 }
-                            );
-                            continue;
+                                stage = Stage::ConfKey(input, typed);
+                            },
+                            wizard::KeyPress::Unknown() => {
+                                println!("Can't tell which key that is, try another one (anything else can be set up by editing the config file)");
+                                stage = Stage::ConfKey(input, Vec::new());
+                            },
                         }
-                        let (name, _file, _joinhandle) = pad.unwrap();
-
-                        if let Some(cdev) = cur_dev.as_ref(){
-                            if &dev == cdev{
-                                // Do a mapping thing (maybe)
-                                let n = &to_map[next_map];
-                                match n{
-                                    JoyInput::Button(_) => {
-                                        match ev.type_() {
-                                            joydev::EventType::Button | joydev::EventType::ButtonSynthetic => {
-                                                if ev.value() == 1{
-                                                    println!("Button number {} is '{}'", ev.number(), n);
-                                                    config.insert(JDEv::Button(ev.number()), n);
-                                                    next!();
-                                                }
-                                            },
-                                            joydev::EventType::Axis | joydev::EventType::AxisSynthetic => {
-                                                if ev.value() != 0{
-                                                    record_axis_event!(ev, 300);
-                                                }
-                                            }
-                                        }
+                    },
+                    Stage::ConfAxis(input) => {
+                        match b {
+                            b'1'..=b'9' if ((b - b'1') as usize) < wizard::axis_targets.len() => {
+                                let n = (b - b'1') as usize;
+                                println!("How fast should it go? Type a number and press enter (just press enter for {}, or use a negative number to go the other way)", wizard::axis_targets[n].1);
+                                stage = Stage::ConfMultiplier(input, n, String::new());
+                            },
+                            b'\x1b' => {
+                                println!("Left {} as it is", input);
+                                conf_ask!();
+                            },
+                            _ => { stage = Stage::ConfAxis(input); },
+                        }
+                    },
+                    Stage::ConfMultiplier(input, n, mut typed) => {
+                        match b {
+                            b'0'..=b'9' | b'.' | b'-' => {
+                                echo!(b as char);
+                                typed.push(b as char);
+                                stage = Stage::ConfMultiplier(input, n, typed);
+                            },
+                            b'\x7f' | b'\x08' => {
+                                if typed.pop().is_some(){
+                                    echo!("\x08 \x08");
+                                }
+                                stage = Stage::ConfMultiplier(input, n, typed);
+                            },
+                            b'\n' | b'\r' => {
+                                println!("");
+                                let multiplier = if typed.is_empty() {Ok(wizard::axis_targets[n].1)} else {typed.parse::<f32>()};
+                                match multiplier.ok().and_then(|m| wizard::axis_target(n, m)){
+                                    Some(t) => {
+                                        conf_record!(TargetMapping{from: input, to: vec![Target::Axis(t)]});
                                     },
-                                    JoyInput::Axis(_) => {
-                                        match ev.type_() {
-                                            joydev::EventType::Button | joydev::EventType::ButtonSynthetic => {}, //ignore buttons if mapping an axis
-                                            joydev::EventType::Axis | joydev::EventType::AxisSynthetic => {
-                                                record_axis_event!(ev, 600);
-                                            }
-                                        }
+                                    None => {
+                                        println!("{} isn't a number, try again", typed);
+                                        stage = Stage::ConfMultiplier(input, n, String::new());
                                     },
                                 }
-                                //println!("Input from joypad {:#?}: {:?}", dev, ev);
+                            },
+                            b'\x1b' => {
+                                println!("");
+                                println!("Left {} as it is", input);
+                                conf_ask!();
+                            },
+                            _ => { stage = Stage::ConfMultiplier(input, n, typed); },
+                        }
+                    },
+                    Stage::Testing() => {
+                        if b == b'\x1b'{
+                            break;
+                        }
+                        stage = Stage::Testing();
+                    },
+                    Stage::Choosing(typed) => { choose!(b, typed, false); },
+                    Stage::Review(typed) => { choose!(b, typed, true); },
+                }
+            },
+            Ev::Joy(dev, ev) => {
+                use joydev::GenericEvent;
+                if let joydev::EventType::Axis | joydev::EventType::AxisSynthetic = ev.type_(){
+                    axis_values.entry(dev.clone()).or_default().insert(ev.number(), ev.value());
+                    if matches!(stage, Stage::Calibrating()) && cur_dev.as_ref() == Some(&dev){
+                        calibration.sample(ev.number(), ev.value());
+                    }
+                }
+                if listening {
+                    let pad = pads.get(&dev);
+                    if pad.is_none(){
+                        continue;
+                    }
+                    if debug_mode {
+                        println!("{}: {}", pad.unwrap().0,
+                            match ev.type_(){
+                                joydev::EventType::Button | joydev::EventType::ButtonSynthetic => {
+                                    format!("button({}) value {}", ev.number(), ev.value())
+                                },
+                                joydev::EventType::Axis | joydev::EventType::AxisSynthetic => {
+                                    format!("axis({}, _, _) value {}", ev.number(), ev.value())
+                                },
+                            }
+                        );
+                        continue;
+                    }
+                    let (name, file, _joinhandle) = pad.unwrap();
+
+                    if test_mode{
+                        if cur_dev.is_none() && is_chosen(&device, &dev, name) && !unmapped_pads.contains(&dev){
+                            match load_pad_mapping(name){
+                                Ok((path, m)) => {
+                                    println!("\nTesting joypad: {}", name);
+                                    println!("Using the mapping file '{}'", path.display());
+                                    println!("Press q to quit\n");
+                                    cur_dev = Some(dev.clone());
+                                    live = live::Live::new(engine::expand_mapping(&m, &HashMap::new()));
+                                    // where the axes were before this, so that it's known where they rest
+                                    for (n, v) in axis_values.get(&dev).into_iter().flatten(){
+                                        live.input(JoyEv::Axis(*n, *v));
+                                    }
+                                    stage = Stage::Testing();
+                                },
+                                Err(e) => {
+                                    println!("{}", e);
+                                    unmapped_pads.insert(dev.clone());
+                                },
                             }
                         }
+                        if cur_dev.as_ref() == Some(&dev){
+                            live.input(JoyEv::from(ev));
+                            redraw!();
+                        }
+                        continue;
+                    }
 
-                        if cur_dev.is_none(){
-                            cur_dev = Some(dev.clone());
-                            let mut path = user_conf_dir.clone();
-                            path.push(map_config::jpname_to_filename(name));
-                            println!("\nStarted mapping joypad: {}", name);
-                            if path.is_file() {
-                                println!("WARNING: Mapping this joypad will overwrite the existing mapping configuration in '{}'.", path.display());
+                    if conf_mode{
+                        if cur_dev.is_none() && is_chosen(&device, &dev, name) && !unmapped_pads.contains(&dev){
+                            match load_pad_mapping(name){
+                                Ok((_path, m)) => {
+                                    println!("\nStarted setting up joypad: {}", name);
+                                    cur_dev = Some(dev.clone());
+                                    pad_mapping = engine::expand_mapping(&m, &HashMap::new());
+                                    conf_ask!();
+                                },
+                                Err(e) => {
+                                    println!("{}", e);
+                                    unmapped_pads.insert(dev.clone());
+                                },
                             }
-                            mapping_path = Some(path);
-                            println!("To skip mapping a button, press the spacebar");
-                            if next_map < to_map.len(){
-                                let n = &to_map[next_map];
-                                println!("\nPress {}", n);
+                        }
+                        else if cur_dev.as_ref() == Some(&dev){
+                            let touched = match (JoyEv::from(ev), wizard::touched(&pad_mapping, JoyEv::from(ev))){
+                                // an axis only counts once each time it is moved
+                                (JoyEv::Axis(number, _), None) => { axes_held.remove(&number); None },
+                                (JoyEv::Axis(number, _), Some(t)) => if axes_held.insert(number) {Some(t)} else {None},
+                                (JoyEv::Button(..), t) => t,
+                            };
+                            if let Some(input) = touched{
+                                conf_touched!(input);
                             }
                         }
+                        continue;
                     }
-                },
-                Ev::JoyAxisSettled() => {
-                    if debug_mode {continue;}
-                    let _ = motion_check_thread.take().unwrap().join();
-                    let event = get_settled_event!();
-                    if event.is_none(){
-                        // spurious event
-                        println!("TODO: figure out why this happens and if it's a problem. (If you're seeing this message then ... first of all, hi :D, secondly, I still haven't fixed what might be a race condition. If the program misbehaves after this point, please send a bug report on github. Thanks.)");
+
+                    if let Some(cdev) = cur_dev.as_ref(){
+                        let current = session.current().map(|p| p.input.clone()).filter(|_| matches!(stage, Stage::Mapping()));
+                        if let (true, Some(n)) = (&dev == cdev, current.as_ref()){
+                            // Do a mapping thing (maybe)
+                            match n{
+                                JoyInput::Button(_) => {
+                                    match ev.type_() {
+                                        joydev::EventType::Button | joydev::EventType::ButtonSynthetic => {
+                                            if ev.value() == 1{
+                                                println!("Button number {} is '{}'", ev.number(), n);
+                                                assign!(JDEv::Button(ev.number()));
+                                            }
+                                        },
+                                        joydev::EventType::Axis | joydev::EventType::AxisSynthetic => {
+                                            record_axis_event!(ev, 300);
+                                        }
+                                    }
+                                },
+                                JoyInput::Axis(_) => {
+                                    match ev.type_() {
+                                        joydev::EventType::Button | joydev::EventType::ButtonSynthetic => {}, //ignore buttons if mapping an axis
+                                        joydev::EventType::Axis | joydev::EventType::AxisSynthetic => {
+                                            record_axis_event!(ev, 600);
+                                        }
+                                    }
+                                },
+                            }
+                            //println!("Input from joypad {:#?}: {:?}", dev, ev);
+                        }
                     }
-                    else{
-                        let (number, motion) = get_settled_event!().unwrap();
-                        reset_axes!();
-                        let n = &to_map[next_map];
-                        match n{
-                            JoyInput::Button(_) => {
-                                if motion.n_events > 1 {
-                                    println!("Detected axis event sequence. Are you sure you pressed a button? Try again.")
-                                }
-                                else{
-                                    let val = if motion.min != 0 {motion.min} else {motion.max};
-                                    println!("Axis {} at value of {} is button '{}'", number, val, n);
-                                    config.insert(JDEv::AxisAsButton(number, val), n);
-                                    next!();
-                                }
-                            },
-                            JoyInput::Axis(_) => {
-                                println!("Axis {} is '{}' with range {}..{}", number, n, motion.min, motion.max);
-                                config.insert(JDEv::Axis(number, motion.min, motion.max), n);
-                                next!();
-                            },
+
+                    if cur_dev.is_none() && is_chosen(&device, &dev, name){
+                        cur_dev = Some(dev.clone());
+                        mapping_path = match &output{
+                            Output::ConfDir() => Some(user_conf_dir.join(map_config::jpname_to_filename(name))),
+                            Output::File(path) => Some(path.clone()),
+                            Output::Stdout() => None,
+                        };
+                        println!("\nStarted mapping joypad: {}", name);
+                        // the file that is about to be replaced, otherwise the one joy2uinput would use now
+                        let found_path = mapping_path.clone().filter(|p| p.is_file()).or_else(|| config_file::find_mapping_file(&map_config::jpname_to_filename(name)));
+                        if let Some(path) = found_path{
+                            match std::fs::read_to_string(&path){
+                                Ok(text) => {
+                                    println!("There is already a mapping for this joypad in '{}'", path.display());
+                                    let file = MappingFile::parse(&text);
+                                    for (line, text) in file.unknown_lines(){
+                                        println!("Line {} of it isn't a mapping, it will be kept as it is: {}", line, text);
+                                    }
+                                    found = Some(file);
+                                },
+                                Err(e) => { warn!("Unable to read the mapping file that is already there, '{}': {}", path.display(), e); },
+                            }
                         }
+                        n_buttons = joydev::io_control::get_button_count(file.as_raw_fd()).unwrap_or(0);
+                        println!("To skip mapping a button, press the spacebar");
+                        println!("To undo the last one, press backspace. To go back to an earlier one, press j");
+                        println!("\nCalibrating, leave all of the sticks and triggers alone for a moment...");
+                        calibration = Calibration::new(axis_values.get(&dev).unwrap_or(&HashMap::new()));
+                        stage = Stage::Calibrating();
+                        _wait_thread = Some(send_after(send.clone(), calibration::calibration_time_ms, Ev::Calibrated()));
                     }
-                },
-                Ev::Println(s) => {
-                    println!("{}", s);
                 }
-                Ev::Listen() => {
-                    listening = true;
-                },
+            },
+            Ev::JoyAxisSettled() => {
+                if debug_mode {continue;}
+                let _ = motion_check_thread.take().unwrap().join();
+                let event = get_settled_event!();
+                if event.is_none(){
+                    // spurious event
+                    // TODO: figure out why this happens and if it's a problem. I still haven't fixed what might be a race condition.
+                    bug!("axis settled, but no axis motion was recorded");
+                }
+                else if !matches!(stage, Stage::Mapping()) || session.current().is_none(){
+                    reset_axes!();
+                }
+                else{
+                    let (number, motion) = get_settled_event!().unwrap();
+                    reset_axes!();
+                    let n = &session.current().unwrap().input.clone();
+                    match n{
+                        JoyInput::Button(_) => {
+                            // whichever end is furthest from where it rests
+                            let centre = calibration.centre(number) as i32;
+                            let val = if centre - motion.min as i32 > motion.max as i32 - centre {motion.min} else {motion.max};
+                            if calibration.is_trigger(number){
+                                // pressed means squeezed all the way, whether or not it was squeezed that far just now
+                                let val = if (val as i32) < centre {-i16::MAX} else {i16::MAX};
+                                println!("Axis {} is a trigger, pressed all the way to {} it is button '{}'", number, val, n);
+                                assign!(JDEv::AxisAsButton(number, val));
+                            }
+                            else if motion.n_events > 1 {
+                                println!("Detected axis event sequence. Are you sure you pressed a button? Try again.")
+                            }
+                            else{
+                                println!("Axis {} at value of {} is button '{}'", number, val, n);
+                                assign!(JDEv::AxisAsButton(number, val));
+                            }
+                        },
+                        JoyInput::Axis(_) => {
+                            let (centre, deadzone) = (calibration.centre(number), calibration.deadzone(number));
+                            if calibration.is_trigger(number){
+                                // the end it rests at is min, so that it goes from 0 when released to 1 when pressed
+                                let (released, pressed) = if centre > 0 {(motion.max, motion.min)} else {(motion.min, motion.max)};
+                                println!("Axis {} is a trigger '{}', from {} when released to {} when pressed, with deadzone {}", number, n, released, pressed, deadzone);
+                                assign!(JDEv::Axis(number, released, pressed, released, deadzone));
+                            }
+                            else{
+                                println!("Axis {} is '{}' with range {}..{}, centre {} and deadzone {}", number, n, motion.min, motion.max, centre, deadzone);
+                                assign!(JDEv::Axis(number, motion.min, motion.max, centre, deadzone));
+                            }
+                        },
+                    }
+                }
+            },
+            Ev::Println(s) => {
+                println!("{}", s);
+            }
+            Ev::Log(level, s) => {
+                logger.log(level, &s);
             }
-            _ => {break;}
+            Ev::Listen() => {
+                listening = true;
+            },
+            Ev::EscapeTimeout() => {
+                match std::mem::replace(&mut stage, Stage::Mapping()){
+                    Stage::ConfKey(input, typed) if typed == [b'\x1b'] => {
+                        conf_record!(TargetMapping{from: input, to: vec![Target::Key(map_config::KeyTarget::Escape())]});
+                    },
+                    other => { stage = other; },
+                }
+            },
+            Ev::Calibrated() => {
+                if !matches!(stage, Stage::Calibrating()){
+                    continue;
+                }
+                for axis in calibration.noisy_axes(){
+                    println!("Axis {} wobbles too much while it is left alone, it will be ignored", axis);
+                }
+                println!("Done");
+                match found.take(){
+                    Some(file) => {
+                        println!("\nPress e to edit the mapping that is already there, or n to start again from scratch");
+                        stage = Stage::Existing(file);
+                    },
+                    None => { ask!(); },
+                }
+            },
         }
     }
 
@@ -554,9 +1209,23 @@ fn wrapped_main<A>(mut stdout: A, args: &Vec<String>) -> Result<(),Fatal> where
 
 fn main() -> Result<(), Fatal>{
     let args: Vec<String> = std::env::args().collect();
-    wrapped_main(std::io::stdout(), &args)
+    let stdin = std::io::stdin();
+    use nix::sys::termios::{self, LocalFlags};
+    // Make best effort to get terminal into the right mode, so that keys are read as soon as they are pressed
+    if let Ok(mut attrs) = termios::tcgetattr(stdin.as_fd()){
+        attrs.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO);
+        let _ = termios::tcsetattr(stdin.as_fd(), nix::sys::termios::SetArg::TCSANOW, &attrs);
+    }
+    // the mapping file goes to stdout, so everything else has to go somewhere else
+    if args.windows(2).any(|a| a[0] == "--output" && a[1] == "-"){
+        wrapped_main(stdin, std::io::stderr(), &args)
+    }
+    else{
+        wrapped_main(stdin, std::io::stdout(), &args)
+    }
 }
 
+#[cfg(test)]
 mod test_utils;
 #[cfg(test)]
 mod test{
@@ -570,6 +1239,18 @@ mod test{
         }
     }
 
+    #[test]
+    fn test_is_chosen(){
+        use crate::is_chosen;
+        use std::ffi::OsString;
+        let js1 = OsString::from("/dev/input/js1");
+        assert!(is_chosen(&None, &js1, "pad"));
+        assert!(is_chosen(&Some("pad".to_string()), &js1, "pad"));
+        assert!(is_chosen(&Some("/dev/input/js1".to_string()), &js1, "pad"));
+        assert!(!is_chosen(&Some("/dev/input/js0".to_string()), &js1, "pad"));
+        assert!(!is_chosen(&Some("other pad".to_string()), &js1, "pad"));
+    }
+
     #[test]
     #[serial]
     fn test_joypad_debug_mode() {
@@ -581,7 +1262,7 @@ mod test{
         let args = vec!["joy2u-mapgen".to_string(), "--debug".to_string()];
         let (_stdout_read_thread, recv, _timeout_joinhandle) = spawn_main(
             move|stdout:std::os::unix::net::UnixStream|{
-                crate::wrapped_main(stdout, &args).unwrap();
+                crate::wrapped_main(std::io::empty(), stdout, &args).unwrap();
             }
         );
 
@@ -696,9 +1377,10 @@ mod test{
         let dir_path = tmp_dir.path();
         std::env::set_var("JOY2UINPUT_CONFDIR", dir_path);
         let args = vec!["joy2u-mapgen".to_string()];
+        let (mut keys, t_keys) = std::os::unix::net::UnixStream::pair().unwrap();
         let (_stdout_read_thread, recv, _timeout_joinhandle) = spawn_main(
             move|stdout:std::os::unix::net::UnixStream|{
-                crate::wrapped_main(stdout, &args).unwrap();
+                crate::wrapped_main(t_keys, stdout, &args).unwrap();
             }
         );
 
@@ -706,14 +1388,26 @@ mod test{
         let mut step = 0;
         let mut success = false;
 
-        use crate::to_map;
         use crate::JoyInput;
+        use std::io::Write;
+        // skip most of the axes, otherwise waiting for them to settle takes too long
+        let skipped = ["analog triggers", "flight and driving controls", "scroll wheels"];
+        let to_map: Vec<_> = crate::sequence::default_sequence().into_iter().filter(|p| !skipped.contains(&p.group.as_str())).map(|p| p.input).collect();
 
         for ev in recv{
             match ev {
                 TestEv::Timeout() => {panic!("Timeout");},
                 TestEv::Line(s) => {
                     println!("{}", s);
+                    if skipped.iter().any(|g| s.contains(&format!("Next: {} ", g))) {
+                        keys.write_all(b"s").expect("Write failed");
+                        continue;
+                    }
+                    // the same button and axis are used for everything
+                    if s.contains("b to keep both") {
+                        keys.write_all(b"b").expect("Write failed");
+                        continue;
+                    }
                     match step {
                         0 => {
                             // 3. wait for the normal output
@@ -800,8 +1494,39 @@ mod test{
                                 let step3 = step2 - (to_map.len() * 2);
                                 match step3 {
                                     0 => {
+                                        // everything was mapped from button 1, so the rest are left over
+                                        if s.contains("button(4) = custom_button(4)") {
+                                            next!(step);
+                                        }
+                                    },
+                                    1 => {
+                                        if s.contains("Press y to keep these as custom buttons") {
+                                            next!(step);
+                                            keys.write_all(b"y").expect("Write failed");
+                                        }
+                                    },
+                                    2 => {
+                                        if s.contains("custom_button(4) is button(4)") {
+                                            next!(step);
+                                        }
+                                    },
+                                    3 => {
+                                        if s.contains("Press enter to write the mapping file") {
+                                            next!(step);
+                                            keys.write_all(b"\n").expect("Write failed");
+                                        }
+                                    },
+                                    4 => {
                                         if s.contains("Complete!") {
-                                            // TODO check file is corect
+                                            next!(step);
+                                        }
+                                    },
+                                    5 => {
+                                        if s.contains("Config file written!") {
+                                            let path = dir_path.join(joy2uinput::map_config::jpname_to_filename("testing_joystick0"));
+                                            let written = std::fs::read_to_string(path).expect("mapping file was not written");
+                                            assert!(written.contains("button(0) = custom_button(0)"), "{}", written);
+                                            assert!(written.contains("button(1) = "), "{}", written);
                                             success = true;
                                             break;
                                         }
@@ -816,4 +1541,225 @@ mod test{
         }
         assert!(success, "Something didn't happen in the right sequence");
     }
+
+    #[test]
+    #[serial]
+    fn test_conf_wizard() {
+        // 1. a mapping file for the virtual joypad, and a config file to change
+        let tmp_dir = tempdir::TempDir::new("tmp_joy2uinput_conf").expect("failed to create temp dir");
+        let dir_path = tmp_dir.path().to_path_buf();
+        std::fs::write(dir_path.join(joy2uinput::map_config::jpname_to_filename("testing_joystick0")), "button(0) = a\nbutton(1) = b\naxis(0,-32767,32767) = leftx\n").unwrap();
+        std::fs::write(dir_path.join("joy2uinput.conf"), "# mine\na = key(x)\nset keyboard_layout = gb\n").unwrap();
+        std::env::set_var("JOY2UINPUT_CONFDIR", &dir_path);
+
+        // 2. create a virtual joypad (js0), and spawn a thread to run main()
+        let mut js0 = new_virtual_joypad("testing_joystick0");
+        let args = vec!["joy2u-mapgen".to_string(), "--conf".to_string()];
+        let (mut keys, t_keys) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (_stdout_read_thread, recv, _timeout_joinhandle) = spawn_main(
+            move|stdout:std::os::unix::net::UnixStream|{
+                crate::wrapped_main(t_keys, stdout, &args).unwrap();
+            }
+        );
+
+        use std::io::Write;
+        let press = |js0: &mut evdev::uinput::VirtualDevice, key: evdev::Key| {
+            js0.emit(&[
+                evdev::InputEvent::new(evdev::EventType::KEY, key.code(), 1),
+                evdev::InputEvent::new(evdev::EventType::KEY, key.code(), 0),
+            ]).expect("Emit failed");
+        };
+
+        let mut step = 0;
+        let mut success = false;
+        for ev in recv{
+            match ev {
+                TestEv::Timeout() => {panic!("Timeout");},
+                TestEv::Line(s) => {
+                    println!("{}", s);
+                    match step {
+                        0 => {
+                            if s.contains("joy2u-mapgen - set up joy2uinput.conf") {
+                                next!(step);
+                            }
+                        },
+                        1 => {
+                            if s.contains("Device connected: testing_joystick0") {
+                                next!(step);
+                                std::thread::sleep(std::time::Duration::from_millis(500)); // first 200ms of events are discarded, so wait a bit
+                                // 3. choose the joypad
+                                press(&mut js0, evdev::Key::BTN_TRIGGER);
+                            }
+                        },
+                        2 => {
+                            if s.contains("Started setting up joypad: testing_joystick0") {
+                                next!(step);
+                            }
+                        },
+                        3 => {
+                            if s.contains("Press a button or move an axis on the joypad") {
+                                next!(step);
+                                // 4. a is button 0
+                                press(&mut js0, evdev::Key::BTN_TRIGGER);
+                            }
+                        },
+                        4 => {
+                            if s.contains("a is currently: a = key(x)") {
+                                next!(step);
+                            }
+                        },
+                        5 => {
+                            if s.contains("Press the key that a should press") {
+                                next!(step);
+                                keys.write_all(b"z").expect("Write failed");
+                            }
+                        },
+                        6 => {
+                            if s.contains("a = key(z)") {
+                                next!(step);
+                            }
+                        },
+                        7 => {
+                            if s.contains("Press a button or move an axis on the joypad") {
+                                next!(step);
+                                // 5. b is button 1
+                                press(&mut js0, evdev::Key::BTN_DPAD_UP);
+                            }
+                        },
+                        8 => {
+                            if s.contains("b doesn't do anything yet") {
+                                next!(step);
+                            }
+                        },
+                        9 => {
+                            if s.contains("Press the key that b should press") {
+                                next!(step);
+                                // the up arrow
+                                keys.write_all(b"\x1b[A").expect("Write failed");
+                            }
+                        },
+                        10 => {
+                            if s.contains("b = key(up)") {
+                                next!(step);
+                            }
+                        },
+                        11 => {
+                            if s.contains("Press a button or move an axis on the joypad") {
+                                next!(step);
+                                // 6. leftx is axis 0
+                                js0.emit(&[
+                                    evdev::InputEvent::new(evdev::EventType::ABSOLUTE, evdev::AbsoluteAxisType::ABS_X.0, 100),
+                                ]).expect("Emit failed");
+                            }
+                        },
+                        12 => {
+                            if s.contains("What should leftx do?") {
+                                next!(step);
+                                keys.write_all(b"3").expect("Write failed");
+                            }
+                        },
+                        13 => {
+                            if s.contains("How fast should it go?") {
+                                next!(step);
+                                keys.write_all(b"2\n").expect("Write failed");
+                            }
+                        },
+                        14 => {
+                            if s.contains("leftx = axis(scrollx,2)") {
+                                next!(step);
+                            }
+                        },
+                        15 => {
+                            if s.contains("Press a button or move an axis on the joypad") {
+                                next!(step);
+                                keys.write_all(b"\n").expect("Write failed");
+                            }
+                        },
+                        16 => {
+                            if s.contains("Config file written!") {
+                                let written = std::fs::read_to_string(dir_path.join("joy2uinput.conf")).expect("config file was not written");
+                                assert_eq!(written, "# mine\na = key(z)\nset keyboard_layout = gb\nb = key(up)\nleftx = axis(scrollx,2)\n");
+                                success = true;
+                                break;
+                            }
+                        },
+                        _ => {panic!("Unexpected step");},
+                    }
+                },
+            }
+        }
+        assert!(success, "Something didn't happen in the right sequence");
+    }
+
+    #[test]
+    #[serial]
+    fn test_test_mode() {
+        // 1. a mapping file for the virtual joypad that doesn't have everything
+        let tmp_dir = tempdir::TempDir::new("tmp_joy2uinput_test").expect("failed to create temp dir");
+        let dir_path = tmp_dir.path().to_path_buf();
+        std::fs::write(dir_path.join(joy2uinput::map_config::jpname_to_filename("testing_joystick0")), "button(0) = a\naxis(0,-32767,32767) = leftx\n").unwrap();
+        std::env::set_var("JOY2UINPUT_CONFDIR", &dir_path);
+
+        // 2. create a virtual joypad (js0), and spawn a thread to run main()
+        let mut js0 = new_virtual_joypad("testing_joystick0");
+        let args = vec!["joy2u-mapgen".to_string(), "--test".to_string()];
+        let (mut keys, t_keys) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (_stdout_read_thread, recv, _timeout_joinhandle) = spawn_main(
+            move|stdout:std::os::unix::net::UnixStream|{
+                crate::wrapped_main(t_keys, stdout, &args).unwrap();
+            }
+        );
+
+        use std::io::Write;
+        let mut step = 0;
+        let mut success = false;
+        for ev in recv{
+            match ev {
+                TestEv::Timeout() => {panic!("Timeout");},
+                TestEv::Line(s) => {
+                    println!("{}", s);
+                    match step {
+                        0 => {
+                            if s.contains("joy2u-mapgen - test a mapping file") {
+                                next!(step);
+                            }
+                        },
+                        1 => {
+                            if s.contains("Device connected: testing_joystick0") {
+                                next!(step);
+                                std::thread::sleep(std::time::Duration::from_millis(500)); // first 200ms of events are discarded, so wait a bit
+                                // 3. choose the joypad, and keep the button held
+                                js0.emit(&[
+                                    evdev::InputEvent::new(evdev::EventType::KEY, evdev::Key::BTN_TRIGGER.code(), 1),
+                                ]).expect("Emit failed");
+                            }
+                        },
+                        2 => {
+                            if s.contains("Testing joypad: testing_joystick0") {
+                                next!(step);
+                            }
+                        },
+                        3 => {
+                            if s.contains("button(0)") && s.contains("pressed") {
+                                next!(step);
+                                // 4. button 2 isn't in the mapping file
+                                js0.emit(&[
+                                    evdev::InputEvent::new(evdev::EventType::KEY, evdev::Key::BTN_DPAD_DOWN.code(), 1),
+                                ]).expect("Emit failed");
+                            }
+                        },
+                        4 => {
+                            if s.contains("NOT MAPPED") && s.contains("button(2)") {
+                                keys.write_all(b"q").expect("Write failed");
+                                success = true;
+                                break;
+                            }
+                        },
+                        _ => {panic!("Unexpected step");},
+                    }
+                },
+            }
+        }
+        assert!(success, "Something didn't happen in the right sequence");
+    }
 }
@@ -17,246 +17,219 @@ What does this file do?
 use std::path::PathBuf;
 use std::fmt::Debug;
 use inotify::{ Inotify, WatchMask, EventMask };
-use std::sync::mpsc::Sender;
-use std::sync::{Arc,Mutex};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::ffi::OsString;
-use std::collections::HashMap;
-use std::thread::JoinHandle;
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
 use std::fs::OpenOptions;
 use std::os::fd::AsRawFd;
-use std::time::Duration;
-mod map_config;
-use map_config::{JDEv, JoyInput, Target};
-use joydev::GenericEvent;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+use joy2uinput::map_config;
+use joy2uinput::config_file::{self, Config};
+use map_config::{Mapping, Target, Settings, Placeholder, Hook};
+mod logging;
+use logging::{Logger, Level};
+use joy2uinput::xkb::{self, Keymap};
+mod launcher;
+use launcher::{Launcher, Launch};
+mod rumble;
+use rumble::Rumbler;
+mod battery;
+use battery::Battery;
+mod event_loop;
+use event_loop::{EventLoop, Wake};
+mod batch;
+use batch::Batch;
+use joy2uinput::engine::{self, Engine, Action, JoyEv, JDCId, Mappings, Hooks};
 use std::fs::File;
 use std::rc::Rc;
-use std::io::BufRead;
+use std::io::{Read, Write, ErrorKind};
 use evdev::{InputEvent, EventType};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGHUP, SIGCHLD};
 
-const conf_dir_env_var: &'static str = "JOY2UINPUT_CONFDIR";
+const sysfs_env_var: &str = "JOY2UINPUT_SYSFS";
+const battery_poll_interval: Duration = Duration::from_secs(60);
 
-fn get_user_conf_dir() -> Option<PathBuf>{
-    if let Some(d) = std::env::var_os(conf_dir_env_var){
-        let dir = PathBuf::from(&d);
-        if !dir.is_dir(){
-            println!("Warning: {} does not point to a directory. No user config will be loaded.", conf_dir_env_var);
-            return None;
-        }
-        return Some(dir);
-    }
-    if let Some(d) = std::env::var_os("XDG_CONFIG_HOME"){
-        if d != ""{
-            let mut dir = PathBuf::from(&d);
-            dir.push("joy2uinput");
-            if dir.is_dir(){
-                return Some(dir);
-            }
-        }
-    }
-    if let Some(mut home) = dirs::home_dir(){
-        home.push(".config/joy2uinput/");
-        if home.is_dir(){
-            return Some(home);
-        }
-    }
-    let dir = PathBuf::from("/opt/joy2uinput/");
-    if dir.is_dir(){
-        return Some(dir);
-    }
-    None
+// where sysfs is mounted, this can be changed for testing
+fn sysfs_root() -> PathBuf {
+    std::env::var_os(sysfs_env_var).map(PathBuf::from).unwrap_or(PathBuf::from("/sys"))
+}
+
+fn get_user_conf_dir<W>(logger: &mut Logger<W>) -> Option<PathBuf> where W: Write {
+    config_file::find_user_conf_dir().unwrap_or_else(|e|{
+        logger.log(Level::Warn, &format!("{}. No user config will be loaded.", e));
+        None
+    })
 }
 
 enum Ev{
     Joy(u32, joydev::Event),
     Connect(OsString, u32),
     Disconnect(u32),
-    Listen(),
-    RawEvent(EventType, u16, i32),
-    Println(String),
+    // an axis is being held, time to send some more motion
+    Motion(),
+    Reload(),
+    // a launched program has exited
+    Reap(),
+    // time to check the joypad batteries again
+    Battery(),
+    Shutdown(),
 }
 
 
-fn hotplug_thread(evs: Sender<Ev>) -> Option<std::thread::JoinHandle<()>> {
-    let inotify = match (||->std::io::Result<Inotify>{
-                let i = Inotify::init()?;
-                i.watches().add("/dev/input", WatchMask::CREATE | WatchMask::DELETE | WatchMask::ATTRIB)?;
-                Ok(i)
-            })() {
-        Ok(a) => { Some(a)},
-        Err(_e) => { println!("Warning: failed to start inotify, hotplugging is unavailable"); None},
-    };
-	
-    if let Some(mut inotify) = inotify{
-        Some(std::thread::spawn(move || {
-            let mut buffer = [0; 1024];
-            loop{
-                if let Ok(events) = inotify.read_events_blocking(&mut buffer){
-                    for event in events{
-                        let n = event.name.unwrap();
-                        let nl = n.to_string_lossy();
-                        if nl.starts_with("js"){
-                            let mut path = PathBuf::from("/dev/input");
-                            path.push(n);
-                            let id: Result<u32,_> = nl[2..].parse();
-                            match id{
-                                Err(e) => {
-                                    eprintln!("Internal error during joypad hotplug handler. This is a bug! {}", e);
-                                },
-                                Ok(id) => {
-                                    let res = match event.mask {
-                                        EventMask::CREATE => { evs.send(Ev::Connect(path.into(), id)) },
-                                        EventMask::ATTRIB => { evs.send(Ev::Connect(path.into(), id)) },
-                                        EventMask::DELETE => { evs.send(Ev::Disconnect(id)) },
-                                        _ => unreachable!()
-                                    };
-                                    if let Err(e) = res {
-                                        eprintln!("Internal error during joypad hotplug handler. This is a bug! {}", e);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+fn hotplug_watch() -> std::io::Result<Inotify> {
+    let i = Inotify::init()?;
+    i.watches().add("/dev/input", WatchMask::CREATE | WatchMask::DELETE | WatchMask::ATTRIB)?;
+    Ok(i)
+}
+
+// Turns everything inotify has seen happen in /dev/input into joypads connecting and disconnecting
+fn hotplug_events(inotify: &mut Inotify) -> Vec<Result<Ev, String>> {
+    let mut buffer = [0; 1024];
+    let mut evs = Vec::new();
+    loop{
+        let events = match inotify.read_events(&mut buffer){
+            Ok(events) => events,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return evs,
+            Err(e) => {
+                evs.push(Err(format!("failed to read hotplug events: {}", e)));
+                return evs;
+            },
+        };
+        for event in events{
+            let n = match event.name{
+                Some(n) => n,
+                None => continue,
+            };
+            let nl = n.to_string_lossy();
+            if let Some(num) = nl.strip_prefix("js"){
+                let mut path = PathBuf::from("/dev/input");
+                path.push(n);
+                let id: Result<u32,_> = num.parse();
+                evs.push(match id{
+                    Err(e) => Err(format!("unable to parse joypad number during hotplug: {}", e)),
+                    Ok(id) => match event.mask {
+                        EventMask::CREATE => Ok(Ev::Connect(path.into(), id)),
+                        EventMask::ATTRIB => Ok(Ev::Connect(path.into(), id)),
+                        EventMask::DELETE => Ok(Ev::Disconnect(id)),
+                        _ => unreachable!()
+                    },
+                });
             }
-        }))
-    }
-    else{
-        None
+        }
     }
 }
 
-fn pad_thread(evs: Sender<Ev>, s: &Path) -> std::io::Result<(String, std::fs::File, JoinHandle<()>)> {
-    let fd = OpenOptions::new().read(true).open(s)?;
-    let rfd = fd.as_raw_fd();
-    let name = joydev::io_control::get_identifier(rfd).unwrap_or("unknown".to_string());
-    let sl = s.to_string_lossy();
-    assert!(sl.starts_with("/dev/input/js"));
-    let id: Result<u32, _> = sl[13..].parse();
-    match id {
-        Err(e) => {
-            eprintln!("Internal error in joypad handler thread. This is a bug! {}", e);
-            Err(std::io::Error::new(std::io::ErrorKind::Other, "Unable to parse ID"))
-        },
-        Ok(id) => {
-            let _ = evs.send(Ev::Println(format!("Device connected: {}", name)));
-            Ok((name.clone(), fd, std::thread::spawn(move ||{
-                loop{
-                    match joydev::io_control::get_event(rfd){
-                        Ok(ev) => {
-                            if let Err(e) = evs.send(Ev::Joy(id, ev)){
-                                eprintln!("Internal error in joypad handler thread. This is a bug! {}", e);
-                            }
-                        },
-                        _ => {break;}
-                    }
-                }
-                let _ = evs.send(Ev::Println(format!("Device disconnected: {}", name)));
-            })))
-        },
-    }
+// Opens a joypad without blocking, so that reading it only gets the events that are already waiting
+fn open_pad(s: &Path) -> std::io::Result<(String, File)> {
+    let fd = OpenOptions::new().read(true).custom_flags(nix::libc::O_NONBLOCK).open(s)?;
+    let name = joydev::io_control::get_identifier(fd.as_raw_fd()).unwrap_or("unknown".to_string());
+    Ok((name, fd))
 }
 
-fn listen_after(evs: Sender<Ev>, msecs: u64) -> JoinHandle<()> {
-    std::thread::spawn(move ||{
-        std::thread::sleep(Duration::from_millis(msecs));
-        if let Err(_) = evs.send(Ev::Listen()){
-            eprintln!("Internal error while waiting to start. This is a bug!");
+// Reads every event that a joypad has waiting, and whether the joypad has gone away
+fn read_pad(file: &File) -> (Vec<joydev::Event>, bool) {
+    let mut events = Vec::new();
+    loop{
+        match joydev::io_control::get_event(file.as_raw_fd()){
+            Ok(ev) => events.push(ev),
+            Err(joydev::Error::QueueEmpty) => return (events, false),
+            Err(_) => return (events, true),
         }
-    })
+    }
+}
+
+// Prints each connected joypad, with the mapping file it would use, and its battery level
+fn list_devices<W>(out: &mut W) -> std::io::Result<()> where W: Write {
+    let mut ids: Vec<u32> = std::fs::read_dir("/dev/input")?.flatten()
+        .filter_map(|j| j.file_name().to_str()?.strip_prefix("js")?.parse().ok())
+        .collect();
+    ids.sort();
+    for id in ids{
+        let path = PathBuf::from(format!("/dev/input/js{}", id));
+        let name = match OpenOptions::new().read(true).open(&path){
+            Ok(fd) => joydev::io_control::get_identifier(fd.as_raw_fd()).unwrap_or("unknown".to_string()),
+            Err(e) => format!("unable to open: {}", e),
+        };
+        let battery = match battery::power_supply(&sysfs_root(), &path).map(|ps| Battery::read(&ps)){
+            None => "none".to_string(),
+            Some(Ok(b)) => b.to_string(),
+            Some(Err(e)) => format!("unable to read: {}", e),
+        };
+        writeln!(out, "{}: {}", path.display(), name)?;
+        writeln!(out, "    mapping file: {}", map_config::jpname_to_filename(&name).to_string_lossy())?;
+        writeln!(out, "    battery: {}", battery)?;
+    }
+    Ok(())
 }
 
-fn read_mappings(path: &PathBuf, mappings: &mut HashMap<OsString, HashMap<JDEv, JoyInput>>) -> bool{
+fn read_mappings<W>(logger: &mut Logger<W>, path: &Path, mappings: &mut HashMap<OsString, Vec<Mapping>>) -> bool where W: Write {
     let mut success = true;
     if let Ok(dir) = std::fs::read_dir(path){
-        for f in dir{
-            match f{
-                Err(_) => {},
-                Ok(f) => {
-                    if let Ok(ft) = f.file_type(){
-                        if ft.is_file(){
-                            if f.path().extension() != Some(&std::ffi::OsStr::new("j2umap")){
-                                continue;
-                            }
-                            let mut this_map = HashMap::new();
-                            let path = f.path();
-                            if !mappings.contains_key(path.file_name().unwrap().into()){ // only if not already loaded this joypad
-                                if let Ok(file) = OpenOptions::new().read(true).open(&path) {
-                                    let mut line_num = 0;
-                                    for line in std::io::BufReader::new(file).lines(){
-                                        line_num += 1;
-                                        match line {
-                                            Ok(line) =>{
-                                                let t = line.trim();
-                                                if t.len() == 0{ continue; }
-                                                if t.starts_with("#"){ continue; }
-                                                let m = t.parse::<map_config::Mapping>();
-                                                match m{
-                                                    Ok(m) => {this_map.insert(m.from, m.to);},
-                                                    Err(e) => {
-                                                        println!("Error ('{}' line {}): {}", path.display(), line_num, e);
-                                                        success = false;
-                                                    }
-                                                }
-                                            },
-                                            Err(e) => {
-                                                println!("Failed to read line from config file: {}", e);
-                                                success = false;
-                                            },
+        for f in dir.flatten(){
+            if let Ok(ft) = f.file_type(){
+                if ft.is_file(){
+                    if f.path().extension() != Some(std::ffi::OsStr::new(config_file::mapping_file_extension)){
+                        continue;
+                    }
+                    let path = f.path();
+                    if !mappings.contains_key(path.file_name().unwrap()){ // only if not already loaded this joypad
+                        match std::fs::read_to_string(&path){
+                            Ok(text) => {
+                                let this_map = match config_file::parse_mapping_file(&text){
+                                    Ok(m) => m,
+                                    Err(errors) => {
+                                        for e in errors{
+                                            logger.log(Level::Error, &format!("'{}' {}", path.display(), e));
                                         }
-                                    }
-                                    mappings.insert(path.file_name().unwrap().into(), this_map);
-                                }
-                            }
+                                        success = false;
+                                        Vec::new()
+                                    },
+                                };
+                                logger.log(Level::Debug, &format!("Loaded mapping file {}", path.display()));
+                                mappings.insert(path.file_name().unwrap().into(), this_map);
+                            },
+                            Err(e) => {
+                                logger.log(Level::Error, &format!("Failed to read mapping file {}: {}", path.display(), e));
+                                success = false;
+                            },
                         }
                     }
-                },
+                }
             }
         }
     }
     success
 }
 
-fn read_config(path: &PathBuf) -> (Option<HashMap<JoyInput, Target>>, bool){
-    let mut success = true;
-    let mut conf_file = path.clone();
-    conf_file.push("joy2uinput.conf");
+fn read_config<W>(logger: &mut Logger<W>, path: &Path) -> (Option<Config>, bool) where W: Write {
+    let mut conf_file = path.to_path_buf();
+    conf_file.push(config_file::conf_file_name);
     if conf_file.is_file(){
-        match OpenOptions::new().read(true).open(&conf_file) {
+        match std::fs::read_to_string(&conf_file) {
             Err(e) => {
-                println!("Error while reading config file {}: {}", &conf_file.display(), e);
-                success = false;
+                logger.log(Level::Error, &format!("Unable to read config file {}: {}", &conf_file.display(), e));
+                return (None, false);
             },
-            Ok(f) => {
-                let mut map = HashMap::new();
-                let mut line_num = 0;
-                for line in std::io::BufReader::new(f).lines(){
-                    line_num += 1;
-                    match line {
-                        Ok(line) =>{
-                            let t = line.trim();
-                            if t.len() == 0{ continue; }
-                            if t.starts_with("#"){ continue; }
-                            let m = t.parse::<map_config::TargetMapping>();
-                            match m{
-                                Ok(m) => {map.insert(m.from, m.to);},
-                                Err(e) => {
-                                    println!("Error ('{}' line {}): {}", &conf_file.display(), line_num, e);
-                                    success = false;
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            println!("Failed to read line from config file: {}", e);
-                            success = false;
-                        },
-                    }
-                }
-                return (Some(map), success);
+            Ok(text) => {
+                let config = config_file::parse_config_file(&text);
+                logger.log(Level::Debug, &format!("Loaded config file {}", conf_file.display()));
+                return match config{
+                    Ok(config) => (Some(config), true),
+                    Err(errors) => {
+                        for e in errors{
+                            logger.log(Level::Error, &format!("'{}' {}", &conf_file.display(), e));
+                        }
+                        (Some(Config::default()), false)
+                    },
+                };
             }
         }
     }
-    (None, success)
+    (None, true)
 }
 
 enum Fatal{ Msg(String) }
@@ -266,34 +239,21 @@ impl Debug for Fatal{
         match self{
             Fatal::Msg(s) => write!(f, "{}", s),
         }
-   } 
+   }
 }
 
 
-// joydev control id (the number of a button or axis)
-#[derive(Debug,Eq, Hash, PartialEq)]
-enum JDCId{
-    Button(u8),
-    AxisAsButton(u8,i16),
-    Axis(u8),
-}
-
 #[derive(Debug)]
 struct ConnectedPad{
-    #[allow(dead_code)] // because we don't want to drop the File
     file: File,
-    mapping: Rc<HashMap<JDCId, (JDEv,Target)>>,
-    join: JoinHandle<()>,
-}
-
-impl From<&JDEv> for JDCId{
-    fn from(e: &JDEv) -> Self {
-        match e {
-            JDEv::Button(n) => JDCId::Button(*n),
-            JDEv::AxisAsButton(n,v) => JDCId::AxisAsButton(*n,*v),
-            JDEv::Axis(n,_,_) => JDCId::Axis(*n),
-        }
-    }
+    name: String,
+    // where to find the evdev device for rumbling, and the device itself once it has been opened
+    event_node: Option<PathBuf>,
+    rumble: Option<Rumbler>,
+    // the battery in sysfs, what it said last time, and whether battery_low has fired since it was last charged
+    power_supply: Option<PathBuf>,
+    battery: Option<Battery>,
+    battery_low: bool,
 }
 
 impl From<std::io::Error> for Fatal {
@@ -303,57 +263,76 @@ impl From<std::io::Error> for Fatal {
 }
 
 
-fn launch(args: &Vec<String>){
-    let c = &args[0];
-    let res = std::process::Command::new(c)
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .args(&args[1..])
-        .spawn();
-    if let Err(e) = res{
-        eprintln!("Failed to launch program: {:?}\n{}", args, e);
-    }
-    println!("Launched {:?}", args);
+// The signal handlers only set a flag and wake up the event loop through a pipe, the event loop does the real work
+struct Signals{
+    pipe: UnixStream,
+    reload: Arc<AtomicBool>,
+    reap: Arc<AtomicBool>,
+    shutdown: Arc<AtomicBool>,
 }
 
-fn wrapped_main<A>(mut stdout: A, _args: &Vec<String>) -> Result<(),Fatal> where A: std::io::Write  + std::marker::Send + 'static  {
+impl Signals{
+    fn new() -> std::io::Result<Signals> {
+        let (pipe, write) = UnixStream::pair()?;
+        pipe.set_nonblocking(true)?;
+        let signals = Signals{pipe, reload: Arc::default(), reap: Arc::default(), shutdown: Arc::default()};
+        for (sig, flag) in [(SIGHUP, &signals.reload), (SIGCHLD, &signals.reap), (SIGINT, &signals.shutdown), (SIGTERM, &signals.shutdown)]{
+            // the flag must be set before the wake up, so it is registered first
+            signal_hook::flag::register(sig, flag.clone())?;
+            signal_hook::low_level::pipe::register(sig, write.try_clone()?)?;
+        }
+        Ok(signals)
+    }
 
-    macro_rules! println {
-        () => { write!(stdout, "\n"); };
-        ($fstr:literal) => {{ let _res = write!(stdout, concat!($fstr, "\n")); }};
-        ($fstr:literal, $($arg:tt)*) => {{ let _res = write!(stdout, concat!($fstr, "\n"), $($arg)*); }};
+    fn events(&mut self) -> Vec<Ev> {
+        let mut buffer = [0; 64];
+        while matches!(self.pipe.read(&mut buffer), Ok(n) if n > 0) {}
+        let mut evs = Vec::new();
+        if self.reload.swap(false, Ordering::Relaxed){
+            evs.push(Ev::Reload());
+        }
+        if self.reap.swap(false, Ordering::Relaxed){
+            evs.push(Ev::Reap());
+        }
+        if self.shutdown.swap(false, Ordering::Relaxed){
+            evs.push(Ev::Shutdown());
+        }
+        evs
     }
+}
 
-    let mut pads: HashMap<u32,ConnectedPad> = HashMap::new();
-    let mut listening = false;
-    let mut _wait_thread = None;
-    let mut mappings: HashMap<OsString, HashMap<JDEv, JoyInput>> = HashMap::new();
-    let mut expanded_mappings: HashMap<OsString, Rc<HashMap<JDCId, (JDEv, Target)>>> = HashMap::new();
+// the mapping for each joypad, the hooks, the settings, and the keyboard layout for typing
+type LoadedConfig = (Mappings, Hooks, Settings, Keymap);
+
+fn load_config<W>(logger: &mut Logger<W>) -> Result<LoadedConfig, Fatal> where W: Write {
+    let mut mappings: HashMap<OsString, Vec<Mapping>> = HashMap::new();
+    let mut expanded_mappings: Mappings = HashMap::new();
 
     let mut outmap = None;
     let mut valid = true;
     let mut valid2;
 
-    if let Some(user_conf_dir) = get_user_conf_dir(){
-        valid &= read_mappings(&user_conf_dir, &mut mappings);
-        (outmap, valid2) = read_config(&user_conf_dir);
+    let user_conf_dir = get_user_conf_dir(logger);
+    if let Some(user_conf_dir) = &user_conf_dir{
+        valid &= read_mappings(logger, user_conf_dir, &mut mappings);
+        (outmap, valid2) = read_config(logger, user_conf_dir);
         valid &= valid2;
     }
 
-    let default_conf = PathBuf::from("/etc/joy2uinput/");
+    let default_conf = PathBuf::from(config_file::default_conf_dir);
     if default_conf.is_dir(){
-        valid &= read_mappings(&default_conf, &mut mappings);
+        valid &= read_mappings(logger, &default_conf, &mut mappings);
         if outmap.is_none(){
-            (outmap, valid2) = read_config(&default_conf);
+            (outmap, valid2) = read_config(logger, &default_conf);
             valid &= valid2;
         }
         if outmap.is_none(){
-            println!("Error: Unable to find config file joy2uinput.conf in user config dir or default config dir.");
-            match get_user_conf_dir(){
-                None => {println!("No user config dir searched was found");},
-                Some(d) => {println!("User config dir searched was: {}", d.display());},
+            logger.log(Level::Error, "Unable to find config file joy2uinput.conf in user config dir or default config dir.");
+            match &user_conf_dir{
+                None => {logger.log(Level::Info, "No user config dir searched was found");},
+                Some(d) => {logger.log(Level::Info, &format!("User config dir searched was: {}", d.display()));},
             }
-            println!("Default config dir searched was: /etc/joy2uinput/");
+            logger.log(Level::Info, "Default config dir searched was: /etc/joy2uinput/");
             return Err(Fatal::Msg("No config".to_string()));
         }
     }
@@ -362,57 +341,43 @@ fn wrapped_main<A>(mut stdout: A, _args: &Vec<String>) -> Result<(),Fatal> where
         return Err(Fatal::Msg("Config invalid".to_string()));
     }
 
-    if outmap.is_none(){
-        return Err(Fatal::Msg("No output mapping config found. Default config is missing from /etc/joy2uinput/joy2uinput.conf. User config dir also does not contain joy2uinput.conf. See documentation for user config dir search order.".to_string()));
-    }
-
-    let outmap = outmap.unwrap();
+    let Config{mappings: outmap, hooks, settings} = match outmap{
+        Some(outmap) => outmap,
+        None => {
+            return Err(Fatal::Msg("No output mapping config found. Default config is missing from /etc/joy2uinput/joy2uinput.conf. User config dir also does not contain joy2uinput.conf. See documentation for user config dir search order.".to_string()));
+        },
+    };
 
     for (k,v) in mappings.iter(){
-        let mut expmap: HashMap<JDCId, (JDEv, Target)> = HashMap::new();
-        for (from, to) in v.iter(){
-            if let Some(to) = outmap.get(&to) {
-                expmap.insert(from.into(), (from.clone(), to.clone()));
-            }
-        }
-        expanded_mappings.insert(k.clone(), Rc::new(expmap));
+        expanded_mappings.insert(k.clone(), Rc::new(engine::expand_mapping(v, &outmap)));
     }
 
-    let (send, recv) = std::sync::mpsc::channel::<Ev>();
-    let _hp_thread = hotplug_thread(send.clone());
-    // enumerate already connected joypads
-    match std::fs::read_dir("/dev/input"){
-        Err(_) => return Err(Fatal::Msg("Unable to read from /dev/input".to_string())),
-        Ok(d) => {
-            for f in d{
-                if let Ok(j) = f{
-                    let n = j.path();
-                    let nl = n.to_string_lossy();
-                    if nl.starts_with("/dev/input/js"){
-                        let id: Result<u32, _> = nl[13..].parse();
-                        match id {
-                            Err(e) => {
-                                eprintln!("Internal error in joypad handler thread. This is a bug! {}", e);
-                            },
-                            Ok(id) => {
-                                if let Err(_) = send.send(Ev::Connect(n.into(), id)) {
-                                    eprintln!("Internal error while enumerating joypad devices. This is a bug!");
-                                }
-                            }
-                        }
-                    }
-                }
+    // only load the keyboard layout if something is going to type with it
+    let texts: Vec<&String> = outmap.values().chain(hooks.values()).flatten().filter_map(|t| if let Target::Type(text) = t {Some(text)} else {None}).collect();
+    let mut keymap = Keymap::default();
+    if !texts.is_empty(){
+        keymap = Keymap::load(&xkb::symbols_dir(), &settings.keyboard_layout).map_err(|e|{
+            logger.log(Level::Error, &e);
+            Fatal::Msg("Unable to load keyboard layout".to_string())
+        })?;
+        for text in texts{
+            let missing: String = text.chars().filter(|c| keymap.stroke(*c).is_none()).collect();
+            if !missing.is_empty(){
+                logger.log(Level::Warn, &format!("These characters can't be typed with the keyboard layout {}, they will be skipped: {:?} (in {:?})", settings.keyboard_layout, missing, text));
             }
         }
     }
+    Ok((expanded_mappings, hooks, settings, keymap))
+}
 
-    let axis_speeds = Arc::new(Mutex::new(HashMap::<_, i32>::new()));
-    let fake_axis_speeds = Arc::new(Mutex::new(HashMap::<_, i32>::new()));
-
+// The virtual device only gets the keys and axes that the config could possibly send
+fn build_uinput_device(expanded_mappings: &Mappings, hooks: &Hooks, keymap: &Keymap) -> Result<evdev::uinput::VirtualDevice, Fatal> {
     let mut keys = evdev::AttributeSet::new();
     let mut axes = evdev::AttributeSet::new();
-    for (_jp, mapping) in expanded_mappings.iter(){
-        for (_from, (_from2, to)) in mapping.iter(){
+    let mut abs_axes: HashMap<u16, (i32, i32)> = HashMap::new();
+    let mapped = expanded_mappings.values().flat_map(|mapping| mapping.values().flat_map(|(_from, inputs)| inputs.iter().map(|(_input, to)| to)));
+    for targets in mapped.chain(hooks.values()){
+        for to in targets.iter(){
             match to{
                 Target::Key(k) => {
                     keys.insert(k.uinput_key());
@@ -425,250 +390,416 @@ fn wrapped_main<A>(mut stdout: A, _args: &Vec<String>) -> Result<(),Fatal> where
                     let aaxes = a.uinput_axis();
                     if let Some(axis) = aaxes{
                         axes.insert(axis);
-                        axis_speeds.lock().unwrap().insert(axis.0, 0);
+                    }
+                }
+                Target::RawAbs(axis, min, max) => {
+                    // if several targets use the same axis, the device gets a range that covers all of them
+                    let (lo, hi) = (*min.min(max), *min.max(max));
+                    let range = abs_axes.entry(axis.0).or_insert((lo, hi));
+                    *range = (range.0.min(lo), range.1.max(hi));
+                }
+                Target::Type(text) => {
+                    for stroke in text.chars().filter_map(|c| keymap.stroke(c)){
+                        keys.insert(stroke.key);
+                        keys.insert(xkb::SHIFT_KEY);
+                        keys.insert(xkb::ALTGR_KEY);
                     }
                 }
                 Target::ToggleEnabled() => {}
                 Target::Launch(_) => {}
+                Target::KillLaunched() => {}
+                Target::Rumble(..) => {}
             }
         }
     }
 
     let uinput_dev: Result<evdev::uinput::VirtualDevice,std::io::Error> = (||->_{
-        Ok(evdev::uinput::VirtualDeviceBuilder::new()?.name("joy2udev").with_keys(&keys)?.with_relative_axes(&axes)?.build()?)
+        let mut builder = evdev::uinput::VirtualDeviceBuilder::new()?.name("joy2udev").with_keys(&keys)?.with_relative_axes(&axes)?;
+        for (axis, (min, max)) in abs_axes.iter(){
+            let info = evdev::AbsInfo::new(*min, *min, *max, 0, 0, 0);
+            builder = builder.with_absolute_axis(&evdev::UinputAbsSetup::new(evdev::AbsoluteAxisType(*axis), info))?;
+        }
+        builder.build()
     })();
-    let mut uinput_dev = match uinput_dev {
-        Err(e) => { return Err(Fatal::Msg(format!("Unable to create virtual input device via uinput: {}", e))); },
-        Ok(a) => { a },
-    };
+    match uinput_dev {
+        Err(e) => { Err(Fatal::Msg(format!("Unable to create virtual input device via uinput: {}", e))) },
+        Ok(a) => { Ok(a) },
+    }
+}
 
-    let poll_axis = Arc::new(Mutex::new(false));
-    let (start_poll, recv_start) = std::sync::mpsc::channel::<()>();
-    let t_axis_speeds = axis_speeds.clone();
-    let t_fake_axis_speeds = fake_axis_speeds.clone();
-    let t_poll_axis = poll_axis.clone();
-    let t_poll_send = send.clone();
-
-    // thread that consumes zero energy while no axis is moving
-    let _axis_poll_thread = std::thread::spawn(move||{
-        for _ev in recv_start{
-            loop{
-                let speeds = {
-                    t_axis_speeds.lock().unwrap().clone()
-                };
-                let ax_speeds = {
-                    t_fake_axis_speeds.lock().unwrap().clone()
-                };
-                for (axis, speed) in speeds.iter() {
-                    if let Err(e) = t_poll_send.send(Ev::RawEvent(EventType::RELATIVE, *axis, *speed)){
-                        eprintln!("Error handling axis input. This is a bug! {}", e);
-                    }
+fn wrapped_main<A>(stdout: A, args: &[String]) -> Result<(),Fatal> where A: std::io::Write  + std::marker::Send + 'static  {
+
+    let mut logger = Logger::new(stdout);
+
+    macro_rules! error { ($($arg:tt)*) => { logger.log(Level::Error, &format!($($arg)*)) }; }
+    macro_rules! warn { ($($arg:tt)*) => { logger.log(Level::Warn, &format!($($arg)*)) }; }
+    macro_rules! info { ($($arg:tt)*) => { logger.log(Level::Info, &format!($($arg)*)) }; }
+    macro_rules! debug { ($($arg:tt)*) => { logger.log(Level::Debug, &format!($($arg)*)) }; }
+    macro_rules! bug { ($($arg:tt)*) => { logger.bug(&format!($($arg)*)) }; }
+    // trace is the very chatty per-event log, so skip all the formatting work when it is off
+    macro_rules! trace {
+        ($msg:literal, $($k:literal = $v:expr),*) => {
+            if logger.enabled(Level::Trace){
+                logger.log_with(Level::Trace, $msg, &[$(($k, &$v)),*]);
+            }
+        };
+    }
+
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next(){
+        match arg.as_ref(){
+            "--log-level" => {
+                let level = args_iter.next().ok_or(Fatal::Msg("--log-level requires an argument".to_string()))?;
+                logger.level = level.parse().map_err(Fatal::Msg)?;
+            },
+            "--log-format" => {
+                let format = args_iter.next().ok_or(Fatal::Msg("--log-format requires an argument".to_string()))?;
+                logger.format = format.parse().map_err(Fatal::Msg)?;
+            },
+            "--list-targets" => {
+                for target in map_config::list_targets(){
+                    writeln!(logger.out, "{}", target)?;
                 }
-                for ((neg, pos), speed) in ax_speeds.iter() {
-                    if let Err(e) = (||->Result<(),std::sync::mpsc::SendError<_>>{
-                        if speed < &0 {
-                            t_poll_send.send(Ev::RawEvent(EventType::KEY, *neg, 1))?;
-                            t_poll_send.send(Ev::RawEvent(EventType::KEY, *neg, 0))?;
-                        }
-                        else if speed > &0{
-                            t_poll_send.send(Ev::RawEvent(EventType::KEY, *pos, 1))?;
-                            t_poll_send.send(Ev::RawEvent(EventType::KEY, *pos, 0))?;
+                return Ok(());
+            },
+            "--list-devices" => {
+                list_devices(&mut logger.out).map_err(|e| Fatal::Msg(format!("Unable to list joypads: {}", e)))?;
+                return Ok(());
+            },
+            _ => {
+                warn!("ignored argument: {}", arg);
+            },
+        }
+    }
+
+    let mut pads: HashMap<u32,ConnectedPad> = HashMap::new();
+    let mut next_battery_check = Instant::now() + battery_poll_interval;
+
+    let (expanded_mappings, hooks, mut settings, keymap) = load_config(&mut logger)?;
+
+    let mut event_loop = EventLoop::new()?;
+    let mut inotify = match hotplug_watch().and_then(|i|{ event_loop.add_hotplug(&i)?; Ok(i) }){
+        Ok(i) => Some(i),
+        Err(e) => {
+            warn!("failed to start inotify, hotplugging is unavailable: {}", e);
+            None
+        },
+    };
+    let mut signals = match Signals::new().and_then(|s|{ event_loop.add_signals(&s.pipe)?; Ok(s) }){
+        Ok(s) => Some(s),
+        Err(e) => {
+            warn!("Unable to handle signals, held keys will not be released on shutdown: {}", e);
+            None
+        },
+    };
+    // the events that the main loop handles next
+    let mut evs: VecDeque<Ev> = VecDeque::new();
+    // enumerate already connected joypads
+    match std::fs::read_dir("/dev/input"){
+        Err(_) => return Err(Fatal::Msg("Unable to read from /dev/input".to_string())),
+        Ok(d) => {
+            for j in d.flatten(){
+                let n = j.path();
+                let nl = n.to_string_lossy();
+                if let Some(num) = nl.strip_prefix("/dev/input/js"){
+                    let id: Result<u32, _> = num.parse();
+                    match id {
+                        Err(e) => {
+                            bug!("unable to parse joypad number while enumerating joypad devices: {}", e);
+                        },
+                        Ok(id) => {
+                            evs.push_back(Ev::Connect(n.into(), id));
                         }
-                        Ok(())
-                    })(){
-                        eprintln!("Error handling axis input. This is a bug! {}", e);
                     }
                 }
-                std::thread::sleep(Duration::from_millis(20));
-                if !(*t_poll_axis.lock().unwrap()){
-                    break;
-                }
             }
         }
-    });
+    }
+
+    let mut uinput_dev = build_uinput_device(&expanded_mappings, &hooks, &keymap)?;
+
+    // All of the mapping happens in here, the rest of this function does the I/O that it asks for
+    let mut engine = Engine::new(expanded_mappings, hooks, keymap);
+    engine.trace = logger.enabled(Level::Trace);
+
+    // Output is collected and sent all at once when there is no more input waiting, see batch.rs
+    let mut batch = Batch::new();
 
-    macro_rules! set_speed {
-        ($code:expr, $delta:expr) => {
+    macro_rules! emit {
+        ($events:expr) => {
             {
-                axis_speeds.lock().unwrap().insert($code.0, $delta);
+                let events: &[InputEvent] = $events;
+                for e in events{
+                    batch.push(*e);
+                }
             }
-            if !*poll_axis.lock().unwrap() {
-                *poll_axis.lock().unwrap() = true;
-                if let Err(e) = start_poll.send(()){
-                    println!("Internal error: axis event input sender failed. This is a bug! {}", e);
+        }
+    }
+
+    macro_rules! flush {
+        () => {
+            if !batch.is_empty(){
+                let events = batch.take();
+                for e in events.iter(){
+                    trace!("uinput out", "type" = format!("{:?}", e.event_type()), "code" = e.code(), "value" = e.value());
+                }
+                // emit ends with a SYN_REPORT of its own
+                if let Err(e) = uinput_dev.emit(&events){
+                    error!("Failed to send event: {}", e);
                 }
             }
         }
     }
 
-    let mut enabled = true;
+    let mut launcher: Launcher<(u32, JDCId)> = Launcher::new();
 
-    loop{
-        match recv.recv(){
-            Ok(msg) => match msg {
-                Ev::Connect(s, id) => {
-                    listening = false;
-                    if !pads.contains_key(&id){
-                        let t = pad_thread(send.clone(), &Path::new(&s));
-                        match t{
-                            Ok((name, file, join)) => {
-                                let mapping = expanded_mappings.get(&map_config::jpname_to_filename(&name)).cloned();
-                                if mapping.is_none(){
-                                    println!("Warning: There is no mapping file for the joypad: {}", name);
-                                    println!("No inputs will be handled for this joypad.");
-                                }
-                                else{
-                                    let mapping = mapping.unwrap();
-                                    pads.insert(id,ConnectedPad{
-                                        file,
-                                        mapping,
-                                        join,
-                                    });
-                                }
+    // Launches a program, filling in the placeholders with the device name, the joypad input, and the player number
+    macro_rules! launch {
+        ($target:expr, $owner:expr, $device:expr, $input:expr, $player:expr) => {
+            {
+                let target: &map_config::LaunchTarget = $target;
+                let placeholder = |p: Placeholder| match p{
+                    Placeholder::Device => $device.to_string(),
+                    Placeholder::Input => $input.to_string(),
+                    // joy2uinput doesn't have profiles yet, so this is always "default" (as the README and man page say)
+                    Placeholder::Profile => "default".to_string(),
+                    Placeholder::Player => $player.to_string(),
+                };
+                match launcher.launch(target, $owner, placeholder){
+                    Ok(Launch::Started(pid)) => info!("Launched {:?} (pid {})", target.args, pid),
+                    Ok(Launch::AlreadyRunning(pid)) => {
+                        if target.single == Some(map_config::SingleInstance::Focus){
+                            match launcher.focus(pid){
+                                Ok(()) => info!("{:?} is already running (pid {}), focusing it", target.args, pid),
+                                Err(e) => warn!("Failed to focus {:?} (pid {}), is xdotool installed? {}", target.args, pid, e),
                             }
-                            Err(e) => {println!("Error connecting to joypad {}, will retry if device file attributes change...", e);}
                         }
+                        else{
+                            info!("{:?} is already running (pid {}), not launching it again", target.args, pid);
+                        }
+                    },
+                    Err(e) => error!("Failed to launch program: {:?}: {}", target.args, e),
+                }
+            }
+        }
+    }
+
+    // The evdev device is only opened the first time the pad is asked to rumble
+    macro_rules! rumble {
+        ($pad:expr, $strength:expr, $duration:expr) => {
+            {
+                let pad: &mut ConnectedPad = $pad;
+                if pad.rumble.is_none(){
+                    match pad.event_node.take().map(|node| Rumbler::open(&node)){
+                        None => debug!("Joypad {} can't rumble", pad.name),
+                        // taking event_node means this is only reported once
+                        Some(Err(e)) => warn!("Joypad {} can't rumble: {}", pad.name, e),
+                        Some(Ok(r)) => pad.rumble = Some(r),
                     }
-                    _wait_thread = Some(listen_after(send.clone(), 200));
-                },
-                Ev::Disconnect(id) => {
-                    let pad = pads.remove(&id);
-                    if pad.is_none(){
-                        continue;
+                }
+                if let Some(r) = pad.rumble.as_mut(){
+                    if let Err(e) = r.rumble($strength, $duration){
+                        warn!("Failed to rumble joypad {}: {}", pad.name, e);
                     }
-                    let _ = pad.unwrap().join.join();
-                },
-                Ev::Joy(dev, ev) => {
-                    if listening {
-                        let pad = pads.get(&dev);
-                        if pad.is_none(){
-                            continue;
+                }
+            }
+        }
+    }
+
+    // Does what the engine asked for
+    macro_rules! run {
+        ($actions:expr) => {
+            for action in $actions{
+                match action{
+                    Action::KeyDown(k) => emit!(&[InputEvent::new(EventType::KEY, k.code(), 1)]),
+                    Action::KeyUp(k) => emit!(&[InputEvent::new(EventType::KEY, k.code(), 0)]),
+                    Action::Rel(axis, value) => emit!(&[InputEvent::new(EventType::RELATIVE, axis.0, value)]),
+                    Action::Abs(axis, value) => emit!(&[InputEvent::new(EventType::ABSOLUTE, axis.0, value)]),
+                    Action::Launch{target, owner, device, input, player} => launch!(&target, owner, device, input, player),
+                    Action::StopLaunched(owner) => {
+                        for pid in launcher.release(|o| *o == owner){
+                            info!("Stopping launched program (pid {})", pid);
                         }
-                        let pad = pad.unwrap();
-                        match ev.type_() {
-                            joydev::EventType::Button | joydev::EventType::ButtonSynthetic => {
-                                if let Some((_, target)) = pad.mapping.get(&JDCId::Button(ev.number())){
-                                    match target {
-                                        Target::Key(k) => {
-                                            if enabled{
-                                                if let Err(e) = uinput_dev.emit(&[InputEvent::new(EventType::KEY, k.uinput_key().code(), ev.value().into())]){
-                                                    println!("Error sending event: {}", e);
-                                                }
-                                            }
-                                        },
-                                        Target::Axis(a) => {
-                                            if enabled{
-                                                let val = ev.value();
-                                                let speed = val as f32;
-                                                let mult = a.multiplier();
-                                                let delta = (speed * mult).round() as i32;
-                                                if let Some(code) = a.uinput_axis(){
-                                                    set_speed!(code, delta);
-                                                } 
-                                            }
-                                        },
-                                        Target::ToggleEnabled() => {
-                                            if ev.value() != 0{
-                                                enabled = !enabled;
-                                            }
-                                        },
-                                        Target::Launch(args) => {
-                                            if ev.value() != 0{
-                                                launch(&args);
-                                            }
-                                        },
-                                    }
+                    },
+                    Action::StopPad(id) => {
+                        for pid in launcher.release(|(pad, _)| *pad == id){
+                            info!("Stopping launched program (pid {})", pid);
+                        }
+                    },
+                    Action::KillLaunched() => {
+                        for pid in launcher.kill_all(){
+                            info!("Stopping launched program (pid {})", pid);
+                        }
+                    },
+                    Action::Rumble(id, strength, duration) => {
+                        if let Some(pad) = pads.get_mut(&id){
+                            rumble!(pad, strength, duration);
+                        }
+                    },
+                    Action::Log(level, msg) => logger.log(level, &msg),
+                    Action::Bug(msg) => bug!("{}", msg),
+                    Action::Trace(msg, fields) => {
+                        let fields: Vec<(&str, &dyn std::fmt::Display)> = fields.iter().map(|(k, v)| (*k, v as &dyn std::fmt::Display)).collect();
+                        logger.log_with(Level::Trace, msg, &fields);
+                    },
+                }
+            }
+        }
+    }
+
+    // Logs the battery level when it changes, and runs the battery_low hooks when it gets low
+    macro_rules! check_battery {
+        ($id:expr) => {
+            {
+                let id: u32 = $id;
+                let mut became_low = false;
+                if let Some(pad) = pads.get_mut(&id){
+                    if let Some(ps) = &pad.power_supply{
+                        match Battery::read(ps){
+                            Err(e) => debug!("Unable to read the battery of joypad {}: {}", pad.name, e),
+                            Ok(b) => {
+                                if pad.battery.as_ref() != Some(&b){
+                                    info!("Joypad {} battery: {}", pad.name, b);
                                 }
-                            },
-                            joydev::EventType::Axis | joydev::EventType::AxisSynthetic => {
-                                match pad.mapping.get(&JDCId::Axis(ev.number())){
-                                    Some((JDEv::Axis(_n,min,max), target)) => {
-                                        match target {
-                                            Target::Axis(a) => {
-                                                if enabled{
-                                                    let val = ev.value();
-                                                    let speed = if val < 0 {(val as f32) / (-*min as f32)} else {(val as f32) / (*max as f32)};
-                                                    let mult = a.multiplier();
-                                                    let delta = (speed * mult).round() as i32;
-                                                    if let Some(code) = a.uinput_axis(){
-                                                        set_speed!(code, delta);
-                                                    }
-                                                    else{
-                                                        let keys = a.uinput_keys();
-                                                        let neg = keys[0].code();
-                                                        let pos = keys[1].code();
-                                                        {
-                                                            fake_axis_speeds.lock().unwrap().insert((neg,pos), delta);
-                                                        }
-                                                        if !*poll_axis.lock().unwrap() {
-                                                            *poll_axis.lock().unwrap() = true;
-                                                            if let Err(e) = start_poll.send(()){
-                                                                eprintln!("Internal error: axis event input sender failed. This is a bug! {}", e);
-                                                            }
-                                                        }
-
-                                                    }
-                                                }
-                                            }
-                                            Target::Key(a) => {
-                                                println!("Warning: This axis is mapped to a button? Not sure what that means. Target event dropped: {:?}", a);
-                                            },
-                                            Target::ToggleEnabled() => {
-                                                println!("Warning: This axis is mapped to toggle enabled? Not sure what that means.");
-                                            },
-                                            Target::Launch(_) => {
-                                                println!("Warning: This axis is mapped to launch a program? Not sure what that means.");
-                                            },
-                                        }
-                                        
-                                    },
-                                    _ => {
-                                        match pad.mapping.get(&JDCId::AxisAsButton(ev.number(), ev.value())) {
-                                            Some((_, target)) => {
-                                                match target {
-                                                    Target::Key(k) => {
-                                                        let code = k.uinput_key().code();
-                                                        // Can't group these because that doesn't work when a mouse press and mouse release event are both sent in one group (I don't know why)
-                                                        if let Err(e) = uinput_dev.emit(&[ InputEvent::new(EventType::KEY, code, 1), ]){ println!("Error sending event: {}", e); }
-                                                        if let Err(e) = uinput_dev.emit(&[ InputEvent::new(EventType::KEY, code, 0), ]){ println!("Error sending event: {}", e); }
-                                                    },
-                                                    Target::Axis(a) => {
-                                                        println!("Warning: Unable to map this button to its axis target because the device models the button as an axis. Target event dropped: {:?}\nFor an explanation of why this happens, see the github issue here: https://github.com/lexbailey/joy2uinput/issues/2", a);
-                                                    },
-                                                    Target::ToggleEnabled() => {
-                                                        enabled = !enabled;
-                                                    }
-                                                    Target::Launch(args) => {
-                                                        launch(&args);
-                                                    }
-                                                }
-                                            },
-                                            _ => {},
-                                        }
-                                    },
+                                let low = b.is_low(settings.battery_low);
+                                if low && !pad.battery_low{
+                                    warn!("Joypad {} battery is low: {}", pad.name, b);
+                                    became_low = true;
                                 }
+                                pad.battery_low = low;
+                                pad.battery = Some(b);
                             },
                         }
                     }
-                },
-                Ev::RawEvent(evty, code, value) => {
-                    if let Err(e) = uinput_dev.emit(&[
-                        InputEvent::new(evty, code, value),
-                    ]){
-                        println!("Error sending event: {}", e);
+                }
+                if became_low{
+                    run!(engine.hook(id, Hook::BatteryLow()));
+                }
+            }
+        }
+    }
+
+    loop{
+        let msg = match evs.pop_front(){
+            Some(msg) => msg,
+            None => {
+                // nothing left to do, so send what this lot of input caused and wait for something to happen
+                flush!();
+                event_loop.set_moving(engine.is_moving())?;
+                let wakes = event_loop.wait(Some(next_battery_check.saturating_duration_since(Instant::now())))?;
+                let now = Instant::now();
+                if next_battery_check <= now{
+                    next_battery_check = now + battery_poll_interval;
+                    evs.push_back(Ev::Battery());
+                }
+                for wake in wakes{
+                    match wake{
+                        Wake::Pad(id) => {
+                            if let Some(pad) = pads.get(&id){
+                                let (events, gone) = read_pad(&pad.file);
+                                evs.extend(events.into_iter().map(|ev| Ev::Joy(id, ev)));
+                                if gone{
+                                    evs.push_back(Ev::Disconnect(id));
+                                }
+                            }
+                        },
+                        Wake::Hotplug() => {
+                            for ev in inotify.as_mut().map(hotplug_events).unwrap_or_default(){
+                                match ev{
+                                    Ok(ev) => evs.push_back(ev),
+                                    Err(e) => bug!("{}", e),
+                                }
+                            }
+                        },
+                        Wake::Signal() => {
+                            if let Some(signals) = signals.as_mut(){
+                                evs.extend(signals.events());
+                            }
+                        },
+                        Wake::Motion() => evs.push_back(Ev::Motion()),
                     }
-                    if axis_speeds.lock().unwrap().values().all(|&a|a==0){
-                        *poll_axis.lock().unwrap() = false;
+                }
+                continue;
+            },
+        };
+        match msg {
+            Ev::Connect(s, id) => {
+                if let std::collections::hash_map::Entry::Vacant(slot) = pads.entry(id){
+                    let t = open_pad(Path::new(&s)).and_then(|(name, file)|{ event_loop.add_pad(id, &file)?; Ok((name, file)) });
+                    match t{
+                        Ok((name, file)) => {
+                            info!("Device connected: {}", name);
+                            let event_node = rumble::event_node(&sysfs_root(), Path::new(&s));
+                            let power_supply = battery::power_supply(&sysfs_root(), Path::new(&s));
+                            slot.insert(ConnectedPad{
+                                file,
+                                name: name.clone(),
+                                event_node,
+                                rumble: None,
+                                power_supply,
+                                battery: None,
+                                battery_low: false,
+                            });
+                            run!(engine.connect(id, name, Instant::now()));
+                        }
+                        Err(e) => {error!("Unable to connect to joypad {}, will retry if device file attributes change...", e);}
                     }
+                    check_battery!(id);
                 }
-                Ev::Listen() => {
-                    listening = true;
+            },
+            Ev::Disconnect(id) => {
+                run!(engine.disconnect(id));
+                if let Some(pad) = pads.remove(&id){
+                    let _ = event_loop.remove_pad(&pad.file);
+                    info!("Device disconnected: {}", pad.name);
                 }
-                Ev::Println(s) => {
-                    println!("{}", s);
+            },
+            Ev::Joy(dev, ev) => {
+                let ev = JoyEv::from(ev);
+                let (kind, number, value) = match ev{
+                    JoyEv::Button(n, v) => ("button", n, v),
+                    JoyEv::Axis(n, v) => ("axis", n, v),
+                };
+                trace!("joydev in", "pad" = dev, "kind" = kind, "number" = number, "value" = value);
+                run!(engine.input(dev, ev, Instant::now()));
+            },
+            Ev::Motion() => {
+                run!(engine.motion());
+            }
+            Ev::Reload() => {
+                info!("Reloading config");
+                run!(engine.release_all());
+                // the releases must go to the old device before it is replaced
+                flush!();
+                match load_config(&mut logger).and_then(|(m, h, s, k)|{ let dev = build_uinput_device(&m, &h, &k)?; Ok((m, h, s, k, dev)) }){
+                    Err(e) => {
+                        error!("Failed to reload config, keeping the old one: {:?}", e);
+                    },
+                    Ok((new_mappings, new_hooks, new_settings, new_keymap, new_dev)) => {
+                        settings = new_settings;
+                        uinput_dev = new_dev;
+                        run!(engine.set_config(new_mappings, new_hooks, new_keymap));
+                        info!("Config reloaded");
+                    },
                 }
             }
-            _ => {break;}
+            Ev::Battery() => {
+                let ids: Vec<u32> = pads.keys().copied().collect();
+                for id in ids{
+                    check_battery!(id);
+                }
+            }
+            Ev::Reap() => {
+                for (args, status) in launcher.reap(){
+                    debug!("Launched program {:?} exited: {}", args, status);
+                }
+            }
+            Ev::Shutdown() => {
+                info!("Shutting down");
+                run!(engine.release_all());
+                flush!();
+                break;
+            }
         }
     }
     Ok(())
@@ -679,6 +810,7 @@ fn main() -> Result<(), Fatal>{
     wrapped_main(std::io::stdout(), &args)
 }
 
+#[cfg(test)]
 mod test_utils;
 
 #[cfg(test)]
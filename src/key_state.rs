@@ -0,0 +1,102 @@
+/*
+
+What does this file do?
+
+    Keeps count of how many joypad inputs are holding down each key (or mouse button)
+    on the virtual device.
+    Several inputs, possibly on several joypads, can be mapped to the same key. The
+    virtual device should only see the key go down when the first of them is pressed,
+    and only see it go up when the last of them is released. Otherwise releasing one
+    input would let go of a key that another input is still holding, which also cuts
+    off the key's autorepeat.
+
+*/
+
+use std::collections::HashMap;
+
+#[derive(Debug,Default)]
+pub struct KeyState{
+    counts: HashMap<evdev::Key, u32>,
+}
+
+impl KeyState{
+    pub fn new() -> Self {
+        KeyState::default()
+    }
+
+    // returns true if the key just went down, and so a key down event should be sent
+    pub fn press(&mut self, key: evdev::Key) -> bool {
+        let n = self.counts.entry(key).or_insert(0);
+        *n += 1;
+        *n == 1
+    }
+
+    // returns true if the key just went up, and so a key up event should be sent
+    pub fn release(&mut self, key: evdev::Key) -> bool {
+        match self.counts.get_mut(&key){
+            None => false,
+            Some(n) => {
+                *n -= 1;
+                if *n == 0{
+                    self.counts.remove(&key);
+                    true
+                }
+                else{
+                    false
+                }
+            },
+        }
+    }
+
+    // For a momentary press and release (like the ones generated by axis_as_button).
+    // Returns true if the press and release should be sent. If something else is already
+    // holding the key down, then sending them would let go of it, so they are not sent.
+    pub fn tap(&self, key: evdev::Key) -> bool {
+        !self.is_down(key)
+    }
+
+    pub fn is_down(&self, key: evdev::Key) -> bool {
+        self.counts.contains_key(&key)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::key_state::KeyState;
+    use evdev::Key;
+
+    #[test]
+    fn test_shared_key(){
+        let mut s = KeyState::new();
+        assert!(s.press(Key::KEY_ENTER));
+        assert!(!s.press(Key::KEY_ENTER));
+        assert!(s.is_down(Key::KEY_ENTER));
+        assert!(!s.release(Key::KEY_ENTER));
+        assert!(s.is_down(Key::KEY_ENTER));
+        assert!(s.release(Key::KEY_ENTER));
+        assert!(!s.is_down(Key::KEY_ENTER));
+        // releasing a key that is not down does nothing
+        assert!(!s.release(Key::KEY_ENTER));
+        assert!(s.press(Key::KEY_ENTER));
+    }
+
+    #[test]
+    fn test_independent_keys(){
+        let mut s = KeyState::new();
+        assert!(s.press(Key::KEY_A));
+        assert!(s.press(Key::BTN_LEFT));
+        assert!(s.release(Key::KEY_A));
+        assert!(!s.is_down(Key::KEY_A));
+        assert!(s.is_down(Key::BTN_LEFT));
+    }
+
+    #[test]
+    fn test_tap(){
+        let mut s = KeyState::new();
+        assert!(s.tap(Key::KEY_PAGEUP));
+        s.press(Key::KEY_PAGEUP);
+        assert!(!s.tap(Key::KEY_PAGEUP));
+        s.release(Key::KEY_PAGEUP);
+        assert!(s.tap(Key::KEY_PAGEUP));
+    }
+}
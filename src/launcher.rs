@@ -0,0 +1,239 @@
+/*
+
+What does this file do?
+
+    Starts the programs for launch targets, and keeps track of them while they run.
+    Programs that have exited are reaped (so they don't hang around as zombies),
+    and programs that are still running can be found again, so that a launch target
+    can refuse to start a second copy, or can stop the program when its button is
+    released.
+
+*/
+
+use crate::map_config::{Arg, LaunchTarget, Placeholder};
+use std::fs::OpenOptions;
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+#[derive(Debug)]
+struct Launched<O>{
+    child: Child,
+    args: Vec<String>,
+    // the input that launched this program, if it should be stopped when that input is released
+    kill_on_release: Option<O>,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum Launch{
+    Started(u32),
+    // single instance launch where the program was already running, with its pid
+    AlreadyRunning(u32),
+}
+
+#[derive(Debug)]
+pub struct Launcher<O>{
+    running: Vec<Launched<O>>,
+}
+
+impl<O> Default for Launcher<O>{
+    fn default() -> Self {
+        Launcher{running: Vec::new()}
+    }
+}
+
+impl<O> Launcher<O> where O: PartialEq + Copy {
+    pub fn new() -> Self {
+        Launcher::default()
+    }
+
+    // owner is the input that launched it, if any. placeholder gives the value to fill in for each %placeholder%
+    pub fn launch<P>(&mut self, target: &LaunchTarget, owner: Option<O>, placeholder: P) -> std::io::Result<Launch> where P: Fn(Placeholder) -> String {
+        let expand = |a: &Arg| a.expand(|name| std::env::var(name).ok(), &placeholder);
+        let args: Vec<String> = if target.shell{
+            // the shell will split the command up, so the filled in values must be quoted to stay in one piece
+            let cmd = target.args.iter().map(|a| a.expand(|_| None, |p| shell_quote(&placeholder(p)))).collect::<Vec<_>>().join(" ");
+            vec!["sh".to_string(), "-c".to_string(), cmd]
+        }
+        else{
+            target.args.iter().map(expand).collect()
+        };
+        if target.single.is_some(){
+            if let Some(l) = self.running.iter().find(|l| l.args == args){
+                return Ok(Launch::AlreadyRunning(l.child.id()));
+            }
+        }
+        let (stdout, stderr) = match &target.log{
+            None => (Stdio::null(), Stdio::null()),
+            Some(path) => {
+                let f = OpenOptions::new().create(true).append(true).open(expand(path))?;
+                (Stdio::from(f.try_clone()?), Stdio::from(f))
+            },
+        };
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]).stdout(stdout).stderr(stderr);
+        if let Some(cwd) = &target.cwd{
+            command.current_dir(expand(cwd));
+        }
+        for (k, v) in target.env.iter(){
+            command.env(k, expand(v));
+        }
+        let child = command.spawn()?;
+        let pid = child.id();
+        self.running.push(Launched{
+            child,
+            args,
+            kill_on_release: if target.kill_on_release {owner} else {None},
+        });
+        Ok(Launch::Started(pid))
+    }
+
+    // Brings the window of an already running program to the front. This needs xdotool, so only works on X
+    pub fn focus(&mut self, pid: u32) -> std::io::Result<()> {
+        let child = Command::new("xdotool")
+            .args(["search", "--pid", &pid.to_string(), "windowactivate"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        // tracked like anything else, so that it gets reaped
+        self.running.push(Launched{child, args: vec!["xdotool".to_string()], kill_on_release: None});
+        Ok(())
+    }
+
+    // Collects the exit status of every program that has finished
+    pub fn reap(&mut self) -> Vec<(Vec<String>, ExitStatus)> {
+        let mut done = Vec::new();
+        self.running.retain_mut(|l|{
+            match l.child.try_wait(){
+                Ok(Some(status)) => {
+                    done.push((l.args.clone(), status));
+                    false
+                },
+                Ok(None) => true,
+                // can't wait for it, so there's no point tracking it
+                Err(_) => false,
+            }
+        });
+        done
+    }
+
+    // Stops the kill_on_release programs that were started by inputs that match, returns their pids
+    pub fn release<F>(&mut self, matches: F) -> Vec<u32> where F: Fn(&O) -> bool {
+        let mut killed = Vec::new();
+        for l in self.running.iter_mut(){
+            if let Some(owner) = &l.kill_on_release{
+                if matches(owner){
+                    terminate(&l.child);
+                    killed.push(l.child.id());
+                    // it will be reaped once it has actually stopped
+                    l.kill_on_release = None;
+                }
+            }
+        }
+        killed
+    }
+
+    // Stops everything that has been launched, returns the pids
+    pub fn kill_all(&mut self) -> Vec<u32> {
+        for l in self.running.iter_mut(){
+            terminate(&l.child);
+            l.kill_on_release = None;
+        }
+        self.running.iter().map(|l| l.child.id()).collect()
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// SIGTERM rather than Child::kill's SIGKILL, so the program has a chance to clean up
+fn terminate(child: &Child){
+    let _ = nix::sys::signal::kill(nix::unistd::Pid::from_raw(child.id() as i32), nix::sys::signal::Signal::SIGTERM);
+}
+
+#[cfg(test)]
+mod test{
+    use crate::launcher::{Launcher, Launch};
+    use crate::map_config::{Arg, LaunchTarget, SingleInstance, Placeholder};
+    use std::time::Duration;
+    use tempdir::TempDir;
+
+    fn target(args: &[&str]) -> LaunchTarget {
+        LaunchTarget{args: args.iter().map(|a| Arg::from(*a)).collect(), ..Default::default()}
+    }
+
+    fn reap_all(l: &mut Launcher<u8>, n: usize) -> usize {
+        let mut reaped = 0;
+        for _ in 0..200{
+            reaped += l.reap().len();
+            if reaped >= n{
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        reaped
+    }
+
+    #[test]
+    fn test_reap(){
+        let mut l: Launcher<u8> = Launcher::new();
+        assert!(matches!(l.launch(&target(&["true"]), None, |_| String::new()), Ok(Launch::Started(_))));
+        assert!(matches!(l.launch(&target(&["true"]), None, |_| String::new()), Ok(Launch::Started(_))));
+        assert_eq!(reap_all(&mut l, 2), 2);
+        assert!(l.launch(&target(&["/nonexistent/program"]), None, |_| String::new()).is_err());
+    }
+
+    #[test]
+    fn test_single_instance_and_kill(){
+        let mut l = Launcher::new();
+        let mut t = target(&["sleep", "10"]);
+        t.single = Some(SingleInstance::Ignore);
+        t.kill_on_release = true;
+        let pid = match l.launch(&t, Some(1), |_| String::new()){
+            Ok(Launch::Started(pid)) => pid,
+            r => panic!("{:?}", r),
+        };
+        assert_eq!(l.launch(&t, Some(1), |_| String::new()).unwrap(), Launch::AlreadyRunning(pid));
+        // another input's release doesn't stop it
+        assert!(l.release(|o| *o == 2).is_empty());
+        assert_eq!(l.release(|o| *o == 1), vec![pid]);
+        assert_eq!(reap_all(&mut l, 1), 1);
+        // and once it's gone, it can be started again
+        assert!(matches!(l.launch(&t, Some(1), |_| String::new()), Ok(Launch::Started(_))));
+        assert_eq!(l.kill_all().len(), 1);
+        assert_eq!(reap_all(&mut l, 1), 1);
+    }
+
+    #[test]
+    fn test_log(){
+        let dir = TempDir::new("launch").unwrap();
+        let log = dir.path().join("out.log");
+        let mut l = Launcher::new();
+        let mut t = target(&["sh", "-c", "echo out; echo err >&2"]);
+        t.log = Some(Arg::from(log.to_string_lossy().as_ref()));
+        l.launch(&t, None, |_| String::new()).unwrap();
+        assert_eq!(reap_all(&mut l, 1), 1);
+        l.launch(&t, None, |_| String::new()).unwrap();
+        assert_eq!(reap_all(&mut l, 1), 1);
+        let text = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(text.lines().filter(|l| *l == "out").count(), 2);
+        assert_eq!(text.lines().filter(|l| *l == "err").count(), 2);
+    }
+
+    #[test]
+    fn test_cwd_env_and_placeholders(){
+        let dir = TempDir::new("launch").unwrap();
+        let log = dir.path().join("out.log");
+        let mut l = Launcher::new();
+        let mut t: LaunchTarget = "(shell, cwd=/, env=J2U_TEST=\"a b\") echo $J2U_TEST %device% %player%; pwd".parse().unwrap();
+        t.log = Some(Arg::from(log.to_string_lossy().as_ref()));
+        let placeholder = |p| match p{
+            Placeholder::Device => "Some Pad; echo oops".to_string(),
+            Placeholder::Player => "2".to_string(),
+            _ => String::new(),
+        };
+        l.launch(&t, None, placeholder).unwrap();
+        assert_eq!(reap_all(&mut l, 1), 1);
+        let text = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(text, "a b Some Pad; echo oops 2\n/\n");
+    }
+}
@@ -0,0 +1,48 @@
+/*
+
+What does this file do?
+
+    The levels that log messages can have. They are in the library so that the
+    parts of it that report things (such as the mapping engine) can say how
+    important each message is, without needing the logger that joy2uinput and
+    joy2u-mapgen write them out with.
+
+*/
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Level{
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Display for Level{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(match self{
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        })
+    }
+}
+
+impl FromStr for Level{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        match s.trim().to_lowercase().as_ref(){
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("No such log level: {}. Expected one of error, warn, info, debug, trace", s)),
+        }
+    }
+}
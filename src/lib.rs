@@ -0,0 +1,24 @@
+/*
+
+What does this file do?
+
+    This is the joy2uinput library. It has the config file format (the types, the
+    parsers, and the writers) and the rules for where config files are found, so
+    that other programs can read and write joy2uinput config without their own
+    copy of the parser. It also has the mapping engine, which works out what the
+    config says should happen for each bit of joypad input, so that anything that
+    needs to know that does it the same way joy2uinput does. joy2uinput and
+    joy2u-mapgen both use it.
+
+*/
+#![allow(non_upper_case_globals)]
+pub mod map_config;
+pub mod config_file;
+pub mod level;
+pub mod key_state;
+pub mod xkb;
+pub mod engine;
+
+pub use map_config::{JDEv, JoyInput, Target, Mapping, TargetMapping, ErrorKind, jpname_to_filename};
+pub use level::Level;
+pub use config_file::{Config, ParseError, find_user_conf_dir, user_conf_dir, find_mapping_file, find_config_file, parse_mapping_file, parse_config_file, parse_config_lines};
@@ -0,0 +1,126 @@
+/*
+
+What does this file do?
+
+    Keeps track of the buttons and axes of a joypad for joy2u-mapgen --test, and
+    turns them into a table of what each one is according to the joypad's mapping
+    file. The mapping engine decides what each input is, so the table shows what
+    joy2uinput would see: axes from -1 to 1 (or 0 to 1 for triggers), and a button
+    that is mapped twice as both of the inputs. Buttons and axes that the mapping
+    file doesn't have anything for are listed too, so that it's obvious when
+    something is missing.
+
+*/
+
+use joy2uinput::engine::{resolve, JDCId, JoyEv, PadMapping};
+use joy2uinput::map_config::{JDEv, JoyInput};
+use std::collections::{BTreeMap, HashMap};
+
+pub struct Live{
+    mapping: PadMapping,
+    // one for each input of each button or axis in the mapping
+    rows: Vec<(JDCId, JDEv, JoyInput)>,
+    // where each input is, as the engine sees it, once it has been touched
+    positions: HashMap<(JDCId, JoyInput), f32>,
+    buttons: BTreeMap<u8, i16>,
+    axes: BTreeMap<u8, i16>,
+    // where each axis was when it was first seen, so that an unmapped axis only shows up once it is moved
+    rest: HashMap<u8, i16>,
+}
+
+impl Live{
+    pub fn new(mapping: PadMapping) -> Self {
+        let mut ids: Vec<&JDCId> = mapping.keys().collect();
+        ids.sort();
+        let rows = ids.into_iter().flat_map(|id| {
+            let (from, inputs) = &mapping[id];
+            inputs.iter().map(move |(input, _)| (*id, from.clone(), input.clone()))
+        }).collect();
+        Live{mapping, rows, positions: HashMap::new(), buttons: BTreeMap::new(), axes: BTreeMap::new(), rest: HashMap::new()}
+    }
+
+    pub fn input(&mut self, ev: JoyEv){
+        match ev{
+            JoyEv::Button(n, v) => { self.buttons.insert(n, v); },
+            JoyEv::Axis(n, v) => {
+                self.rest.entry(n).or_insert(v);
+                self.axes.insert(n, v);
+            },
+        }
+        for r in resolve(&self.mapping, ev){
+            self.positions.insert((r.id, r.input.clone()), r.position);
+        }
+    }
+
+    fn state(&self, id: JDCId, input: &JoyInput) -> String {
+        match (id, self.positions.get(&(id, input.clone()))){
+            (_, None) => String::new(),
+            (JDCId::Axis(_), Some(p)) => format!("{:+.2}", p),
+            (_, Some(p)) => if *p != 0.0 {"pressed".to_string()} else {String::new()},
+        }
+    }
+
+    // One row for each input in the mapping, then one for each button or axis that isn't in it
+    pub fn table(&self) -> Vec<String> {
+        let mut rows: Vec<String> = self.rows.iter().map(|(id, from, input)| format!("{:<16} {:<36} {}", input.to_string(), from.to_string(), self.state(*id, input))).collect();
+        for (n, v) in &self.buttons{
+            if resolve(&self.mapping, JoyEv::Button(*n, *v)).is_empty(){
+                rows.push(format!("{:<16} {:<36} {}", "NOT MAPPED", JDEv::Button(*n).to_string(), if *v == 1 {"pressed"} else {"released"}));
+            }
+        }
+        for (n, v) in &self.axes{
+            let covered = resolve(&self.mapping, JoyEv::Axis(*n, *v)).iter().any(|r| matches!(r.id, JDCId::Axis(_)) || r.position != 0.0);
+            if !covered && self.rest.get(n) != Some(v){
+                rows.push(format!("{:<16} {:<36} {}", "NOT MAPPED", format!("axis({})", n), v));
+            }
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::live::Live;
+    use joy2uinput::config_file::parse_mapping_file;
+    use joy2uinput::engine::{expand_mapping, JoyEv};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_live(){
+        let mut l = Live::new(expand_mapping(&parse_mapping_file("axis(0,-32767,32767) = leftx
+button(0) = a
+axis_as_button(7,-32767) = up
+axis(2,-32767,32767,-32767,0) = leftz
+").unwrap(), &HashMap::new()));
+        let states = |l: &Live| l.table().iter().map(|r| r.split_whitespace().skip(2).collect::<Vec<_>>().join(" ")).collect::<Vec<_>>();
+        // nothing has happened yet
+        assert_eq!(states(&l), vec!["", "", "", ""]);
+
+        l.input(JoyEv::Button(0, 1));
+        l.input(JoyEv::Axis(7, 0));
+        l.input(JoyEv::Axis(0, -16384));
+        l.input(JoyEv::Axis(2, 0));
+        assert_eq!(states(&l), vec!["pressed", "", "-0.50", "+0.50"]);
+        assert!(l.table()[0].starts_with("a "));
+        assert!(l.table()[0].contains("button(0)"));
+
+        l.input(JoyEv::Axis(7, -32767));
+        l.input(JoyEv::Button(0, 0));
+        assert_eq!(states(&l), vec!["", "pressed", "-0.50", "+0.50"]);
+
+        // things that aren't in the mapping file
+        l.input(JoyEv::Button(5, 1));
+        l.input(JoyEv::Axis(4, 0));
+        assert_eq!(l.table().len(), 5);
+        assert_eq!(l.table()[4].split_whitespace().collect::<Vec<_>>(), vec!["NOT", "MAPPED", "button(5)", "pressed"]);
+        l.input(JoyEv::Axis(4, 12000));
+        l.input(JoyEv::Axis(7, 32767));
+        assert_eq!(l.table()[5..].iter().map(|r| r.split_whitespace().collect::<Vec<_>>().join(" ")).collect::<Vec<_>>(), vec!["NOT MAPPED axis(4) 12000", "NOT MAPPED axis(7) 32767"]);
+
+        // a button that is mapped twice is both, like it is in joy2uinput
+        let mut l = Live::new(expand_mapping(&parse_mapping_file("button(1) = start\nbutton(1) = plus\n").unwrap(), &HashMap::new()));
+        l.input(JoyEv::Button(1, 1));
+        assert_eq!(states(&l), vec!["pressed", "pressed"]);
+        assert!(l.table()[1].starts_with("plus "));
+    }
+}
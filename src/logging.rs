@@ -0,0 +1,157 @@
+/*
+
+What does this file do?
+
+    This is the logging layer used by joy2uinput and joy2u-mapgen.
+    Every message has a level (error, warn, info, debug or trace) and is written
+    either as a line of plain text, or as a line of JSON for other programs to
+    consume. Messages below the configured level are discarded.
+
+*/
+
+use std::fmt::Display;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+pub use joy2uinput::level::Level;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Format{
+    Text,
+    Json,
+}
+
+impl FromStr for Format{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        match s.trim().to_lowercase().as_ref(){
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("No such log format: {}. Expected text or json", s)),
+        }
+    }
+}
+
+pub fn json_escape(s: &str) -> String{
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars(){
+        match c{
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub struct Logger<W> where W: Write{
+    pub out: W,
+    pub level: Level,
+    pub format: Format,
+}
+
+impl<W> Logger<W> where W: Write{
+    pub fn new(out: W) -> Self {
+        Logger{ out, level: Level::Info, format: Format::Text }
+    }
+
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    pub fn log(&mut self, level: Level, msg: &str) {
+        self.log_with(level, msg, &[]);
+    }
+
+    // fields are extra key/value pairs, they become extra keys in json mode
+    pub fn log_with(&mut self, level: Level, msg: &str, fields: &[(&str, &dyn Display)]) {
+        if !self.enabled(level){
+            return;
+        }
+        let line = match self.format{
+            Format::Text => {
+                let mut line = match level{
+                    Level::Error => format!("Error: {}", msg),
+                    Level::Warn => format!("Warning: {}", msg),
+                    Level::Info => msg.to_string(),
+                    Level::Debug => format!("Debug: {}", msg),
+                    Level::Trace => format!("Trace: {}", msg),
+                };
+                for (k, v) in fields{
+                    line += &format!(" {}={}", k, v);
+                }
+                line
+            },
+            Format::Json => {
+                let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d|d.as_secs_f64()).unwrap_or(0.0);
+                let mut line = format!("{{\"time\":{:.3},\"level\":\"{}\",\"msg\":\"{}\"", time, level, json_escape(msg));
+                for (k, v) in fields{
+                    line += &format!(",\"{}\":\"{}\"", json_escape(k), json_escape(&v.to_string()));
+                }
+                line += "}";
+                line
+            },
+        };
+        let _ = writeln!(self.out, "{}", line);
+    }
+
+    // For "this should never happen" situations, so that they all get reported in the same way
+    pub fn bug(&mut self, msg: &str) {
+        let msg = format!("Internal error: {}. This is a bug! Please report it at https://github.com/lexbailey/joy2uinput/issues", msg);
+        self.log_with(Level::Error, &msg, &[("bug", &true)]);
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::logging::{Logger, Level, Format, json_escape};
+
+    fn lines(l: Logger<Vec<u8>>) -> Vec<String> {
+        String::from_utf8(l.out).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_text_levels(){
+        let mut l = Logger::new(Vec::new());
+        l.log(Level::Error, "one");
+        l.log(Level::Warn, "two");
+        l.log(Level::Info, "three");
+        l.log(Level::Debug, "four");
+        l.log(Level::Trace, "five");
+        assert_eq!(lines(l), ["Error: one", "Warning: two", "three"]);
+
+        let mut l = Logger::new(Vec::new());
+        l.level = Level::Trace;
+        l.log_with(Level::Trace, "joydev in", &[("pad", &0), ("value", &-32767)]);
+        assert_eq!(lines(l), ["Trace: joydev in pad=0 value=-32767"]);
+    }
+
+    #[test]
+    fn test_json_lines(){
+        let mut l = Logger::new(Vec::new());
+        l.format = Format::Json;
+        l.log_with(Level::Warn, "say \"hi\"", &[("pad", &3)]);
+        let out = lines(l);
+        assert_eq!(out.len(), 1);
+        assert!(out[0].starts_with("{\"time\":"), "{}", out[0]);
+        assert!(out[0].ends_with(",\"level\":\"warn\",\"msg\":\"say \\\"hi\\\"\",\"pad\":\"3\"}"), "{}", out[0]);
+    }
+
+    #[test]
+    fn test_parse_levels(){
+        assert_eq!("TRACE".parse::<Level>(), Ok(Level::Trace));
+        assert_eq!("warning".parse::<Level>(), Ok(Level::Warn));
+        assert!("loud".parse::<Level>().is_err());
+        assert_eq!("json".parse::<Format>(), Ok(Format::Json));
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_json_escape(){
+        assert_eq!(json_escape("a\\b\n\"c\"\u{1}"), "a\\\\b\\n\\\"c\\\"\\u0001");
+    }
+}
@@ -3,7 +3,8 @@
 What does this file do?
 
     This is the file with all the datatypes that are used internally in joy2uinput
-    These are also used somewhat by joy2u-mapgen.
+    These are also used somewhat by joy2u-mapgen, and by anything else that uses
+    the joy2uinput library.
     This includes all of the parser logic for reading from config files, and also
     the generation logic for writing config files.
 
@@ -13,8 +14,10 @@ use std::str::FromStr;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::ffi::OsString;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
-fn parse_args(s: &str, n: usize) -> Result<Vec<&str>, String> {
+fn parse_arg_list(s: &str) -> Result<Vec<&str>, String> {
     let s = s.trim();
     if s.len() < 2{
         return Err(format!("Malformed arguments: {}.", s));
@@ -23,14 +26,18 @@ fn parse_args(s: &str, n: usize) -> Result<Vec<&str>, String> {
         return Err(format!("Malformed arguments: {}.", s));
     }
     let inner = &s[1..s.len()-1];
-    let args: Vec<_> = inner.split(',').map(str::trim).collect();
+    Ok(inner.split(',').map(str::trim).collect())
+}
+
+fn parse_args(s: &str, n: usize) -> Result<Vec<&str>, String> {
+    let args = parse_arg_list(s)?;
     if args.len() != n{
         return Err(format!("Expected {} args, found {}", n, args.len()));
     }
     Ok(args)
 }
 
-#[derive(Debug,Clone,PartialEq,Eq,Hash,Ord)]
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 pub enum Button{
     Up(),
     Down(),
@@ -100,13 +107,19 @@ impl Button {
     }
 }
 
+impl Ord for Button{
+    fn cmp(&self, other: &Button) -> std::cmp::Ordering {
+        self.as_number().cmp(&other.as_number())
+    }
+}
+
 impl PartialOrd for Button{
     fn partial_cmp(&self, other: &Button) -> Option<std::cmp::Ordering> {
-        self.as_number().partial_cmp(&other.as_number())
+        Some(self.cmp(other))
     }
 }
 
-#[derive(Debug,Clone,PartialEq,Eq,Hash,Ord)]
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 pub enum Axis{
     LeftX(),
     LeftY(),
@@ -151,9 +164,15 @@ impl Axis{
     }
 }
 
+impl Ord for Axis{
+    fn cmp(&self, other: &Axis) -> std::cmp::Ordering {
+        self.as_number().cmp(&other.as_number())
+    }
+}
+
 impl PartialOrd for Axis{
     fn partial_cmp(&self, other: &Axis) -> Option<std::cmp::Ordering> {
-        self.as_number().partial_cmp(&other.as_number())
+        Some(self.cmp(other))
     }
 }
 
@@ -231,8 +250,8 @@ impl FromStr for Button{
             "plus" => Ok(Button::Plus()),
             "minus" => Ok(Button::Minus()),
             _ => {
-                if l.starts_with("custom_button"){
-                    let args = parse_args(&l[13..],1)?;
+                if let Some(args) = l.strip_prefix("custom_button"){
+                    let args = parse_args(args,1)?;
                     let n: Result<u128,_> = args[0].parse();
                     if let Ok(n) = n{
                         return Ok(Button::Custom(n));
@@ -267,8 +286,8 @@ impl FromStr for Axis{
 			"yaw" =>      Ok(Axis::Yaw()      ),
 
             _ => {
-                if l.starts_with("custom_axis"){
-                    let args = l[11..].trim();
+                if let Some(args) = l.strip_prefix("custom_axis"){
+                    let args = args.trim();
                     if args.starts_with("(") && args.ends_with(")"){
                         let n: Result<u128,_> = args[1..args.len()-1].parse();
                         if let Ok(n) = n{
@@ -335,6 +354,179 @@ pub enum KeyTarget{
     MouseButtonExtra(),
     MouseButtonForward(),
     MouseButtonBack(),
+    Extra(ExtraKey),
+    // any other key or button that linux knows about, given by kernel name or number
+    Code(evdev::Key),
+}
+
+// Friendly names for some keys that don't fit in the KeyTarget variants above.
+// Anything not listed here can still be used by its kernel name (e.g. KEY_PLAYPAUSE)
+#[derive(Debug,Clone,Copy,PartialEq,EnumIter)]
+pub enum ExtraKey{
+    PlayPause,
+    NextSong,
+    PreviousSong,
+    StopCD,
+    Mute,
+    MicMute,
+    BrightnessUp,
+    BrightnessDown,
+    BrowserBack,
+    BrowserForward,
+    BrowserRefresh,
+    BrowserHome,
+    BrowserSearch,
+    BrowserFavorites,
+    NumpadPlus,
+    NumpadMinus,
+    NumpadMultiply,
+    NumpadDivide,
+    NumpadEnter,
+    NumpadDot,
+    NumpadEquals,
+    Backspace,
+    Insert,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    PrintScreen,
+    Pause,
+    Grave,
+}
+
+impl ExtraKey{
+    // All the names this key can be given in a key(...) target. The first one is the main name
+    pub fn names(&self) -> &'static [&'static str] {
+        match self{
+            ExtraKey::PlayPause => &["playpause"],
+            ExtraKey::NextSong => &["nextsong", "next"],
+            ExtraKey::PreviousSong => &["previoussong", "prevsong", "previous", "prev"],
+            ExtraKey::StopCD => &["stop"],
+            ExtraKey::Mute => &["mute"],
+            ExtraKey::MicMute => &["micmute"],
+            ExtraKey::BrightnessUp => &["brightnessup"],
+            ExtraKey::BrightnessDown => &["brightnessdown"],
+            ExtraKey::BrowserBack => &["browserback"],
+            ExtraKey::BrowserForward => &["browserforward"],
+            ExtraKey::BrowserRefresh => &["browserrefresh"],
+            ExtraKey::BrowserHome => &["browserhome"],
+            ExtraKey::BrowserSearch => &["browsersearch"],
+            ExtraKey::BrowserFavorites => &["browserfavorites"],
+            ExtraKey::NumpadPlus => &["numpadplus"],
+            ExtraKey::NumpadMinus => &["numpadminus"],
+            ExtraKey::NumpadMultiply => &["numpadmultiply", "numpadasterisk"],
+            ExtraKey::NumpadDivide => &["numpaddivide", "numpadslash"],
+            ExtraKey::NumpadEnter => &["numpadenter"],
+            ExtraKey::NumpadDot => &["numpaddot"],
+            ExtraKey::NumpadEquals => &["numpadequals"],
+            ExtraKey::Backspace => &["backspace"],
+            ExtraKey::Insert => &["insert", "ins"],
+            ExtraKey::CapsLock => &["capslock"],
+            ExtraKey::NumLock => &["numlock"],
+            ExtraKey::ScrollLock => &["scrolllock"],
+            ExtraKey::PrintScreen => &["printscreen", "sysrq"],
+            ExtraKey::Pause => &["pause"],
+            ExtraKey::Grave => &["grave", "`"],
+        }
+    }
+
+    pub fn uinput_key(&self) -> evdev::Key{
+        match self{
+            ExtraKey::PlayPause => evdev::Key::KEY_PLAYPAUSE,
+            ExtraKey::NextSong => evdev::Key::KEY_NEXTSONG,
+            ExtraKey::PreviousSong => evdev::Key::KEY_PREVIOUSSONG,
+            ExtraKey::StopCD => evdev::Key::KEY_STOPCD,
+            ExtraKey::Mute => evdev::Key::KEY_MUTE,
+            ExtraKey::MicMute => evdev::Key::KEY_MICMUTE,
+            ExtraKey::BrightnessUp => evdev::Key::KEY_BRIGHTNESSUP,
+            ExtraKey::BrightnessDown => evdev::Key::KEY_BRIGHTNESSDOWN,
+            ExtraKey::BrowserBack => evdev::Key::KEY_BACK,
+            ExtraKey::BrowserForward => evdev::Key::KEY_FORWARD,
+            ExtraKey::BrowserRefresh => evdev::Key::KEY_REFRESH,
+            ExtraKey::BrowserHome => evdev::Key::KEY_HOMEPAGE,
+            ExtraKey::BrowserSearch => evdev::Key::KEY_SEARCH,
+            ExtraKey::BrowserFavorites => evdev::Key::KEY_BOOKMARKS,
+            ExtraKey::NumpadPlus => evdev::Key::KEY_KPPLUS,
+            ExtraKey::NumpadMinus => evdev::Key::KEY_KPMINUS,
+            ExtraKey::NumpadMultiply => evdev::Key::KEY_KPASTERISK,
+            ExtraKey::NumpadDivide => evdev::Key::KEY_KPSLASH,
+            ExtraKey::NumpadEnter => evdev::Key::KEY_KPENTER,
+            ExtraKey::NumpadDot => evdev::Key::KEY_KPDOT,
+            ExtraKey::NumpadEquals => evdev::Key::KEY_KPEQUAL,
+            ExtraKey::Backspace => evdev::Key::KEY_BACKSPACE,
+            ExtraKey::Insert => evdev::Key::KEY_INSERT,
+            ExtraKey::CapsLock => evdev::Key::KEY_CAPSLOCK,
+            ExtraKey::NumLock => evdev::Key::KEY_NUMLOCK,
+            ExtraKey::ScrollLock => evdev::Key::KEY_SCROLLLOCK,
+            ExtraKey::PrintScreen => evdev::Key::KEY_SYSRQ,
+            ExtraKey::Pause => evdev::Key::KEY_PAUSE,
+            ExtraKey::Grave => evdev::Key::KEY_GRAVE,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExtraKey>{
+        ExtraKey::iter().find(|k| k.names().contains(&name))
+    }
+}
+
+// The number of key codes linux has (KEY_CNT in linux/input-event-codes.h)
+const KEY_CNT: u16 = 0x300;
+
+// Looks up a key by its kernel name (e.g. KEY_PLAYPAUSE or BTN_TL) or by its number.
+// Numbers must have at least two digits so they don't clash with key(0) to key(9), use key(02) for code 2
+fn kernel_key(name: &str) -> Option<evdev::Key>{
+    if name.len() >= 2 && name.chars().all(|c| c.is_ascii_digit()){
+        return name.parse::<u16>().ok().filter(|n| *n < KEY_CNT).map(evdev::Key::new);
+    }
+    name.to_uppercase().parse::<evdev::Key>().ok()
+}
+
+// Every target that can be used in joy2uinput.conf, one per line, for --list-targets
+pub fn list_targets() -> Vec<String>{
+    let mut out = Vec::new();
+    for b in ["left", "right", "middle", "side", "extra", "forward", "back"]{
+        out.push(format!("mousebutton({})", b));
+    }
+    let names: &[&[&str]] = &[
+        &["up"], &["down"], &["left"], &["right"], &["escape", "esc"], &["return", "enter"], &["space"], &["pageup", "pgup"],
+        &["pagedown", "pgdn"], &["home"], &["end"], &["delete"], &["tab"], &["lctrl", "lcontrol"], &["rctrl", "rcontrol"],
+        &["lshift"], &["rshift"], &["lsuper"], &["rsuper"], &["lalt"], &["ralt"], &["menu"], &["volup", "volumeup"], &["voldown", "volumedown"],
+    ];
+    for k in names.iter().copied().chain(ExtraKey::iter().map(|k| k.names())){
+        out.push(k.iter().map(|n| format!("key({})", n)).collect::<Vec<_>>().join(" or "));
+    }
+    for c in ('a'..='z').chain('0'..='9'){
+        out.push(format!("key({})", c));
+    }
+    for n in 1..=24{
+        out.push(format!("key(f{})", n));
+    }
+    for n in 0..=9{
+        out.push(format!("key(numpad{})", n));
+    }
+    for k in ["-", "equals", "[", "]", ";", "'", "comma", ".", "/", "\\"]{
+        out.push(format!("key({})", k));
+    }
+    // evdev's debug output for a key is its kernel name, if it has one
+    for code in 1..KEY_CNT{
+        let name = format!("{:?}", evdev::Key::new(code));
+        if !name.starts_with("unknown"){
+            out.push(format!("key({})", name));
+        }
+    }
+    for a in ["mousex", "mousey", "scrollx", "scrolly", "pageupdown", "leftright", "updown", "volupdown"]{
+        out.push(format!("axis({},M)", a));
+    }
+    out.push("raw_key(CODE)".to_string());
+    out.push("raw_rel(CODE,M)".to_string());
+    out.push("raw_abs(CODE,MIN,MAX)".to_string());
+    out.push("type(\"TEXT\")".to_string());
+    out.push("rumble(STRENGTH,DURATION_MS)".to_string());
+    out.push("toggle_enabled".to_string());
+    out.push("launch <program args>".to_string());
+    out.push("launch(OPTIONS) <program args>".to_string());
+    out.push("kill_launched".to_string());
+    out
 }
 
 #[derive(Debug,Clone,PartialEq)]
@@ -347,6 +539,8 @@ pub enum AxisTarget{
     LeftRight(f32),
     UpDown(f32),
     VolUpDown(f32),
+    // any relative axis, by kernel name or number
+    Raw(evdev::RelativeAxisType, f32),
 }
 
 #[derive(Debug,Clone,PartialEq)]
@@ -354,7 +548,439 @@ pub enum Target{
     Key(KeyTarget),
     Axis(AxisTarget),
     ToggleEnabled(),
-    Launch(Vec<String>),
+    Launch(LaunchTarget),
+    // stops everything that was started by a launch target
+    KillLaunched(),
+    // an absolute axis, by kernel name or number, with the range of values it should be sent
+    RawAbs(evdev::AbsoluteAxisType, i32, i32),
+    // types out some text, using the keyboard layout from the keyboard_layout setting
+    Type(String),
+    // rumbles the joypad, with a strength from 0 to 1, for some number of milliseconds
+    Rumble(f32, u16),
+}
+
+// What to do when a launch target is pressed again while the program it started is still running
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum SingleInstance{
+    Ignore,
+    Focus,
+}
+
+// Things that are filled in when a program is launched, written as %device% etc
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Placeholder{
+    Device,
+    Input,
+    // always "default", until there are profiles
+    Profile,
+    Player,
+}
+
+impl Placeholder{
+    const ALL: [Placeholder; 4] = [Placeholder::Device, Placeholder::Input, Placeholder::Profile, Placeholder::Player];
+
+    pub fn name(&self) -> &'static str {
+        match self{
+            Placeholder::Device => "device",
+            Placeholder::Input => "input",
+            Placeholder::Profile => "profile",
+            Placeholder::Player => "player",
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ArgPart{
+    Text(String),
+    // an environment variable, $NAME or ${NAME}
+    Var(String),
+    Placeholder(Placeholder),
+}
+
+// One argument of a launch target. It is only turned into a string when the program is launched
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct Arg(pub Vec<ArgPart>);
+
+impl Arg{
+    fn push_char(&mut self, c: char){
+        if let Some(ArgPart::Text(t)) = self.0.last_mut(){
+            t.push(c);
+        }
+        else{
+            self.0.push(ArgPart::Text(c.to_string()));
+        }
+    }
+
+    pub fn expand<V,P>(&self, var: V, placeholder: P) -> String where V: Fn(&str) -> Option<String>, P: Fn(Placeholder) -> String {
+        let mut s = String::new();
+        for part in self.0.iter(){
+            match part{
+                ArgPart::Text(t) => s.push_str(t),
+                // unset variables are empty, like in a shell
+                ArgPart::Var(name) => s.push_str(&var(name).unwrap_or_default()),
+                ArgPart::Placeholder(p) => s.push_str(&placeholder(*p)),
+            }
+        }
+        s
+    }
+}
+
+impl From<&str> for Arg{
+    fn from(s: &str) -> Self {
+        Arg(vec![ArgPart::Text(s.to_string())])
+    }
+}
+
+impl Display for Arg{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let mut s = String::new();
+        let mut quote = self.0.is_empty();
+        for part in self.0.iter(){
+            match part{
+                ArgPart::Text(t) => {
+                    for c in t.chars(){
+                        match c{
+                            '"' | '\\' | '$' | '%' | '\'' => { s.push('\\'); s.push(c); },
+                            ',' | '(' | ')' => { quote = true; s.push(c); },
+                            c if c.is_whitespace() => { quote = true; s.push(c); },
+                            c => s.push(c),
+                        }
+                    }
+                },
+                ArgPart::Var(name) => { s.push_str("${"); s.push_str(name); s.push('}'); },
+                ArgPart::Placeholder(p) => { s.push('%'); s.push_str(p.name()); s.push('%'); },
+            }
+        }
+        if quote{
+            write!(f, "\"{}\"", s)
+        }
+        else{
+            f.write_str(&s)
+        }
+    }
+}
+
+// If s starts with a placeholder name and a closing %, returns the placeholder and its length
+fn placeholder_at(s: &str) -> Option<(Placeholder, usize)> {
+    Placeholder::ALL.iter().find_map(|p|{
+        let rest = s.strip_prefix(p.name())?;
+        if rest.starts_with('%') {Some((*p, p.name().len() + 1))} else {None}
+    })
+}
+
+// Splits a launch command line into arguments, the way a (very simple) shell would.
+// Single quotes keep everything as it is, double quotes still allow $VAR and %placeholder%
+pub fn parse_args_words(s: &str) -> Result<Vec<Arg>, String> {
+    let mut args = Vec::new();
+    let mut arg: Option<Arg> = None;
+    let mut single = false;
+    let mut double = false;
+    let mut i = 0;
+    while let Some(c) = s[i..].chars().next(){
+        i += c.len_utf8();
+        if single{
+            if c == '\''{
+                single = false;
+            }
+            else{
+                arg.get_or_insert_with(Arg::default).push_char(c);
+            }
+            continue;
+        }
+        match c{
+            '\\' => {
+                match s[i..].chars().next(){
+                    Some(e) if "\"\\$%'".contains(e) => {
+                        arg.get_or_insert_with(Arg::default).push_char(e);
+                        i += e.len_utf8();
+                    },
+                    Some(e) => return Err(format!("unrecognised escape sequence: '\\{}'", e)),
+                    None => return Err("unfinished escape sequence at the end of the line".to_string()),
+                }
+            },
+            '"' => {
+                double = !double;
+                arg.get_or_insert_with(Arg::default);
+            },
+            '\'' if !double => {
+                single = true;
+                arg.get_or_insert_with(Arg::default);
+            },
+            ' ' | '\t' if !double => {
+                if let Some(a) = arg.take(){
+                    args.push(a);
+                }
+            },
+            '$' => {
+                let rest = &s[i..];
+                let (name, len) = if let Some(braced) = rest.strip_prefix('{'){
+                    match braced.split_once('}'){
+                        Some((name, _)) => (name, name.len() + 2),
+                        None => return Err(format!("unfinished variable name: ${}", rest)),
+                    }
+                }
+                else{
+                    let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                    (&rest[..len], len)
+                };
+                let a = arg.get_or_insert_with(Arg::default);
+                if name.is_empty(){
+                    a.push_char('$');
+                }
+                else{
+                    a.0.push(ArgPart::Var(name.to_string()));
+                }
+                i += len;
+            },
+            '%' => {
+                let a = arg.get_or_insert_with(Arg::default);
+                match placeholder_at(&s[i..]){
+                    Some((p, len)) => {
+                        a.0.push(ArgPart::Placeholder(p));
+                        i += len;
+                    },
+                    None => a.push_char('%'),
+                }
+            },
+            c => arg.get_or_insert_with(Arg::default).push_char(c),
+        }
+    }
+    if single || double{
+        return Err(format!("unfinished quote in: {}", s));
+    }
+    if let Some(a) = arg{
+        args.push(a);
+    }
+    Ok(args)
+}
+
+// With shell=true the command is given to sh as it is, only the placeholders are filled in
+fn parse_shell_command(s: &str) -> Arg {
+    let mut arg = Arg::default();
+    let mut i = 0;
+    while let Some(c) = s[i..].chars().next(){
+        i += c.len_utf8();
+        match (c, placeholder_at(&s[i..])){
+            ('%', Some((p, len))) => {
+                arg.0.push(ArgPart::Placeholder(p));
+                i += len;
+            },
+            (c, _) => arg.push_char(c),
+        }
+    }
+    arg
+}
+
+// Splits on sep, except where it is inside quotes
+fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut single = false;
+    let mut double = false;
+    let mut escape = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices(){
+        if escape{
+            escape = false;
+            continue;
+        }
+        match c{
+            '\\' if !single => { escape = true; },
+            '\'' if !double => { single = !single; },
+            '"' if !single => { double = !double; },
+            c if c == sep && !single && !double => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            },
+            _ => {},
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+#[derive(Debug,Clone,PartialEq,Default)]
+pub struct LaunchTarget{
+    pub args: Vec<Arg>,
+    pub single: Option<SingleInstance>,
+    pub kill_on_release: bool,
+    // file to append the program's stdout and stderr to
+    pub log: Option<Arg>,
+    pub cwd: Option<Arg>,
+    pub env: Vec<(String, Arg)>,
+    // run the command with sh -c, args then holds the whole command as one Arg
+    pub shell: bool,
+}
+
+// An option value, which is one argument
+fn parse_option_value(name: &str, v: &str) -> Result<Arg, String> {
+    let mut words = parse_args_words(v)?;
+    if words.len() != 1{
+        return Err(format!("Invalid value for launch option {}: {}. Use quotes if it contains spaces", name, v));
+    }
+    Ok(words.remove(0))
+}
+
+fn parse_bool_option(name: &str, v: Option<&str>) -> Result<bool, String> {
+    match v{
+        None => Ok(true),
+        Some(v) => v.parse::<bool>().map_err(|_| format!("Invalid value for launch option {}: {}. Expected true or false", name, v)),
+    }
+}
+
+// Parses the options in launch(single=focus, kill_on_release, log=/tmp/game.log)
+fn parse_launch_options(s: &str, target: &mut LaunchTarget) -> Result<(), String> {
+    for opt in split_unquoted(s, ',').into_iter().map(str::trim).filter(|o| !o.is_empty()){
+        let (name, value) = match opt.split_once('='){
+            Some((name, value)) => (name.trim().to_lowercase(), Some(value.trim())),
+            None => (opt.to_lowercase(), None),
+        };
+        match (name.as_str(), value){
+            ("single", Some(v)) => {
+                target.single = Some(match v.to_lowercase().as_str(){
+                    "ignore" => SingleInstance::Ignore,
+                    "focus" => SingleInstance::Focus,
+                    _ => return Err(format!("Invalid value for launch option single: {}. Expected ignore or focus", v)),
+                });
+            },
+            ("kill_on_release", v) => { target.kill_on_release = parse_bool_option(&name, v)?; },
+            ("shell", v) => { target.shell = parse_bool_option(&name, v)?; },
+            ("log", Some(v)) if !v.is_empty() => { target.log = Some(parse_option_value(&name, v)?); },
+            ("cwd", Some(v)) if !v.is_empty() => { target.cwd = Some(parse_option_value(&name, v)?); },
+            ("env", Some(v)) => {
+                match v.split_once('='){
+                    Some((k, v)) if !k.trim().is_empty() => target.env.push((k.trim().to_string(), parse_option_value(&name, v.trim())?)),
+                    _ => return Err(format!("Invalid value for launch option env: {}. Expected env=NAME=VALUE", v)),
+                }
+            },
+            _ => return Err(format!("Invalid launch option: {}", opt)),
+        }
+    }
+    Ok(())
+}
+
+// Parses everything after the word launch
+impl FromStr for LaunchTarget{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        let mut target = LaunchTarget::default();
+        let mut rest = s.trim();
+        if let Some(opts) = rest.strip_prefix('('){
+            // the options end at the first ) that isn't in quotes
+            let parts = split_unquoted(opts, ')');
+            if parts.len() < 2{
+                return Err(format!("Malformed options to launch target specifier: launch{}", s));
+            }
+            parse_launch_options(parts[0], &mut target)?;
+            rest = opts[parts[0].len() + 1..].trim();
+        }
+        if target.shell{
+            if !rest.is_empty(){
+                target.args = vec![parse_shell_command(rest)];
+            }
+        }
+        else{
+            target.args = parse_args_words(rest)?;
+        }
+        if target.args.is_empty(){
+            return Err(format!("No program given to launch target specifier: launch{}", s));
+        }
+        Ok(target)
+    }
+}
+
+impl Display for LaunchTarget{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let mut opts = Vec::new();
+        match self.single{
+            Some(SingleInstance::Ignore) => opts.push("single=ignore".to_string()),
+            Some(SingleInstance::Focus) => opts.push("single=focus".to_string()),
+            None => {},
+        }
+        if self.kill_on_release{
+            opts.push("kill_on_release".to_string());
+        }
+        if let Some(log) = &self.log{
+            opts.push(format!("log={}", log));
+        }
+        if let Some(cwd) = &self.cwd{
+            opts.push(format!("cwd={}", cwd));
+        }
+        for (k, v) in self.env.iter(){
+            opts.push(format!("env={}={}", k, v));
+        }
+        if self.shell{
+            opts.push("shell".to_string());
+        }
+        f.write_str("launch")?;
+        if !opts.is_empty(){
+            write!(f, "({})", opts.join(", "))?;
+        }
+        if self.shell{
+            // the command is written out as it was given
+            let cmd: String = self.args.iter().flat_map(|a| a.0.iter()).map(|p| match p{
+                ArgPart::Text(t) => t.clone(),
+                ArgPart::Var(name) => format!("${{{}}}", name),
+                ArgPart::Placeholder(p) => format!("%{}%", p.name()),
+            }).collect();
+            write!(f, " {}", cmd)
+        }
+        else{
+            for a in self.args.iter(){
+                write!(f, " {}", a)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Parses the quoted string in type("some text")
+fn parse_type_text(s: &str) -> Result<String, String> {
+    let inner = s.trim().strip_prefix('(').and_then(|s| s.strip_suffix(')')).map(str::trim);
+    let inner = inner.and_then(|s| s.strip_prefix('"')).and_then(|s| s.strip_suffix('"'));
+    let inner = match inner{
+        Some(inner) => inner,
+        None => return Err(format!("Malformed arguments to type target specifier: {}. Expected some text in double quotes.", s)),
+    };
+    let mut text = String::new();
+    let mut escape = false;
+    for c in inner.chars(){
+        if escape{
+            match c{
+                '"' | '\\' => text.push(c),
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                c => return Err(format!("unrecognised escape sequence: '\\{}'", c)),
+            }
+            escape = false;
+        }
+        else if c == '\\'{
+            escape = true;
+        }
+        else if c == '"'{
+            return Err(format!("Malformed arguments to type target specifier: {}. Use \\\" for a double quote inside the text.", s));
+        }
+        else{
+            text.push(c);
+        }
+    }
+    if escape{
+        return Err(format!("Malformed arguments to type target specifier: {}. The text ends with a backslash.", s));
+    }
+    Ok(text)
+}
+
+// The number of relative and absolute axis codes linux has (REL_CNT and ABS_CNT in linux/input-event-codes.h)
+const REL_CNT: u16 = 0x10;
+const ABS_CNT: u16 = 0x40;
+
+// A raw evdev code, given either by its kernel name (e.g. REL_DIAL) or by its number
+fn raw_code<T: FromStr>(name: &str, count: u16, code: impl Fn(T) -> u16) -> Result<u16, String>{
+    let name = name.trim();
+    match name.parse::<u16>(){
+        Ok(n) if n < count => Ok(n),
+        Ok(n) => Err(format!("Code {} is out of range, it must be less than {}", n, count)),
+        Err(_) => name.to_uppercase().parse::<T>().map(code).map_err(|_| format!("Unknown code name: {}", name)),
+    }
 }
 
 impl AxisTarget{
@@ -373,6 +999,7 @@ impl AxisTarget{
                 keys.push(evdev::Key::KEY_DOWN);
                 keys.push(evdev::Key::KEY_UP);
             },
+            AxisTarget::Raw(..) => {},
             AxisTarget::VolUpDown(_) => {
                 keys.push(evdev::Key::KEY_VOLUMEDOWN);
                 keys.push(evdev::Key::KEY_VOLUMEUP);
@@ -391,6 +1018,7 @@ impl AxisTarget{
             AxisTarget::LeftRight(_) => None,
             AxisTarget::UpDown(_) => None,
             AxisTarget::VolUpDown(_) => None,
+            AxisTarget::Raw(a, _) => Some(*a),
             AxisTarget::MouseX(_) => {
                 Some(evdev::RelativeAxisType::REL_X)
             },
@@ -412,6 +1040,7 @@ impl AxisTarget{
             AxisTarget::LeftRight(m) => *m,
             AxisTarget::UpDown(m) => *m,
             AxisTarget::VolUpDown(m) => *m,
+            AxisTarget::Raw(_, m) => *m,
             AxisTarget::MouseX(m) => *m,
             AxisTarget::MouseY(m) => *m,
             AxisTarget::ScrollX(m) => *m,
@@ -455,6 +1084,8 @@ impl KeyTarget{
             KeyTarget::MouseButtonExtra() => evdev::Key::BTN_EXTRA,
             KeyTarget::MouseButtonBack() => evdev::Key::BTN_BACK,
             KeyTarget::MouseButtonForward() => evdev::Key::BTN_FORWARD,
+            KeyTarget::Extra(k) => k.uinput_key(),
+            KeyTarget::Code(k) => *k,
             KeyTarget::F(n) => match n{
                 1 => evdev::Key::KEY_F1,
                 2 => evdev::Key::KEY_F2,
@@ -610,9 +1241,15 @@ impl FromStr for KeyTarget{
                         "volup" | "volumeup" => Ok(KeyTarget::VolUp()),
                         "voldown" | "volumedown" => Ok(KeyTarget::VolDown()),
                         a => {
-                            if a.len() == 1{
+                            if let Some(k) = ExtraKey::from_name(a){
+                                Ok(KeyTarget::Extra(k))
+                            }
+                            else if a.len() == 1{
                                 Ok(KeyTarget::AlphaNum(a.chars().next().unwrap()))
                             }
+                            else if let Some(k) = kernel_key(a){
+                                Ok(KeyTarget::Code(k))
+                            }
                             else{
                                 if a == "equals"{
                                     Ok(KeyTarget::AlphaNum('='))
@@ -620,15 +1257,15 @@ impl FromStr for KeyTarget{
                                 else if a == "comma"{
                                     Ok(KeyTarget::AlphaNum(','))
                                 }
-                                else if a.starts_with("f"){
-                                    let num = a[1..].parse::<u8>();
+                                else if let Some(num) = a.strip_prefix('f'){
+                                    let num = num.parse::<u8>();
                                     match num{
                                         Ok(num) => Ok(KeyTarget::F(num)),
                                         Err(e) => Err(format!("Invalid key target specifier: {}. {}", s, e)),
                                     }
                                 }
-                                else if a.starts_with("numpad"){
-                                    let num = a[6..].parse::<u8>();
+                                else if let Some(num) = a.strip_prefix("numpad"){
+                                    let num = num.parse::<u8>();
                                     match num{
                                         Ok(num) => Ok(KeyTarget::Numpad(num)),
                                         Err(e) => Err(format!("Invalid key target specifier: {}. {}", s, e)),
@@ -646,12 +1283,110 @@ impl FromStr for KeyTarget{
     }
 }
 
+impl Display for KeyTarget{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self{
+            KeyTarget::Up() => f.write_str("key(up)"),
+            KeyTarget::Down() => f.write_str("key(down)"),
+            KeyTarget::Left() => f.write_str("key(left)"),
+            KeyTarget::Right() => f.write_str("key(right)"),
+            KeyTarget::Escape() => f.write_str("key(escape)"),
+            KeyTarget::Return() => f.write_str("key(return)"),
+            KeyTarget::Space() => f.write_str("key(space)"),
+            KeyTarget::PageUp() => f.write_str("key(pageup)"),
+            KeyTarget::PageDown() => f.write_str("key(pagedown)"),
+            KeyTarget::Home() => f.write_str("key(home)"),
+            KeyTarget::End() => f.write_str("key(end)"),
+            KeyTarget::Delete() => f.write_str("key(delete)"),
+            KeyTarget::Tab() => f.write_str("key(tab)"),
+            KeyTarget::LCtrl() => f.write_str("key(lctrl)"),
+            KeyTarget::RCtrl() => f.write_str("key(rctrl)"),
+            KeyTarget::LShift() => f.write_str("key(lshift)"),
+            KeyTarget::RShift() => f.write_str("key(rshift)"),
+            KeyTarget::LSuper() => f.write_str("key(lsuper)"),
+            KeyTarget::RSuper() => f.write_str("key(rsuper)"),
+            KeyTarget::LAlt() => f.write_str("key(lalt)"),
+            KeyTarget::RAlt() => f.write_str("key(ralt)"),
+            KeyTarget::Menu() => f.write_str("key(menu)"),
+            KeyTarget::VolUp() => f.write_str("key(volup)"),
+            KeyTarget::VolDown() => f.write_str("key(voldown)"),
+            KeyTarget::MouseButtonLeft() => f.write_str("mousebutton(left)"),
+            KeyTarget::MouseButtonRight() => f.write_str("mousebutton(right)"),
+            KeyTarget::MouseButtonMiddle() => f.write_str("mousebutton(middle)"),
+            KeyTarget::MouseButtonSide() => f.write_str("mousebutton(side)"),
+            KeyTarget::MouseButtonExtra() => f.write_str("mousebutton(extra)"),
+            KeyTarget::MouseButtonForward() => f.write_str("mousebutton(forward)"),
+            KeyTarget::MouseButtonBack() => f.write_str("mousebutton(back)"),
+            KeyTarget::AlphaNum(',') => f.write_str("key(comma)"),
+            KeyTarget::AlphaNum('=') => f.write_str("key(equals)"),
+            KeyTarget::AlphaNum(c) => write!(f, "key({})", c),
+            KeyTarget::Numpad(n) => write!(f, "key(numpad{})", n),
+            KeyTarget::F(n) => write!(f, "key(f{})", n),
+            KeyTarget::Extra(k) => write!(f, "key({})", k.names()[0]),
+            // the leading zero stops a single digit code from reading back as a number key
+            KeyTarget::Code(k) => write!(f, "key({})", code_name(format!("{:?}", k), k.code(), 2)),
+        }
+    }
+}
+
+// The name evdev has for a code, or the code itself (padded to width) for the ones it has no name for
+fn code_name(name: String, code: u16, width: usize) -> String {
+    if name.starts_with("unknown"){
+        format!("{:0width$}", code, width = width)
+    }
+    else{
+        name
+    }
+}
+
+impl Display for AxisTarget{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self{
+            AxisTarget::MouseX(m) => write!(f, "axis(mousex,{})", m),
+            AxisTarget::MouseY(m) => write!(f, "axis(mousey,{})", m),
+            AxisTarget::ScrollX(m) => write!(f, "axis(scrollx,{})", m),
+            AxisTarget::ScrollY(m) => write!(f, "axis(scrolly,{})", m),
+            AxisTarget::PageUpDown(m) => write!(f, "axis(pageupdown,{})", m),
+            AxisTarget::LeftRight(m) => write!(f, "axis(leftright,{})", m),
+            AxisTarget::UpDown(m) => write!(f, "axis(updown,{})", m),
+            AxisTarget::VolUpDown(m) => write!(f, "axis(volupdown,{})", m),
+            AxisTarget::Raw(a, m) => write!(f, "raw_rel({},{})", code_name(format!("{:?}", a), a.0, 1), m),
+        }
+    }
+}
+
+impl Display for Target{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self{
+            Target::Key(k) => write!(f, "{}", k),
+            Target::Axis(a) => write!(f, "{}", a),
+            Target::ToggleEnabled() => f.write_str("toggle_enabled"),
+            Target::Launch(l) => write!(f, "{}", l),
+            Target::KillLaunched() => f.write_str("kill_launched"),
+            Target::RawAbs(a, min, max) => write!(f, "raw_abs({},{},{})", code_name(format!("{:?}", a), a.0, 1), min, max),
+            Target::Type(text) => {
+                let mut escaped = String::new();
+                for c in text.chars(){
+                    match c{
+                        '"' | '\\' => { escaped.push('\\'); escaped.push(c); },
+                        '\n' => escaped.push_str("\\n"),
+                        '\t' => escaped.push_str("\\t"),
+                        c => escaped.push(c),
+                    }
+                }
+                write!(f, "type(\"{}\")", escaped)
+            },
+            Target::Rumble(strength, duration) => write!(f, "rumble({},{})", strength, duration),
+        }
+    }
+}
+
 impl FromStr for AxisTarget{
     type Err = String;
     fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
         let l = s.to_lowercase();
         if !l.starts_with("axis"){
-            return Err(format!("Invalid axis target specifier: {}", s));
+            Err(format!("Invalid axis target specifier: {}", s))
         }
         else{
             let args = parse_args(&l[4..], 2);
@@ -695,54 +1430,41 @@ impl FromStr for Target{
         if l.starts_with("axis"){
             return Ok(Target::Axis(s.parse()?));
         }
+        if l.starts_with("raw_key"){
+            let args = parse_args(&s[7..], 1).map_err(|e| format!("Malformed arguments to raw key target specifier: {}. {}", s, e))?;
+            let code = raw_code(args[0], KEY_CNT, |k: evdev::Key| k.code())?;
+            return Ok(Target::Key(KeyTarget::Code(evdev::Key::new(code))));
+        }
+        if l.starts_with("raw_rel"){
+            let args = parse_args(&s[7..], 2).map_err(|e| format!("Malformed arguments to raw relative axis target specifier: {}. {}", s, e))?;
+            let code = raw_code(args[0], REL_CNT, |a: evdev::RelativeAxisType| a.0)?;
+            let mult = args[1].trim().parse::<f32>().map_err(|_| format!("Malformed arguments to raw relative axis target specifier: {}. Argument 2 should be a float", s))?;
+            return Ok(Target::Axis(AxisTarget::Raw(evdev::RelativeAxisType(code), mult)));
+        }
+        if l.starts_with("raw_abs"){
+            let args = parse_args(&s[7..], 3).map_err(|e| format!("Malformed arguments to raw absolute axis target specifier: {}. {}", s, e))?;
+            let code = raw_code(args[0], ABS_CNT, |a: evdev::AbsoluteAxisType| a.0)?;
+            let min = args[1].trim().parse::<i32>().map_err(|_| format!("Malformed arguments to raw absolute axis target specifier: {}. Argument 2 should be an integer", s))?;
+            let max = args[2].trim().parse::<i32>().map_err(|_| format!("Malformed arguments to raw absolute axis target specifier: {}. Argument 3 should be an integer", s))?;
+            return Ok(Target::RawAbs(evdev::AbsoluteAxisType(code), min, max));
+        }
+        if l.starts_with("type"){
+            return Ok(Target::Type(parse_type_text(&s[4..])?));
+        }
+        if l.starts_with("rumble"){
+            let args = parse_args(&s[6..], 2).map_err(|e| format!("Malformed arguments to rumble target specifier: {}. {}", s, e))?;
+            let strength = args[0].parse::<f32>().ok().filter(|s| (0.0..=1.0).contains(s)).ok_or(format!("Malformed arguments to rumble target specifier: {}. Argument 1 should be a strength from 0 to 1", s))?;
+            let duration = args[1].parse::<u16>().ok().filter(|d| *d > 0).ok_or(format!("Malformed arguments to rumble target specifier: {}. Argument 2 should be a number of milliseconds, up to {}", s, u16::MAX))?;
+            return Ok(Target::Rumble(strength, duration));
+        }
         if l.trim() == "toggle_enabled"{
             return Ok(Target::ToggleEnabled());
         }
-        if l.starts_with("launch "){
-            let mut args = Vec::new();
-            let mut this_arg = String::new();
-            let mut in_quote = false;
-            let mut escape = false;
-            for c in s.chars().skip(7){
-                match c{
-                    ' ' => {
-                        if escape{ return Err(format!("unrecognised escape sequence: '\\{}'", c)); }
-                        if in_quote{
-                            this_arg.push(' ');
-                        }
-                        else{
-                            args.push(this_arg);
-                            this_arg = String::new();
-                        }
-                    }
-                    '"' => {
-                        if escape{
-                            this_arg.push('"');
-                            escape = false;
-                        }
-                        else{
-                            in_quote = !in_quote;
-                        }
-                    }
-                    '\\' => {
-                        if escape{
-                            this_arg.push('\\');
-                            escape = false;
-                        }
-                        else{
-                            escape = true;
-                        }
-                    }
-                    a => {
-                        if escape{ return Err(format!("unrecognised escape sequence: '\\{}'", c)); }
-                        this_arg.push(a);
-                    }
-                }
-            }
-            if this_arg.len() > 0{
-                args.push(this_arg);
-            }
-            return Ok(Target::Launch(args));
+        if l.trim() == "kill_launched"{
+            return Ok(Target::KillLaunched());
+        }
+        if l.starts_with("launch ") || l.starts_with("launch("){
+            return Ok(Target::Launch(s[6..].parse()?));
         }
         Err(format!("Unrecognised uinput target specifier: {}", s))
     }
@@ -752,7 +1474,8 @@ impl FromStr for Target{
 pub enum JDEv{
     Button(u8),
     AxisAsButton(u8, i16),
-    Axis(u8, i16, i16),
+    // number, min, max, centre, deadzone (distance from the centre that still counts as the centre)
+    Axis(u8, i16, i16, i16, u16),
 }
 
 impl Display for JDEv{
@@ -760,7 +1483,8 @@ impl Display for JDEv{
         match self {
 		    JDEv::Button(b) => write!(f, "button({})", b),
 		    JDEv::AxisAsButton(a, val) => write!(f, "axis_as_button({},{})", a, val),
-		    JDEv::Axis(a,min,max) => write!(f, "axis({},{},{})", a,min,max),
+		    JDEv::Axis(a,min,max,0,0) => write!(f, "axis({},{},{})", a,min,max),
+		    JDEv::Axis(a,min,max,centre,deadzone) => write!(f, "axis({},{},{},{},{})", a,min,max,centre,deadzone),
         }
 	}
 }
@@ -776,31 +1500,37 @@ impl FromStr for JDEv{
     type Err = String;
     fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
         let l = s.trim().to_lowercase();
-        if l.starts_with("button"){
-            let args = l[6..].trim();
+        if let Some(args) = l.strip_prefix("button"){
+            let args = args.trim();
             return match (||->Result<JDEv, String>{
                 let args = parse_args(args, 1)?;
-                return Ok(JDEv::Button(string_err(args[0].parse())?));
+                Ok(JDEv::Button(string_err(args[0].parse())?))
             })(){
                 Err(a) => Err(format!("Unable to parse joydev event 'button': {}", a)),
                 Ok(a) => Ok(a),
             };
         }
-        if l.starts_with("axis_as_button"){
-            let args = l[14..].trim();
+        if let Some(args) = l.strip_prefix("axis_as_button"){
+            let args = args.trim();
             return match (||->Result<JDEv, String>{
                 let args = parse_args(args, 2)?;
-                return Ok(JDEv::AxisAsButton(string_err(args[0].parse())?,string_err(args[1].parse())?));
+                Ok(JDEv::AxisAsButton(string_err(args[0].parse())?,string_err(args[1].parse())?))
             })(){
                 Err(a) => Err(format!("Unable to parse joydev event 'axis_as_button': {}", a)),
                 Ok(a) => Ok(a),
             };
         }
-        if l.starts_with("axis"){
-            let args = l[4..].trim();
+        if let Some(args) = l.strip_prefix("axis"){
+            let args = args.trim();
             return match (||->Result<JDEv, String>{
-                let args = parse_args(args, 3)?;
-                return Ok(JDEv::Axis(string_err(args[0].parse())?,string_err(args[1].parse())?,string_err(args[2].parse())?));
+                // the centre and deadzone are optional, without them the centre is 0
+                let args = parse_arg_list(args)?;
+                let (centre, deadzone) = match args.len(){
+                    3 => (0, 0),
+                    5 => (string_err(args[3].parse())?, string_err(args[4].parse())?),
+                    n => return Err(format!("Expected 3 or 5 args, found {}", n)),
+                };
+                Ok(JDEv::Axis(string_err(args[0].parse())?,string_err(args[1].parse())?,string_err(args[2].parse())?,centre,deadzone))
             })(){
                 Err(a) => Err(format!("Unable to parse joydev event 'axis': {}", a)),
                 Ok(a) => Ok(a),
@@ -822,56 +1552,351 @@ impl Display for Mapping{
 	}
 }
 
-impl FromStr for Mapping{
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+// What sort of problem a line has, for anything that needs to do more than show the message
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ErrorKind{
+    // the line isn't the right shape, such as a mapping with no '=', or an empty target in a list
+    Syntax(),
+    // not button, axis_as_button or axis
+    UnknownJoydevEvent(),
+    // not the name of a button or an axis
+    UnknownInput(),
+    // not the name of a target
+    UnknownTarget(),
+    // a joydev event, input or target that exists, with arguments or options that are wrong
+    BadArgument(),
+    // a target that can't be used in an 'on' line
+    HookTarget(),
+    UnknownHook(),
+    UnknownSetting(),
+    BadSettingValue(),
+}
+
+// A problem with one part of a line, offset is the byte in the line where that part starts
+#[derive(Debug,Clone,PartialEq)]
+pub struct LineError{
+    pub offset: usize,
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+// Where part (which must be a slice of line) starts in line
+fn offset_of(line: &str, part: &str) -> usize {
+    part.as_ptr() as usize - line.as_ptr() as usize
+}
+
+pub fn error_at(line: &str, part: &str, kind: ErrorKind, message: String) -> LineError {
+    LineError{offset: offset_of(line, part), kind, message}
+}
+
+// A part of a line that didn't parse is unknown if its name (up to any arguments) isn't one of names,
+// otherwise it is the arguments that are wrong
+fn kind_by_name(part: &str, names: &[&str], unknown: ErrorKind) -> ErrorKind {
+    let name = part.trim().split(|c: char| c == '(' || c.is_whitespace()).next().unwrap_or("").to_lowercase();
+    if names.contains(&name.as_str()) {ErrorKind::BadArgument()} else {unknown}
+}
+
+// What is wrong with a button or axis name that didn't parse
+pub fn input_error_kind(part: &str) -> ErrorKind {
+    kind_by_name(part, &["custom_button", "custom_axis"], ErrorKind::UnknownInput())
+}
+
+fn jdev_error_kind(part: &str) -> ErrorKind {
+    kind_by_name(part, &["button", "axis_as_button", "axis"], ErrorKind::UnknownJoydevEvent())
+}
+
+// the names that Target::from_str knows, anything else is an unknown target
+fn target_error_kind(part: &str) -> ErrorKind {
+    kind_by_name(part, &["key", "mousebutton", "axis", "raw_key", "raw_rel", "raw_abs", "type", "rumble", "toggle_enabled", "kill_launched", "launch"], ErrorKind::UnknownTarget())
+}
+
+fn join_errors(errors: Vec<LineError>) -> String {
+    errors.into_iter().map(|e| e.message).collect::<Vec<_>>().join(", also ")
+}
+
+impl Mapping{
+    // Like parse, but says where in the line each problem is
+    pub fn parse_located(s: &str) -> Result<Mapping, Vec<LineError>> {
 		// syntax is: "JDEv = JoyInput"
 		let sides: Vec<_> = s.split("=").collect();
 		if sides.len() != 2{
-			return Err(format!("Invalid mapping. Expected exactly one '=' character. '<from> = <to>'"));
+			return Err(vec![error_at(s, s.trim_start(), ErrorKind::Syntax(), "Invalid mapping. Expected exactly one '=' character. '<from> = <to>'".to_string())]);
 		}
 		let left = sides[0].trim();
 		let right = sides[1].trim();
-        let jdev = left.parse::<JDEv>();
-        let joyinput = right.parse::<JoyInput>();
-        // TODO: more helpful error messages with column numbers?
+        let mut errors = Vec::new();
+        let jdev = left.parse::<JDEv>().map_err(|e| errors.push(error_at(s, left, jdev_error_kind(left), e)));
+        let joyinput = right.parse::<JoyInput>().map_err(|e| errors.push(error_at(s, right, input_error_kind(right), e)));
         match (jdev, joyinput) {
             (Ok(jd), Ok(ji)) => Ok(Mapping{from: jd, to: ji}),
-            (Ok(_), Err(ji)) => Err(ji),
-            (Err(jd), Ok(_)) => Err(jd),
-            (Err(jd), Err(ji)) => Err(format!("{}, also {}", jd, ji)),
+            _ => Err(errors),
         }
+    }
+}
+
+impl FromStr for Mapping{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        Mapping::parse_located(s).map_err(join_errors)
 	}
 }
 
 #[derive(Debug,PartialEq)]
 pub struct TargetMapping{
     pub from: JoyInput,
-    pub to: Target,
+    pub to: Vec<Target>,
 }
 
-impl FromStr for TargetMapping{
+// Options that are set in the main config file with lines like: set keyboard_layout = de
+#[derive(Debug,Clone,PartialEq)]
+pub struct Settings{
+    pub keyboard_layout: String,
+    // battery percentage below which "on battery_low" fires
+    pub battery_low: u8,
+}
+
+impl Default for Settings{
+    fn default() -> Self {
+        Settings{
+            keyboard_layout: "us".to_string(),
+            battery_low: 20,
+        }
+    }
+}
+
+impl Settings{
+    pub const names: [&'static str; 2] = ["keyboard_layout", "battery_low"];
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name{
+            "keyboard_layout" => {
+                if value.is_empty(){
+                    return Err("keyboard_layout can't be empty".to_string());
+                }
+                self.keyboard_layout = value.to_string();
+            },
+            "battery_low" => {
+                self.battery_low = value.parse().ok().filter(|v| *v <= 100).ok_or(format!("battery_low should be a percentage from 0 to 100, not {}", value))?;
+            },
+            _ => return Err(format!("Unknown setting: {}", name)),
+        }
+        Ok(())
+    }
+}
+
+// Things that happen inside joy2uinput that can have targets attached, with lines like: on disabled = rumble(0.5,200)
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum Hook{
+    Enabled(),
+    Disabled(),
+    // the joypad's battery has dropped below the battery_low setting
+    BatteryLow(),
+}
+
+impl FromStr for Hook{
     type Err = String;
     fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
-		// syntax is: "JDEv = JoyInput"
-		let sides: Vec<_> = s.split("=").collect();
-		if sides.len() != 2{
-			return Err(format!("Invalid mapping. Expected exactly one '=' character. '<from> = <to>'"));
-		}
-		let left = sides[0].trim();
-		let right = sides[1].trim();
-        let joyinput = left.parse::<JoyInput>();
-        let target = right.parse::<Target>();
-        // TODO: more helpful error messages with column numbers?
-        match (joyinput, target) {
-            (Ok(ji), Ok(targ)) => Ok(TargetMapping{from: ji, to: targ}),
-            (Ok(_), Err(targ)) => Err(targ),
-            (Err(ji), Ok(_)) => Err(ji),
-            (Err(ji), Err(targ)) => Err(format!("{}, also {}", ji, targ)),
+        match s.trim().to_lowercase().as_ref(){
+            "enabled" => Ok(Hook::Enabled()),
+            "disabled" => Ok(Hook::Disabled()),
+            "battery_low" => Ok(Hook::BatteryLow()),
+            _ => Err(format!("Unknown event: {}", s.trim())),
+        }
+    }
+}
+
+impl Display for Hook{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self{
+            Hook::Enabled() => f.write_str("enabled"),
+            Hook::Disabled() => f.write_str("disabled"),
+            Hook::BatteryLow() => f.write_str("battery_low"),
+        }
+    }
+}
+
+// A line from the main config file
+#[derive(Debug,PartialEq)]
+pub enum ConfigLine{
+    Mapping(TargetMapping),
+    // set <name> = <value>
+    Setting(String, String),
+    // on <event> = <targets>
+    Hook(Hook, Vec<Target>),
+}
+
+// Hooks aren't a button press, so only the targets that make sense without one are allowed
+fn check_hook_target(t: &Target) -> Result<(), String> {
+    match t{
+        Target::Rumble(..) | Target::Type(_) | Target::KillLaunched() => Ok(()),
+        Target::Launch(l) if !l.kill_on_release => Ok(()),
+        _ => Err(format!("{} can't be used in an 'on' line, only rumble, type, launch (without kill_on_release) and kill_launched can", t)),
+    }
+}
+
+// The rest of the line if it starts with the keyword (in any case) and then some whitespace
+pub fn strip_keyword<'a>(t: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = t.get(keyword.len()..)?;
+    if t[..keyword.len()].eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace){
+        return Some(rest);
+    }
+    None
+}
+
+impl ConfigLine{
+    // Like parse, but says where in the line each problem is
+    pub fn parse_located(s: &str) -> Result<ConfigLine, Vec<LineError>> {
+        let t = s.trim();
+        if let Some(rest) = strip_keyword(t, "set"){
+            return match rest.split_once('='){
+                Some((name, value)) => {
+                    let (name, value) = (name.trim(), value.trim());
+                    let lower = name.to_lowercase();
+                    // the value is checked here so that a bad one is reported on the right line
+                    if let Err(e) = Settings::default().set(&lower, value){
+                        return Err(vec![if Settings::names.contains(&lower.as_str()){
+                            error_at(s, value, ErrorKind::BadSettingValue(), e)
+                        }
+                        else{
+                            error_at(s, name, ErrorKind::UnknownSetting(), e)
+                        }]);
+                    }
+                    Ok(ConfigLine::Setting(lower, value.to_string()))
+                },
+                None => Err(vec![error_at(s, t, ErrorKind::Syntax(), format!("Malformed setting: {}. Expected: set <name> = <value>", t))]),
+            };
+        }
+        if let Some(rest) = strip_keyword(t, "on"){
+            let (hook, targets) = rest.split_once('=').ok_or(vec![error_at(s, t, ErrorKind::Syntax(), format!("Malformed event line: {}. Expected: on <event> = <targets>", t))])?;
+            let hook = hook.parse::<Hook>().map_err(|e| vec![error_at(s, hook.trim(), ErrorKind::UnknownHook(), e)])?;
+            let targets = parse_targets(s, targets.trim())?;
+            let errors: Vec<LineError> = targets.iter().filter_map(|(offset, target)| check_hook_target(target).err().map(|message| LineError{offset: *offset, kind: ErrorKind::HookTarget(), message})).collect();
+            if !errors.is_empty(){
+                return Err(errors);
+            }
+            return Ok(ConfigLine::Hook(hook, targets.into_iter().map(|(_, t)| t).collect()));
+        }
+        Ok(ConfigLine::Mapping(TargetMapping::parse_located(s)?))
+    }
+}
+
+impl FromStr for ConfigLine{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        ConfigLine::parse_located(s).map_err(join_errors)
+    }
+}
+
+// Parses the target list s, which is part of line, each target comes with where it starts in line
+fn parse_targets(line: &str, s: &str) -> Result<Vec<(usize, Target)>, Vec<LineError>> {
+    let parts = split_targets(s).map_err(|e| vec![error_at(line, s, ErrorKind::Syntax(), e)])?;
+    let mut targets = Vec::new();
+    let mut errors = Vec::new();
+    for part in parts{
+        match part.parse::<Target>(){
+            Ok(t) => targets.push((offset_of(line, part), t)),
+            Err(e) => errors.push(error_at(line, part, target_error_kind(part), e)),
+        }
+    }
+    if !errors.is_empty(){
+        return Err(errors);
+    }
+    Ok(targets)
+}
+
+// Splits a list of targets on the commas that are not inside brackets or quotes
+fn split_targets(s: &str) -> Result<Vec<&str>, String> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut in_quote = false;
+    // single quotes only mean anything in launch arguments, key(') is just a key
+    let mut in_single = false;
+    let mut escape = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices(){
+        if escape{
+            escape = false;
+            continue;
+        }
+        if in_single{
+            in_single = c != '\'';
+            continue;
+        }
+        match c{
+            '\\' => { escape = true; },
+            '"' => { in_quote = !in_quote; },
+            '\'' if !in_quote && depth == 0 => { in_single = true; },
+            '(' if !in_quote => { depth += 1; },
+            ')' if !in_quote => { depth -= 1; },
+            ',' if !in_quote && depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
         }
+    }
+    parts.push(s[start..].trim());
+    if parts.iter().any(|p| p.is_empty()){
+        return Err(format!("Empty target in target list: {}", s));
+    }
+    Ok(parts)
+}
+
+impl Display for TargetMapping{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{} = ", self.from)?;
+        for (i, t) in self.to.iter().enumerate(){
+            if i > 0{
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", t)?;
+        }
+        Ok(())
+    }
+}
+
+impl TargetMapping{
+    // Like parse, but says where in the line each problem is
+    pub fn parse_located(s: &str) -> Result<TargetMapping, Vec<LineError>> {
+		// syntax is: "JoyInput = Target, Target, ..." (targets can contain '=' too, so split on the first one)
+		let (left, right) = match s.split_once('='){
+			Some(sides) => sides,
+			None => return Err(vec![error_at(s, s.trim_start(), ErrorKind::Syntax(), "Invalid mapping. Expected an '=' character. '<from> = <to>'".to_string())]),
+		};
+		let left = left.trim();
+		let right = right.trim();
+        let mut errors = Vec::new();
+        let joyinput = left.parse::<JoyInput>().map_err(|e| errors.push(error_at(s, left, input_error_kind(left), e)));
+        let targets = parse_targets(s, right).map_err(|e| errors.extend(e));
+        match (joyinput, targets) {
+            (Ok(ji), Ok(targ)) => Ok(TargetMapping{from: ji, to: targ.into_iter().map(|(_, t)| t).collect()}),
+            _ => Err(errors),
+        }
+    }
+}
+
+impl FromStr for TargetMapping{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, <Self as FromStr>::Err> {
+        TargetMapping::parse_located(s).map_err(join_errors)
 	}
 }
 
+// How far the axis is from its centre towards min (-1) or max (1). Inside the deadzone is 0,
+// and the rest of the range is stretched so that it starts from 0 at the edge of the deadzone.
+// min can be more than max, for axes that go the other way. A trigger rests at min, so it only goes from 0 to 1
+pub fn axis_position(value: i16, min: i16, max: i16, centre: i16, deadzone: u16) -> f32 {
+    let offset = value as f32 - centre as f32;
+    let (end, sign) = if offset * (max as f32 - centre as f32) > 0.0 {(max, 1.0)} else {(min, -1.0)};
+    let range = (end as f32 - centre as f32).abs();
+    let outside = offset.abs() - deadzone as f32;
+    // also 0 when it is past the end that it rests at
+    if outside <= 0.0 || range <= deadzone as f32 || offset * (end as f32 - centre as f32) <= 0.0{
+        return 0.0;
+    }
+    outside / (range - deadzone as f32) * sign
+}
+
 pub fn jpname_to_filename(jp: &str) -> OsString{
     let mut s = OsString::from(jp
         .replace("_", "___")
@@ -884,7 +1909,7 @@ pub fn jpname_to_filename(jp: &str) -> OsString{
 
 #[cfg(test)]
 mod test{
-    use crate::map_config::{TargetMapping,JoyInput,Target,KeyTarget,Button,Axis,AxisTarget,JDEv,Mapping};
+    use crate::map_config::{axis_position,TargetMapping,JoyInput,Target,KeyTarget,Button,Axis,AxisTarget,JDEv,Mapping,LaunchTarget,SingleInstance,Arg,ArgPart,Placeholder};
 
     #[test]
     fn test_name_conversion() {
@@ -897,7 +1922,9 @@ mod test{
             ("button(1)=a", "button(1) = a", Ok(Mapping{from:JDEv::Button(1),to:JoyInput::Button(Button::A())})),
             ("button(2)=lstick	   ", "button(2) = lstick", Ok(Mapping{from:JDEv::Button(2),to:JoyInput::Button(Button::LStick())})),
             ("axIS_As_buTToN(1, -32767) =  uP", "axis_as_button(1,-32767) = up", Ok(Mapping{from:JDEv::AxisAsButton(1,-32767),to:JoyInput::Button(Button::Up())})),
-            ("   axis(1,-32767,  32767) = leftx  ", "axis(1,-32767,32767) = leftx", Ok(Mapping{from:JDEv::Axis(1,-32767,32767),to:JoyInput::Axis(Axis::LeftX())})),
+            ("   axis(1,-32767,  32767) = leftx  ", "axis(1,-32767,32767) = leftx", Ok(Mapping{from:JDEv::Axis(1,-32767,32767,0,0),to:JoyInput::Axis(Axis::LeftX())})),
+            ("axis(2,-32767,32767, 0,0) = lefty", "axis(2,-32767,32767) = lefty", Ok(Mapping{from:JDEv::Axis(2,-32767,32767,0,0),to:JoyInput::Axis(Axis::LeftY())})),
+            ("axis(5, -32767, 32767, -32767, 900) = rightz", "axis(5,-32767,32767,-32767,900) = rightz", Ok(Mapping{from:JDEv::Axis(5,-32767,32767,-32767,900),to:JoyInput::Axis(Axis::RightZ())})),
         ];
         for (input, canonical, expected) in tests{
             let mapping = input.parse::<Mapping>();
@@ -909,20 +1936,134 @@ mod test{
     #[test]
     fn test_config_reading() {
         let tests = [
-            ("  A =key(a)", "a", Ok(TargetMapping{from:JoyInput::Button(Button::A()), to:Target::Key(KeyTarget::AlphaNum('a'))})),
-            ("Custom_button  (  1  ) =     key( b) ", "custom_button(1)", Ok(TargetMapping{from:JoyInput::Button(Button::Custom(1)), to:Target::Key(KeyTarget::AlphaNum('b'))})),
-            ("LeftX=axis(moUSex,2)", "leftx", Ok(TargetMapping{from:JoyInput::Axis(Axis::LeftX()), to:Target::Axis(AxisTarget::MouseX(2.0))})),
-            ("throttle=mousebutton(side)", "throttle", Ok(TargetMapping{from:JoyInput::Axis(Axis::Throttle()), to:Target::Key(KeyTarget::MouseButtonSide())})),
-            ("Roll=key(equals)", "roll", Ok(TargetMapping{from:JoyInput::Axis(Axis::Roll()), to:Target::Key(KeyTarget::AlphaNum('='))})),
-            ("rightx=toggle_enabled", "rightx", Ok(TargetMapping{from:JoyInput::Axis(Axis::RightX()), to:Target::ToggleEnabled()})),
-            ("righty=axis(scrolly,2)", "righty", Ok(TargetMapping{from:JoyInput::Axis(Axis::RightY()), to:Target::Axis(AxisTarget::ScrollY(2.0))})),
-            ("brake=axis(scrollx,1)", "brake", Ok(TargetMapping{from:JoyInput::Axis(Axis::Brake()), to:Target::Axis(AxisTarget::ScrollX(1.0))})),
+            ("  A =key(a)", "a", Ok(TargetMapping{from:JoyInput::Button(Button::A()), to:vec![Target::Key(KeyTarget::AlphaNum('a'))]})),
+            ("Custom_button  (  1  ) =     key( b) ", "custom_button(1)", Ok(TargetMapping{from:JoyInput::Button(Button::Custom(1)), to:vec![Target::Key(KeyTarget::AlphaNum('b'))]})),
+            ("LeftX=axis(moUSex,2)", "leftx", Ok(TargetMapping{from:JoyInput::Axis(Axis::LeftX()), to:vec![Target::Axis(AxisTarget::MouseX(2.0))]})),
+            ("throttle=mousebutton(side)", "throttle", Ok(TargetMapping{from:JoyInput::Axis(Axis::Throttle()), to:vec![Target::Key(KeyTarget::MouseButtonSide())]})),
+            ("Roll=key(equals)", "roll", Ok(TargetMapping{from:JoyInput::Axis(Axis::Roll()), to:vec![Target::Key(KeyTarget::AlphaNum('='))]})),
+            ("rightx=toggle_enabled", "rightx", Ok(TargetMapping{from:JoyInput::Axis(Axis::RightX()), to:vec![Target::ToggleEnabled()]})),
+            ("righty=axis(scrolly,2)", "righty", Ok(TargetMapping{from:JoyInput::Axis(Axis::RightY()), to:vec![Target::Axis(AxisTarget::ScrollY(2.0))]})),
+            ("brake=axis(scrollx,1)", "brake", Ok(TargetMapping{from:JoyInput::Axis(Axis::Brake()), to:vec![Target::Axis(AxisTarget::ScrollX(1.0))]})),
+            ("x=raw_key(KEY_PLAYPAUSE)", "x", Ok(TargetMapping{from:JoyInput::Button(Button::X()), to:vec![Target::Key(KeyTarget::Code(evdev::Key::KEY_PLAYPAUSE))]})),
+            ("z=raw_key(656)", "z", Ok(TargetMapping{from:JoyInput::Button(Button::Z()), to:vec![Target::Key(KeyTarget::Code(evdev::Key::new(656)))]})),
+            ("y=raw_key(2)", "y", Ok(TargetMapping{from:JoyInput::Button(Button::Y()), to:vec![Target::Key(KeyTarget::Code(evdev::Key::KEY_1))]})),
+            ("rightz=raw_rel(rel_dial, -1.5)", "rightz", Ok(TargetMapping{from:JoyInput::Axis(Axis::RightZ()), to:vec![Target::Axis(AxisTarget::Raw(evdev::RelativeAxisType::REL_DIAL, -1.5))]})),
+            ("leftz=raw_rel(9,2)", "leftz", Ok(TargetMapping{from:JoyInput::Axis(Axis::LeftZ()), to:vec![Target::Axis(AxisTarget::Raw(evdev::RelativeAxisType::REL_MISC, 2.0))]})),
+            ("throttle=raw_abs(ABS_THROTTLE, 0, 255)", "throttle", Ok(TargetMapping{from:JoyInput::Axis(Axis::Throttle()), to:vec![Target::RawAbs(evdev::AbsoluteAxisType::ABS_THROTTLE, 0, 255)]})),
+            // codes that evdev has no name for
+            ("yaw=raw_rel(10,1)", "yaw", Ok(TargetMapping{from:JoyInput::Axis(Axis::Yaw()), to:vec![Target::Axis(AxisTarget::Raw(evdev::RelativeAxisType(10), 1.0))]})),
+            ("pitch=raw_abs(41,-2147483648,2147483647)", "pitch", Ok(TargetMapping{from:JoyInput::Axis(Axis::Pitch()), to:vec![Target::RawAbs(evdev::AbsoluteAxisType(41), i32::MIN, i32::MAX)]})),
+            ("home=toggle_enabled, rumble(0.25, 150)", "home", Ok(TargetMapping{from:JoyInput::Button(Button::Home()), to:vec![Target::ToggleEnabled(), Target::Rumble(0.25, 150)]})),
+            ("start=type(\"hello, \\\"wörld\\\"\\n\")", "start", Ok(TargetMapping{from:JoyInput::Button(Button::Start()), to:vec![Target::Type("hello, \"wörld\"\n".to_string())]})),
+            ("select=launch(single=focus, kill_on_release, log=/tmp/game.log) game --fullscreen", "select", Ok(TargetMapping{from:JoyInput::Button(Button::Select()), to:vec![Target::Launch(LaunchTarget{args:vec![Arg::from("game"), Arg::from("--fullscreen")], single:Some(SingleInstance::Focus), kill_on_release:true, log:Some(Arg::from("/tmp/game.log")), ..Default::default()})]})),
+            ("menu=Launch(single=IGNORE,kill_on_release=false)menu.sh, kill_launched", "menu", Ok(TargetMapping{from:JoyInput::Button(Button::Menu()), to:vec![Target::Launch(LaunchTarget{args:vec![Arg::from("menu.sh")], single:Some(SingleInstance::Ignore), ..Default::default()}), Target::KillLaunched()]})),
+            ("a = key(enter), launch beep.sh", "a", Ok(TargetMapping{from:JoyInput::Button(Button::A()), to:vec![Target::Key(KeyTarget::Return()), Target::Launch(LaunchTarget{args:vec![Arg::from("beep.sh")], ..Default::default()})]})),
+            ("home=toggle_enabled ,key(comma),launch say \"a, b\" c", "home", Ok(TargetMapping{from:JoyInput::Button(Button::Home()), to:vec![Target::ToggleEnabled(), Target::Key(KeyTarget::AlphaNum(',')), Target::Launch(LaunchTarget{args:vec![Arg::from("say"), Arg::from("a, b"), Arg::from("c")], ..Default::default()})]})),
+            ("lstick=launch say 'a, \\b', key(')", "lstick", Ok(TargetMapping{from:JoyInput::Button(Button::LStick()), to:vec![Target::Launch(LaunchTarget{args:vec![Arg::from("say"), Arg::from("a, \\b")], ..Default::default()}), Target::Key(KeyTarget::AlphaNum('\''))]})),
+            ("b=launch(cwd=~/games, env=SDL_VIDEODRIVER=x11, env=NAME=\"a b\") run 'it''s $HOME' \"$HOME/${XDG}x\" --pad=%device% \\$1", "b", Ok(TargetMapping{from:JoyInput::Button(Button::B()), to:vec![Target::Launch(LaunchTarget{
+                args:vec![
+                    Arg::from("run"),
+                    Arg::from("its $HOME"),
+                    Arg(vec![ArgPart::Var("HOME".to_string()), ArgPart::Text("/".to_string()), ArgPart::Var("XDG".to_string()), ArgPart::Text("x".to_string())]),
+                    Arg(vec![ArgPart::Text("--pad=".to_string()), ArgPart::Placeholder(Placeholder::Device)]),
+                    Arg::from("$1"),
+                ],
+                cwd:Some(Arg::from("~/games")),
+                env:vec![("SDL_VIDEODRIVER".to_string(), Arg::from("x11")), ("NAME".to_string(), Arg::from("a b"))],
+                ..Default::default()
+            })]})),
+            ("a=launch(env=K=\"a\tb\") echo \"a\tb\"", "a", Ok(TargetMapping{from:JoyInput::Button(Button::A()), to:vec![Target::Launch(LaunchTarget{
+                args:vec![Arg::from("echo"), Arg::from("a\tb")],
+                env:vec![("K".to_string(), Arg::from("a\tb"))],
+                ..Default::default()
+            })]})),
+            ("x=launch(shell=true) notify-send \"player %player%\" | tee /tmp/log, key(x)", "x", Ok(TargetMapping{from:JoyInput::Button(Button::X()), to:vec![Target::Launch(LaunchTarget{
+                args:vec![Arg(vec![ArgPart::Text("notify-send \"player ".to_string()), ArgPart::Placeholder(Placeholder::Player), ArgPart::Text("\" | tee /tmp/log".to_string())])],
+                shell:true,
+                ..Default::default()
+            }), Target::Key(KeyTarget::AlphaNum('x'))]})),
         ];
         for (input, canonical, expected) in tests{
             let mapping = input.parse::<TargetMapping>();
             assert_eq!(mapping, expected, "{}", input);
             let mapping = mapping.unwrap();
             assert_eq!(format!("{}", mapping.from), canonical, "{}", input);
+            // writing it back out must give something that reads back the same
+            let written = format!("{}", mapping);
+            assert_eq!(written.parse::<TargetMapping>(), Ok(mapping), "{}", written);
+        }
+    }
+
+    #[test]
+    fn test_key_targets() {
+        use evdev::Key;
+        let tests = [
+            ("key(a)", Key::KEY_A),
+            ("key(1)", Key::KEY_1),
+            ("key(playpause)", Key::KEY_PLAYPAUSE),
+            ("key(Prev)", Key::KEY_PREVIOUSSONG),
+            ("key(brightnessup)", Key::KEY_BRIGHTNESSUP),
+            ("key(browserback)", Key::KEY_BACK),
+            ("key(numpadplus)", Key::KEY_KPPLUS),
+            ("key(`)", Key::KEY_GRAVE),
+            ("key(KEY_PLAYPAUSE)", Key::KEY_PLAYPAUSE),
+            ("key(btn_tl)", Key::BTN_TL),
+            ("key(KEY_F)", Key::KEY_F),
+            ("key(30)", Key::KEY_A),
+            ("key(02)", Key::KEY_1),
+        ];
+        for (input, expected) in tests{
+            assert_eq!(input.parse::<KeyTarget>().unwrap().uinput_key(), expected, "{}", input);
+        }
+        for bad in ["key(KEY_FOOBAR)", "key(768)", "key(99999)", "key(BTN_)"]{
+            assert!(bad.parse::<KeyTarget>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_list_targets() {
+        let targets = crate::map_config::list_targets();
+        for t in ["key(return) or key(enter)", "key(playpause)", "key(KEY_PLAYPAUSE)", "key(BTN_TL)", "toggle_enabled"]{
+            assert!(targets.iter().any(|l| l == t), "{}", t);
+        }
+        // everything listed as a key should parse as one
+        for line in targets.iter().filter(|l| l.starts_with("key(")){
+            for name in line.split(" or "){
+                let k = name.parse::<KeyTarget>().unwrap();
+                assert_ne!(k.uinput_key(), evdev::Key::KEY_RESERVED, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_settings() {
+        use crate::map_config::{ConfigLine,Settings};
+        assert_eq!("set keyboard_layout = de(nodeadkeys)".parse::<ConfigLine>(), Ok(ConfigLine::Setting("keyboard_layout".to_string(), "de(nodeadkeys)".to_string())));
+        assert_eq!("SET\tKeyboard_Layout=fr".parse::<ConfigLine>(), Ok(ConfigLine::Setting("keyboard_layout".to_string(), "fr".to_string())));
+        assert!(matches!("select=key(tab)".parse::<ConfigLine>(), Ok(ConfigLine::Mapping(_))));
+        assert!("set keyboard_layout".parse::<ConfigLine>().is_err());
+        let mut settings = Settings::default();
+        assert_eq!(settings.keyboard_layout, "us");
+        assert!(settings.set("keyboard_layout", "de").is_ok());
+        assert_eq!(settings.keyboard_layout, "de");
+        assert!(settings.set("keyboard_layout", "").is_err());
+        assert!(settings.set("nope", "1").is_err());
+        assert_eq!(settings.battery_low, 20);
+        assert!(settings.set("battery_low", "15").is_ok());
+        assert_eq!(settings.battery_low, 15);
+        for bad in ["", "-1", "101", "ten"]{
+            assert!(settings.set("battery_low", bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        use crate::map_config::{ConfigLine,Hook};
+        assert_eq!("on disabled = rumble(0.5, 200)".parse::<ConfigLine>(), Ok(ConfigLine::Hook(Hook::Disabled(), vec![Target::Rumble(0.5, 200)])));
+        assert_eq!("on Battery_Low = rumble(1,1000)".parse::<ConfigLine>(), Ok(ConfigLine::Hook(Hook::BatteryLow(), vec![Target::Rumble(1.0, 1000)])));
+        assert_eq!("ON\tEnabled=rumble(1,100), launch notify-send enabled".parse::<ConfigLine>(), Ok(ConfigLine::Hook(Hook::Enabled(), vec![Target::Rumble(1.0, 100), Target::Launch(LaunchTarget{args:vec![Arg::from("notify-send"), Arg::from("enabled")], ..Default::default()})])));
+        for bad in ["on enabled", "on = rumble(1,100)", "on nope = rumble(1,100)", "on enabled = toggle_enabled", "on disabled = key(a)", "on enabled = launch(kill_on_release) foo"]{
+            assert!(bad.parse::<ConfigLine>().is_err(), "{}", bad);
         }
     }
 
@@ -959,6 +2100,44 @@ mod test{
             "custom_axis(1)=axis(foo)",
             "custom_axis[1]=axis(mousex,foo)",
             "custom_axis(1)=axis(foo,2)",
+            "a=key(a),",
+            "a=type(hello)",
+            "a=launch",
+            "a=launch ",
+            "a=launch(single=focus)",
+            "a=launch(single=twice) foo",
+            "a=launch(kill_on_release=maybe) foo",
+            "a=launch(log=) foo",
+            "a=launch(nope) foo",
+            "a=launch(single=focus foo",
+            "a=launch(env=FOO) foo",
+            "a=launch(env==x) foo",
+            "a=launch(cwd=) foo",
+            "a=launch(shell=yes) foo",
+            "a=launch foo 'bar",
+            "a=launch foo \"bar",
+            "a=launch foo bar\\",
+            "a=launch foo ${BAR",
+            "a=rumble(1)",
+            "a=rumble(1.5,100)",
+            "a=rumble(-1,100)",
+            "a=rumble(1,0)",
+            "a=rumble(1,70000)",
+            "a=rumble(1,1.5)",
+            "a=type(\"a\\q\")",
+            "a=type(\"a\"b\")",
+            "a=type(\"a\\\")",
+            "a=raw_key(KEY_NOPE)",
+            "a=raw_key(768)",
+            "a=raw_rel(REL_X)",
+            "a=raw_rel(16,1)",
+            "a=raw_rel(REL_X,fast)",
+            "a=raw_abs(ABS_X,0)",
+            "a=raw_abs(64,0,1)",
+            "a=raw_abs(ABS_X,0,1.5)",
+            "a=,key(a)",
+            "a=key(a),,key(b)",
+            "a=key(a),key(foo)",
         ];
         for t in badtests{
             assert!(t.parse::<TargetMapping>().is_err(), "{}", t);
@@ -1100,4 +2279,31 @@ mod test{
             }
         }
     }
+
+    #[test]
+    fn test_axis_position(){
+        // without a centre or deadzone, this is how it always worked
+        assert_eq!(axis_position(50, -100, 200, 0, 0), 0.25);
+        assert_eq!(axis_position(100, -100, 200, 0, 0), 0.5);
+        assert_eq!(axis_position(-100, -100, 200, 0, 0), -1.0);
+        // a stick that rests off centre
+        assert_eq!(axis_position(10, -100, 100, 10, 0), 0.0);
+        assert_eq!(axis_position(55, -100, 100, 10, 0), 0.5);
+        // the deadzone
+        assert_eq!(axis_position(15, -100, 100, 0, 20), 0.0);
+        assert_eq!(axis_position(-20, -100, 100, 0, 20), 0.0);
+        assert_eq!(axis_position(60, -100, 100, 0, 20), 0.5);
+        assert_eq!(axis_position(-100, -100, 100, 0, 20), -1.0);
+        // a trigger that rests at one end
+        assert_eq!(axis_position(-100, -100, 100, -100, 0), 0.0);
+        assert_eq!(axis_position(0, -100, 100, -100, 0), 0.5);
+        assert_eq!(axis_position(-150, -100, 100, -100, 0), 0.0);
+        // axes that go the other way
+        assert_eq!(axis_position(-100, 100, -100, 0, 0), 1.0);
+        assert_eq!(axis_position(50, 100, -100, 0, 0), -0.5);
+        // and a trigger that rests at the top end, going down when pressed
+        assert_eq!(axis_position(100, 100, -100, 100, 0), 0.0);
+        assert_eq!(axis_position(0, 100, -100, 100, 0), 0.5);
+        assert_eq!(axis_position(-100, 100, -100, 100, 0), 1.0);
+    }
 }
@@ -0,0 +1,138 @@
+/*
+
+What does this file do?
+
+    Makes joypads rumble. The joydev interface (/dev/input/jsN) that the rest of
+    joy2uinput reads from can't do force feedback, so this finds the evdev device
+    (/dev/input/eventN) that belongs to the same joypad by looking in sysfs, and
+    plays rumble effects through that instead.
+
+*/
+
+use evdev::{Device, FFEffect, FFEffectData, FFEffectKind, FFEffectType, FFReplay, FFTrigger};
+use std::path::{Path, PathBuf};
+
+// Finds the evdev node for a joydev node, e.g. /dev/input/js0 -> /dev/input/event5
+// Both are children of the same input device in sysfs: /sys/class/input/js0/device/event5
+pub fn event_node(sysfs: &Path, js: &Path) -> Option<PathBuf> {
+    let mut dir = sysfs.join("class/input");
+    dir.push(js.file_name()?);
+    dir.push("device");
+    let mut events: Vec<u32> = std::fs::read_dir(dir).ok()?.flatten()
+        .filter_map(|e| e.file_name().to_str()?.strip_prefix("event")?.parse().ok())
+        .collect();
+    events.sort();
+    events.first().map(|n| PathBuf::from(format!("/dev/input/event{}", n)))
+}
+
+pub struct Rumbler{
+    device: Device,
+    // uploaded on the first rumble, then changed for each one after that
+    effect: Option<FFEffect>,
+}
+
+impl std::fmt::Debug for Rumbler{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "Rumbler({:?})", self.device.name())
+    }
+}
+
+impl Rumbler{
+    pub fn open(path: &Path) -> std::io::Result<Rumbler> {
+        let device = Device::open(path)?;
+        if !device.supported_ff().is_some_and(|ff| ff.contains(FFEffectType::FF_RUMBLE)){
+            return Err(std::io::Error::other(format!("{} doesn't support rumble", path.display())));
+        }
+        Ok(Rumbler{device, effect: None})
+    }
+
+    // strength is from 0 to 1
+    pub fn rumble(&mut self, strength: f32, duration_ms: u16) -> std::io::Result<()> {
+        let magnitude = (strength.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+        let data = FFEffectData{
+            direction: 0,
+            trigger: FFTrigger::default(),
+            replay: FFReplay{length: duration_ms, delay: 0},
+            kind: FFEffectKind::Rumble{strong_magnitude: magnitude, weak_magnitude: magnitude},
+        };
+        let effect = match self.effect.take(){
+            Some(mut effect) => {
+                effect.update(data)?;
+                effect
+            },
+            None => self.device.upload_ff_effect(data)?,
+        };
+        self.effect.insert(effect).play(1)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::rumble::{event_node, Rumbler};
+    use evdev::{FFEffectKind, InputEventKind, UInputEventType};
+    use serial_test::serial;
+    use std::path::{Path, PathBuf};
+    use tempdir::TempDir;
+
+    // A joypad that advertises EV_FF, so rumble effects can be uploaded to it
+    fn new_virtual_rumble_joypad(name: &str) -> evdev::uinput::VirtualDevice {
+        let mut keys = evdev::AttributeSet::new();
+        keys.insert(evdev::Key::BTN_TRIGGER);
+        let mut ff = evdev::AttributeSet::new();
+        ff.insert(evdev::FFEffectType::FF_RUMBLE);
+
+        evdev::uinput::VirtualDeviceBuilder::new()
+            .expect("Failed to make uinput device builder")
+            .name(name)
+            .with_keys(&keys).expect("Failed to add buttons to virtual joystick device")
+            .with_ff(&ff).expect("Failed to add force feedback to virtual joystick device")
+            .with_ff_effects_max(1)
+            .build().expect("Failed to build virtual joystick device")
+    }
+
+    #[test]
+    fn test_event_node(){
+        let sysfs = TempDir::new("sysfs").unwrap();
+        let device = sysfs.path().join("class/input/js1/device");
+        for d in ["event12", "event3", "js1", "power", "eventful"]{
+            std::fs::create_dir_all(device.join(d)).unwrap();
+        }
+        assert_eq!(event_node(sysfs.path(), Path::new("/dev/input/js1")), Some(PathBuf::from("/dev/input/event3")));
+        assert_eq!(event_node(sysfs.path(), Path::new("/dev/input/js0")), None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_rumble(){
+        let mut pad = new_virtual_rumble_joypad("testing_rumble_joystick");
+        let node = pad.enumerate_dev_nodes_blocking().unwrap().flatten().find(|p| p.to_string_lossy().contains("event")).expect("virtual joypad has no event node");
+
+        // the kernel waits for the virtual device to accept each upload, so that has to happen on another thread
+        let (send, recv) = std::sync::mpsc::channel();
+        std::thread::spawn(move ||{
+            loop{
+                let events: Vec<_> = pad.fetch_events().unwrap().collect();
+                for ev in events{
+                    match ev.kind(){
+                        InputEventKind::UInput(code) if code == UInputEventType::UI_FF_UPLOAD.0 => {
+                            let upload = pad.process_ff_upload(ev).unwrap();
+                            if send.send((upload.effect().kind, upload.effect().replay.length)).is_err(){
+                                return;
+                            }
+                        },
+                        InputEventKind::UInput(code) if code == UInputEventType::UI_FF_ERASE.0 => {
+                            pad.process_ff_erase(ev).unwrap();
+                        },
+                        _ => {},
+                    }
+                }
+            }
+        });
+
+        let mut rumbler = Rumbler::open(&node).unwrap();
+        rumbler.rumble(0.5, 200).unwrap();
+        assert_eq!(recv.recv().unwrap(), (FFEffectKind::Rumble{strong_magnitude: 32768, weak_magnitude: 32768}, 200));
+        rumbler.rumble(1.0, 50).unwrap();
+        assert_eq!(recv.recv().unwrap(), (FFEffectKind::Rumble{strong_magnitude: 65535, weak_magnitude: 65535}, 50));
+    }
+}
@@ -0,0 +1,483 @@
+/*
+
+What does this file do?
+
+    Decides what joy2u-mapgen asks for, and in what order. Every button and axis
+    that the mapping files know about is in here somewhere, in groups of things
+    that pads tend to either have all of or none of, so that a whole group can be
+    skipped at once. Physical buttons that are left over at the end can then be
+    kept as custom buttons.
+    A template can ask for something else instead. Templates have one input per
+    line, optionally followed by "= <what to tell the user>", and "group <name>"
+    lines to start a new group. Some templates are built in.
+    A Session keeps track of what has been mapped so far, so that mistakes can be
+    undone, or any input can be mapped again, without starting over.
+
+*/
+
+use joy2uinput::config_file::{parse_lines, ParseError};
+use joy2uinput::map_config::{error_at, input_error_kind, strip_keyword, Axis, Button, JDEv, JoyInput, LineError, Mapping};
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Prompt{
+    pub input: JoyInput,
+    pub group: String,
+    // extra help, such as which of several identical looking buttons is meant
+    pub text: Option<String>,
+}
+
+pub const builtin_templates: [(&str, &str); 4] = [
+    ("snes", include_str!("../templates/snes.j2utemplate")),
+    ("xbox", include_str!("../templates/xbox.j2utemplate")),
+    ("arcade-stick", include_str!("../templates/arcade-stick.j2utemplate")),
+    ("flight-stick", include_str!("../templates/flight-stick.j2utemplate")),
+];
+
+const groups: [(&str, &[JoyInput]); 10] = [
+    ("d-pad", &[
+        JoyInput::Button(Button::Up()),
+        JoyInput::Button(Button::Down()),
+        JoyInput::Button(Button::Left()),
+        JoyInput::Button(Button::Right()),
+    ]),
+    ("face buttons", &[
+        JoyInput::Button(Button::A()),
+        JoyInput::Button(Button::B()),
+        JoyInput::Button(Button::X()),
+        JoyInput::Button(Button::Y()),
+    ]),
+    ("extra face buttons", &[
+        JoyInput::Button(Button::C()),
+        JoyInput::Button(Button::Z()),
+        JoyInput::Button(Button::W()),
+        JoyInput::Button(Button::D()),
+    ]),
+    ("start and select", &[
+        JoyInput::Button(Button::Start()),
+        JoyInput::Button(Button::Select()),
+    ]),
+    ("menu buttons", &[
+        JoyInput::Button(Button::Home()),
+        JoyInput::Button(Button::Menu()),
+        JoyInput::Button(Button::Plus()),
+        JoyInput::Button(Button::Minus()),
+    ]),
+    ("shoulder buttons", &[
+        JoyInput::Button(Button::RShoulder()),
+        JoyInput::Button(Button::LShoulder()),
+        JoyInput::Button(Button::RTrigger()),
+        JoyInput::Button(Button::LTrigger()),
+    ]),
+    ("analog sticks", &[
+        JoyInput::Axis(Axis::LeftX()),
+        JoyInput::Axis(Axis::LeftY()),
+        JoyInput::Axis(Axis::RightX()),
+        JoyInput::Axis(Axis::RightY()),
+        JoyInput::Button(Button::LStick()),
+        JoyInput::Button(Button::RStick()),
+    ]),
+    ("analog triggers", &[
+        JoyInput::Axis(Axis::LeftZ()),
+        JoyInput::Axis(Axis::RightZ()),
+    ]),
+    ("flight and driving controls", &[
+        JoyInput::Axis(Axis::Throttle()),
+        JoyInput::Axis(Axis::Brake()),
+        JoyInput::Axis(Axis::Roll()),
+        JoyInput::Axis(Axis::Pitch()),
+        JoyInput::Axis(Axis::Yaw()),
+    ]),
+    ("scroll wheels", &[
+        JoyInput::Axis(Axis::ScrollX()),
+        JoyInput::Axis(Axis::ScrollY()),
+        JoyInput::Axis(Axis::ScrollZ()),
+    ]),
+];
+
+pub fn default_sequence() -> Vec<Prompt> {
+    groups.iter().flat_map(|(group, inputs)|
+        inputs.iter().map(|input| Prompt{input: input.clone(), group: group.to_string(), text: None})
+    ).collect()
+}
+
+pub fn builtin_template(name: &str) -> Option<&'static str> {
+    builtin_templates.iter().find(|(n, _)| *n == name).map(|(_, t)| *t)
+}
+
+enum TemplateLine{
+    Group(String),
+    Prompt(JoyInput, Option<String>),
+}
+
+fn parse_template_line(s: &str) -> Result<TemplateLine, Vec<LineError>> {
+    let t = s.trim();
+    if let Some(name) = strip_keyword(t, "group"){
+        return Ok(TemplateLine::Group(name.trim().to_string()));
+    }
+    let (input, text) = match t.split_once('='){
+        Some((input, text)) => (input.trim(), Some(text.trim())),
+        None => (t, None),
+    };
+    let input = input.parse::<JoyInput>().map_err(|e| vec![error_at(s, input, input_error_kind(input), e)])?;
+    Ok(TemplateLine::Prompt(input, text.filter(|t| !t.is_empty()).map(|t| t.to_string())))
+}
+
+// Inputs before the first group line are in a group with no name
+pub fn parse_template(text: &str) -> Result<Vec<Prompt>, Vec<ParseError>> {
+    let mut group = String::new();
+    let mut seq = Vec::new();
+    for line in parse_lines(text, parse_template_line)?{
+        match line{
+            TemplateLine::Group(name) => { group = name; },
+            TemplateLine::Prompt(input, text) => { seq.push(Prompt{input, group: group.clone(), text}); },
+        }
+    }
+    Ok(seq)
+}
+
+// The index of the first prompt after the group that prompt i is in
+pub fn end_of_group(seq: &[Prompt], i: usize) -> usize {
+    let group = &seq[i].group;
+    i + seq[i..].iter().take_while(|p| &p.group == group).count()
+}
+
+// Physical buttons that nothing was mapped from, each with the custom button it would be.
+// The custom button has the same number as the physical one if that is free, so that it
+// matches what `joy2u-mapgen --debug` shows, otherwise it gets the next free number.
+pub fn leftover_buttons(n_buttons: u8, mappings: &[Mapping]) -> Vec<(u8, JoyInput)> {
+    let mut used: Vec<u128> = mappings.iter().filter_map(|m| match m.to{
+        JoyInput::Button(Button::Custom(n)) => Some(n),
+        _ => None,
+    }).collect();
+    let mut leftover = Vec::new();
+    for b in 0..n_buttons{
+        if mappings.iter().any(|m| m.from == JDEv::Button(b)){
+            continue;
+        }
+        let mut n = b as u128;
+        while used.contains(&n){
+            n += 1;
+        }
+        used.push(n);
+        leftover.push((b, JoyInput::Button(Button::Custom(n))));
+    }
+    leftover
+}
+
+// A trigger rests at one end of the axis, which is written as its min
+pub fn is_trigger(a: &JDEv) -> bool {
+    matches!(a, JDEv::Axis(_, min, _, centre, _) if min == centre && *min != 0)
+}
+
+// Whether two things from a joypad come from the same control. Different positions on
+// one axis can each be a button, but the whole axis can't also be an axis, unless it is a
+// trigger, which can be both an analog axis and a button for pressing it all the way
+pub fn same_control(a: &JDEv, b: &JDEv) -> bool {
+    match (a, b){
+        (JDEv::Button(x), JDEv::Button(y)) => x == y,
+        (JDEv::AxisAsButton(x, v), JDEv::AxisAsButton(y, w)) => x == y && v == w,
+        (JDEv::Axis(x, ..), JDEv::Axis(y, ..)) => x == y,
+        (t @ JDEv::Axis(x, ..), JDEv::AxisAsButton(y, _)) | (JDEv::AxisAsButton(y, _), t @ JDEv::Axis(x, ..)) => x == y && !is_trigger(t),
+        _ => false,
+    }
+}
+
+fn opposite(input: &JoyInput) -> Option<JoyInput> {
+    match input{
+        JoyInput::Button(Button::Up()) => Some(JoyInput::Button(Button::Down())),
+        JoyInput::Button(Button::Down()) => Some(JoyInput::Button(Button::Up())),
+        JoyInput::Button(Button::Left()) => Some(JoyInput::Button(Button::Right())),
+        JoyInput::Button(Button::Right()) => Some(JoyInput::Button(Button::Left())),
+        _ => None,
+    }
+}
+
+// the prompt that was being asked for when something changed, and what was in mapped before the change
+type Change = (usize, Vec<(usize, Option<JDEv>)>);
+
+pub struct Session{
+    pub seq: Vec<Prompt>,
+    // what each prompt in seq was mapped from, if anything
+    pub mapped: Vec<Option<JDEv>>,
+    // the prompt being asked for, this is seq.len() when everything has been asked for
+    pub next: usize,
+    history: Vec<Change>,
+    // after mapping something again, carry on from here instead of from the one after it
+    resume_at: Option<usize>,
+}
+
+impl Session{
+    pub fn new(seq: Vec<Prompt>) -> Self {
+        let mapped = vec![None; seq.len()];
+        Session{seq, mapped, next: 0, history: Vec::new(), resume_at: None}
+    }
+
+    pub fn current(&self) -> Option<&Prompt> {
+        self.seq.get(self.next)
+    }
+
+    fn change(&mut self, values: Vec<Option<JDEv>>, unmap: &[usize]){
+        let start = self.next;
+        let mut before = Vec::new();
+        for (i, value) in (start..).zip(values).chain(unmap.iter().map(|i| (*i, None))){
+            before.push((i, self.mapped[i].clone()));
+            self.mapped[i] = value;
+        }
+        let end = start + before.len() - unmap.len();
+        self.history.push((start, before));
+        self.next = self.resume_at.take().unwrap_or(end);
+    }
+
+    // The prompts (other than the current one) that are already mapped from the same control as from
+    pub fn clashes(&self, from: &JDEv) -> Vec<usize> {
+        self.mapped.iter().enumerate().filter(|(i, m)| *i != self.next && m.as_ref().is_some_and(|m| same_control(m, from))).map(|(i, _)| i).collect()
+    }
+
+    pub fn assign(&mut self, from: JDEv){
+        self.change(vec![Some(from)], &[]);
+    }
+
+    // Like assign, but takes the control away from anything else that it was mapped to
+    pub fn reassign(&mut self, from: JDEv){
+        let clashes = self.clashes(&from);
+        self.change(vec![Some(from)], &clashes);
+    }
+
+    pub fn skip(&mut self){
+        self.change(vec![None], &[]);
+    }
+
+    pub fn skip_group(&mut self){
+        let n = end_of_group(&self.seq, self.next) - self.next;
+        self.change(vec![None; n], &[]);
+    }
+
+    // Puts back whatever the last change replaced, and goes back to ask for it again.
+    // Undoing from the end comes back to the end afterwards, otherwise it carries on from there
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop(){
+            Some((start, before)) => {
+                self.resume_at = if self.next >= self.seq.len() {Some(self.seq.len())} else {None};
+                for (i, m) in before.into_iter().rev(){
+                    self.mapped[i] = m;
+                }
+                self.next = start;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Asks for seq[i] again, and then comes back to where it was
+    pub fn jump(&mut self, i: usize){
+        if self.resume_at.is_none(){
+            self.resume_at = Some(self.next);
+        }
+        self.next = i;
+    }
+
+    pub fn add_leftovers(&mut self, leftover: Vec<(u8, JoyInput)>){
+        for (b, input) in leftover{
+            self.seq.push(Prompt{input, group: "leftover buttons".to_string(), text: None});
+            self.mapped.push(Some(JDEv::Button(b)));
+        }
+        self.next = self.seq.len();
+    }
+
+    // Starts from a mapping file that was already there. Each mapping fills the first prompt for
+    // its input that isn't mapped yet, and anything else is added on the end so that it is kept
+    pub fn load(&mut self, mappings: &[&Mapping]){
+        for m in mappings{
+            match (0..self.seq.len()).find(|i| self.seq[*i].input == m.to && self.mapped[*i].is_none()){
+                Some(i) => { self.mapped[i] = Some(m.from.clone()); },
+                None => {
+                    self.seq.push(Prompt{input: m.to.clone(), group: "already mapped".to_string(), text: None});
+                    self.mapped.push(Some(m.from.clone()));
+                },
+            }
+        }
+        self.history.clear();
+        self.resume_at = None;
+        self.next = self.seq.len();
+    }
+
+    // A d-pad that is a hat gives a position on one axis for each direction, the opposite
+    // direction should be the other end of the same axis. Says if prompt i doesn't match up
+    pub fn hat_warning(&self, i: usize) -> Option<String> {
+        let (n, v) = match self.mapped[i]{
+            Some(JDEv::AxisAsButton(n, v)) => (n, v),
+            _ => return None,
+        };
+        let opposite = opposite(&self.seq[i].input)?;
+        let j = self.seq.iter().position(|p| p.input == opposite)?;
+        match &self.mapped[j]{
+            Some(JDEv::AxisAsButton(m, w)) if *m == n && w.signum() == -v.signum() => None,
+            Some(other) => Some(format!("{} is {}, but {} is {}. These are usually opposite ends of the same axis.", self.seq[i].input, JDEv::AxisAsButton(n, v), opposite, other)),
+            None => None,
+        }
+    }
+
+    // Everything mapped so far, sorted by what it is mapped to
+    pub fn mappings(&self) -> Vec<Mapping> {
+        let mut mappings: Vec<Mapping> = self.seq.iter().zip(&self.mapped).filter_map(|(p, m)| m.clone().map(|from| Mapping{from, to: p.input.clone()})).collect();
+        mappings.sort_by(|a, b| (&a.to, &a.from).cmp(&(&b.to, &b.from)));
+        mappings
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::sequence::{builtin_template, builtin_templates, default_sequence, end_of_group, leftover_buttons, parse_template, same_control, Prompt, Session};
+    use joy2uinput::config_file::parse_mapping_file;
+    use joy2uinput::map_config::{Button, JDEv, JoyInput};
+
+    #[test]
+    fn test_sequence(){
+        let seq = default_sequence();
+        // everything is asked for exactly once
+        for s in ["up", "down", "left", "right", "start", "select", "a", "b", "c", "d", "w", "x", "y", "z",
+                "lshoulder", "rshoulder", "ltrigger", "rtrigger", "menu", "home", "lstick", "rstick", "plus", "minus",
+                "leftx", "lefty", "leftz", "rightx", "righty", "rightz", "throttle", "brake",
+                "scrollx", "scrolly", "scrollz", "roll", "pitch", "yaw"]{
+            let input: JoyInput = s.parse().unwrap();
+            assert_eq!(seq.iter().filter(|p| p.input == input).count(), 1, "{}", s);
+        }
+        assert_eq!(seq.len(), 38);
+
+        assert_eq!(end_of_group(&seq, 0), 4);
+        assert_eq!(end_of_group(&seq, 2), 4);
+        assert_eq!(seq[4].input, JoyInput::Button(Button::A()));
+        assert_eq!(end_of_group(&seq, seq.len() - 1), seq.len());
+    }
+
+    #[test]
+    fn test_parse_template(){
+        let text = "# a comment\nup\ngroup panel buttons\n  custom_button(1) = top left\nCustom_Button(2)=\n";
+        assert_eq!(parse_template(text), Ok(vec![
+            Prompt{input: JoyInput::Button(Button::Up()), group: "".to_string(), text: None},
+            Prompt{input: JoyInput::Button(Button::Custom(1)), group: "panel buttons".to_string(), text: Some("top left".to_string())},
+            Prompt{input: JoyInput::Button(Button::Custom(2)), group: "panel buttons".to_string(), text: None},
+        ]));
+
+        let errors = parse_template("up\n  nope = what\ngroupie\n").unwrap_err();
+        assert_eq!(errors.iter().map(|e| (e.line, e.column)).collect::<Vec<_>>(), vec![(2, 3), (3, 1)]);
+
+        for (name, text) in builtin_templates{
+            let seq = parse_template(text).unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+            assert!(!seq.is_empty(), "{}", name);
+            assert!(seq.iter().all(|p| !p.group.is_empty()), "{}", name);
+        }
+        assert_eq!(parse_template(builtin_template("arcade-stick").unwrap()).unwrap()[4].input, JoyInput::Button(Button::Custom(1)));
+        assert_eq!(builtin_template("nope"), None);
+    }
+
+    #[test]
+    fn test_leftover_buttons(){
+        let config = parse_mapping_file("button(0) = a\nbutton(2) = custom_button(3)\naxis(0,-32767,32767) = leftx\n").unwrap();
+        assert_eq!(leftover_buttons(5, &config), vec![
+            (1, JoyInput::Button(Button::Custom(1))),
+            (3, JoyInput::Button(Button::Custom(4))),
+            (4, JoyInput::Button(Button::Custom(5))),
+        ]);
+        assert_eq!(leftover_buttons(0, &config), vec![]);
+    }
+
+    #[test]
+    fn test_session(){
+        let mut s = Session::new(parse_template("group a\nup\ndown\ngroup b\nleft\nright\nstart\n").unwrap());
+        assert_eq!(s.current().unwrap().input, JoyInput::Button(Button::Up()));
+        assert!(!s.undo());
+        s.assign(JDEv::Button(0));
+        s.skip();
+        s.assign(JDEv::Button(2));
+        s.skip_group();
+        assert!(s.current().is_none());
+        assert_eq!(s.mapped, vec![Some(JDEv::Button(0)), None, Some(JDEv::Button(2)), None, None]);
+
+        // undoing from the end asks again, then comes back to the end
+        assert!(s.undo());
+        assert_eq!(s.next, 3);
+        s.assign(JDEv::Button(3));
+        assert_eq!(s.next, 5);
+        assert_eq!(s.mapped[3..], [Some(JDEv::Button(3)), None]);
+
+        // undo goes back one step at a time
+        assert!(s.undo());
+        assert!(s.undo());
+        assert_eq!(s.next, 2);
+        assert_eq!(s.mapped, vec![Some(JDEv::Button(0)), None, None, None, None]);
+        s.assign(JDEv::Button(5));
+        assert_eq!(s.next, 3);
+
+        // jumping back comes back to where it was
+        s.jump(0);
+        s.assign(JDEv::Button(1));
+        assert_eq!(s.next, 3);
+        assert_eq!(s.mapped[0], Some(JDEv::Button(1)));
+
+        s.skip_group();
+        s.add_leftovers(vec![(7, JoyInput::Button(Button::Custom(7)))]);
+        assert!(s.current().is_none());
+        assert_eq!(s.mappings().iter().map(|m| m.to_string()).collect::<Vec<_>>(), vec!["button(1) = up", "button(5) = left", "button(7) = custom_button(7)"]);
+    }
+
+    #[test]
+    fn test_load(){
+        let mut s = Session::new(parse_template("up\ndown\na\n").unwrap());
+        let existing = parse_mapping_file("button(0) = a\nbutton(1) = up\nbutton(2) = a\nbutton(3) = home\n").unwrap();
+        s.load(&existing.iter().collect::<Vec<_>>());
+        assert!(s.current().is_none());
+        assert_eq!(s.mapped, vec![Some(JDEv::Button(1)), None, Some(JDEv::Button(0)), Some(JDEv::Button(2)), Some(JDEv::Button(3))]);
+        assert_eq!(s.seq[3..].iter().map(|p| p.input.to_string()).collect::<Vec<_>>(), vec!["a", "home"]);
+        assert!(!s.undo());
+        // mapping one again comes back to the end
+        s.jump(1);
+        s.assign(JDEv::Button(4));
+        assert!(s.current().is_none());
+        assert_eq!(s.mappings().len(), 5);
+    }
+
+    #[test]
+    fn test_clashes(){
+        assert!(same_control(&JDEv::Button(1), &JDEv::Button(1)));
+        assert!(!same_control(&JDEv::Button(1), &JDEv::Button(2)));
+        assert!(same_control(&JDEv::Axis(1, -5, 5, 0, 0), &JDEv::Axis(1, -3, 3, 0, 0)));
+        assert!(same_control(&JDEv::AxisAsButton(1, 5), &JDEv::Axis(1, -5, 5, 0, 0)));
+        assert!(same_control(&JDEv::Axis(1, -5, 5, 0, 0), &JDEv::AxisAsButton(1, 5)));
+        assert!(!same_control(&JDEv::AxisAsButton(1, 5), &JDEv::AxisAsButton(1, -5)));
+        // a trigger can be both
+        assert!(!same_control(&JDEv::Axis(2, -32767, 32767, -32767, 0), &JDEv::AxisAsButton(2, 32767)));
+        assert!(!same_control(&JDEv::AxisAsButton(2, -32767), &JDEv::Axis(2, 32767, -32767, 32767, 0)));
+        assert!(same_control(&JDEv::Axis(2, -32767, 32767, -32767, 0), &JDEv::Axis(2, -32767, 32767, 0, 0)));
+        assert!(!same_control(&JDEv::Button(1), &JDEv::AxisAsButton(1, 5)));
+
+        let mut s = Session::new(parse_template("start\nplus\nup\ndown\nlefty\n").unwrap());
+        s.assign(JDEv::Button(3));
+        assert_eq!(s.clashes(&JDEv::Button(3)), vec![0]);
+        // keeping both
+        s.assign(JDEv::Button(3));
+        assert_eq!(s.mappings().len(), 2);
+        // going back to one that is already mapped doesn't clash with itself
+        s.jump(0);
+        assert_eq!(s.clashes(&JDEv::Button(3)), vec![1]);
+        s.skip();
+
+        // a hat's directions should be opposite ends of the same axis
+        s.assign(JDEv::AxisAsButton(1, -32767));
+        assert_eq!(s.hat_warning(2), None);
+        s.assign(JDEv::AxisAsButton(0, 32767));
+        assert!(s.hat_warning(3).is_some());
+        assert!(s.hat_warning(2).is_some());
+        assert!(s.undo());
+        s.assign(JDEv::AxisAsButton(1, 32767));
+        assert_eq!(s.hat_warning(3), None);
+
+        // reassigning takes the axis away from the hat
+        assert_eq!(s.clashes(&JDEv::Axis(1, -32767, 32767, 0, 0)), vec![2, 3]);
+        s.reassign(JDEv::Axis(1, -32767, 32767, 0, 0));
+        assert_eq!(s.mapped, vec![None, Some(JDEv::Button(3)), None, None, Some(JDEv::Axis(1, -32767, 32767, 0, 0))]);
+        assert!(s.undo());
+        assert_eq!(s.mapped, vec![None, Some(JDEv::Button(3)), Some(JDEv::AxisAsButton(1, -32767)), Some(JDEv::AxisAsButton(1, 32767)), None]);
+        assert_eq!(s.next, 4);
+    }
+}
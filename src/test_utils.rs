@@ -31,7 +31,7 @@ pub enum TestEv{
     Line(String),
 }
 
-pub fn spawn_main<T>(wrapped_main: T) -> (std::thread::JoinHandle<()>, std::sync::mpsc::Receiver<TestEv>, std::thread::JoinHandle<()>) where T: FnOnce(std::os::unix::net::UnixStream) -> () + std::marker::Send + 'static{
+pub fn spawn_main<T>(wrapped_main: T) -> (std::thread::JoinHandle<()>, std::sync::mpsc::Receiver<TestEv>, std::thread::JoinHandle<()>) where T: FnOnce(std::os::unix::net::UnixStream) + std::marker::Send + 'static{
     let (send, recv) = std::sync::mpsc::channel();
     let send1 = send.clone();
 
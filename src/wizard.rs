@@ -0,0 +1,236 @@
+/*
+
+What does this file do?
+
+    The parts of the joy2uinput.conf wizard (joy2u-mapgen --conf) that don't need a
+    joypad or a terminal. The wizard finds out which input was pressed by asking the
+    mapping engine, and which key was pressed by decoding what the
+    terminal sends for it. The config file it writes is the one that was already there,
+    with the lines for whatever was set up changed, so that everything else is kept.
+
+*/
+
+use joy2uinput::engine::{resolve, JDCId, JoyEv, PadMapping};
+use joy2uinput::map_config::{AxisTarget, ExtraKey, JoyInput, KeyTarget, TargetMapping};
+
+// The axis targets that can be picked by number, and how fast they go if no multiplier is typed
+pub const axis_targets: [(&str, f32); 8] = [
+    ("mousex", 10.0),
+    ("mousey", 10.0),
+    ("scrollx", 0.5),
+    ("scrolly", 0.5),
+    ("pageupdown", 1.0),
+    ("leftright", 1.0),
+    ("updown", 1.0),
+    ("volupdown", 1.0),
+];
+
+pub fn axis_target(n: usize, multiplier: f32) -> Option<AxisTarget> {
+    axis_targets.get(n).and_then(|(name, _)| format!("axis({},{})", name, multiplier).parse().ok())
+}
+
+// What the terminal sent, so far
+#[derive(Debug,PartialEq)]
+pub enum KeyPress{
+    Key(KeyTarget),
+    // the start of an escape sequence, there is more to come (or it was just the escape key)
+    Partial(),
+    // something that can't be turned back into a key, such as ctrl+c or a shifted symbol
+    Unknown(),
+}
+
+// Decodes the bytes a terminal sends for one key. Shift is ignored for letters, so A is key(a)
+pub fn decode_key(bytes: &[u8]) -> KeyPress {
+    let key = match bytes{
+        [] => return KeyPress::Partial(),
+        [b'\r'] | [b'\n'] => KeyTarget::Return(),
+        [b'\t'] => KeyTarget::Tab(),
+        [b' '] => KeyTarget::Space(),
+        [0x7f] | [0x08] => KeyTarget::Extra(ExtraKey::Backspace),
+        [c @ (b'a'..=b'z' | b'0'..=b'9' | b'-' | b'=' | b',' | b'.' | b'/' | b';' | b'\'' | b'[' | b']' | b'\\')] => KeyTarget::AlphaNum(*c as char),
+        [c @ b'A'..=b'Z'] => KeyTarget::AlphaNum(c.to_ascii_lowercase() as char),
+        [0x1b] => return KeyPress::Partial(),
+        [0x1b, b'[' | b'O', rest @ ..] => {
+            // the sequence ends with a letter or ~
+            match rest.last(){
+                Some(c) if c.is_ascii_alphabetic() || *c == b'~' => {},
+                _ => return KeyPress::Partial(),
+            }
+            match rest{
+                b"A" => KeyTarget::Up(),
+                b"B" => KeyTarget::Down(),
+                b"C" => KeyTarget::Right(),
+                b"D" => KeyTarget::Left(),
+                b"H" | b"1~" | b"7~" => KeyTarget::Home(),
+                b"F" | b"4~" | b"8~" => KeyTarget::End(),
+                b"2~" => KeyTarget::Extra(ExtraKey::Insert),
+                b"3~" => KeyTarget::Delete(),
+                b"5~" => KeyTarget::PageUp(),
+                b"6~" => KeyTarget::PageDown(),
+                b"P" | b"11~" => KeyTarget::F(1),
+                b"Q" | b"12~" => KeyTarget::F(2),
+                b"R" | b"13~" => KeyTarget::F(3),
+                b"S" | b"14~" => KeyTarget::F(4),
+                b"15~" => KeyTarget::F(5),
+                b"17~" => KeyTarget::F(6),
+                b"18~" => KeyTarget::F(7),
+                b"19~" => KeyTarget::F(8),
+                b"20~" => KeyTarget::F(9),
+                b"21~" => KeyTarget::F(10),
+                b"23~" => KeyTarget::F(11),
+                b"24~" => KeyTarget::F(12),
+                _ => return KeyPress::Unknown(),
+            }
+        },
+        _ => return KeyPress::Unknown(),
+    };
+    KeyPress::Key(key)
+}
+
+// Which input was touched, as joy2uinput would see it: a button that was pressed, a position on an axis that
+// is mapped as a button, or otherwise an axis that is more than half way from where it rests to one of its ends
+pub fn touched(mapping: &PadMapping, ev: JoyEv) -> Option<JoyInput> {
+    let resolved = resolve(mapping, ev);
+    let is_axis = |id: &JDCId| matches!(id, JDCId::Axis(_));
+    resolved.iter().find(|r| !is_axis(&r.id) && r.position != 0.0)
+        .or_else(|| resolved.iter().find(|r| is_axis(&r.id) && r.position.abs() > 0.5))
+        .map(|r| r.input.clone())
+}
+
+pub struct ConfFile{
+    lines: Vec<String>,
+    // the input that each line sets up, if it does
+    inputs: Vec<Option<JoyInput>>,
+}
+
+impl ConfFile{
+    pub fn parse(text: &str) -> Self {
+        let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+        let inputs = lines.iter().map(|l| {
+            let t = l.trim();
+            if t.is_empty() || t.starts_with('#') {None} else {l.parse::<TargetMapping>().ok().map(|m| m.from)}
+        }).collect();
+        ConfFile{lines, inputs}
+    }
+
+    // What an input does now. When there is more than one line for it, the last one is the one that counts
+    pub fn current(&self, input: &JoyInput) -> Option<&str> {
+        self.lines.iter().zip(&self.inputs).rev().find(|(_, i)| i.as_ref() == Some(input)).map(|(l, _)| l.trim())
+    }
+
+    // The file again, with the first line for each input that was set up replaced, any other
+    // lines for it taken out (because they would win otherwise), and the new ones at the end
+    pub fn rewrite(&self, mappings: &[TargetMapping]) -> String {
+        let mut written = vec![false; mappings.len()];
+        let mut out = String::new();
+        for (line, input) in self.lines.iter().zip(&self.inputs){
+            match input.as_ref().and_then(|input| mappings.iter().position(|m| &m.from == input)){
+                None => {
+                    out += line;
+                    out.push('\n');
+                },
+                Some(i) => {
+                    if !written[i]{
+                        written[i] = true;
+                        out += &format!("{}\n", mappings[i]);
+                    }
+                },
+            }
+        }
+        for (m, w) in mappings.iter().zip(written){
+            if !w{
+                out += &format!("{}\n", m);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::wizard::{axis_target, axis_targets, decode_key, touched, ConfFile, KeyPress};
+    use joy2uinput::config_file::{parse_config_file, parse_mapping_file};
+    use joy2uinput::engine::{expand_mapping, JoyEv};
+    use std::collections::HashMap;
+    use joy2uinput::map_config::{Axis, AxisTarget, Button, ExtraKey, JoyInput, KeyTarget, Target, TargetMapping};
+
+    #[test]
+    fn test_decode_key(){
+        assert_eq!(decode_key(b"a"), KeyPress::Key(KeyTarget::AlphaNum('a')));
+        assert_eq!(decode_key(b"A"), KeyPress::Key(KeyTarget::AlphaNum('a')));
+        assert_eq!(decode_key(b"\r"), KeyPress::Key(KeyTarget::Return()));
+        assert_eq!(decode_key(b"\x7f"), KeyPress::Key(KeyTarget::Extra(ExtraKey::Backspace)));
+        assert_eq!(decode_key(b"\x1b"), KeyPress::Partial());
+        assert_eq!(decode_key(b"\x1b["), KeyPress::Partial());
+        assert_eq!(decode_key(b"\x1b[A"), KeyPress::Key(KeyTarget::Up()));
+        assert_eq!(decode_key(b"\x1b[2"), KeyPress::Partial());
+        assert_eq!(decode_key(b"\x1b[24~"), KeyPress::Key(KeyTarget::F(12)));
+        assert_eq!(decode_key(b"\x1bOP"), KeyPress::Key(KeyTarget::F(1)));
+        assert_eq!(decode_key(b"\x1b[99~"), KeyPress::Unknown());
+        assert_eq!(decode_key(b"\x03"), KeyPress::Unknown());
+        assert_eq!(decode_key(b"!"), KeyPress::Unknown());
+
+        // everything that can be decoded can be written into the config file and read back
+        let mut all: Vec<Vec<u8>> = (0..=127u8).map(|b| vec![b]).collect();
+        for end in ["A", "B", "C", "D", "H", "F", "2~", "3~", "5~", "6~", "P", "Q", "R", "S", "15~", "17~", "18~", "19~", "20~", "21~", "23~", "24~"]{
+            all.push([b"\x1b[".as_slice(), end.as_bytes()].concat());
+        }
+        for bytes in all{
+            if let KeyPress::Key(k) = decode_key(&bytes){
+                let t = Target::Key(k);
+                assert_eq!(t.to_string().parse::<Target>(), Ok(t.clone()), "{:?}", bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_touched(){
+        let mapping = expand_mapping(&parse_mapping_file("button(0) = a
+axis_as_button(2,32767) = ltrigger
+axis(2,-32767,32767,-32767,0) = leftz
+axis(1,32767,-32767) = lefty
+").unwrap(), &HashMap::new());
+        assert_eq!(touched(&mapping, JoyEv::Button(0, 1)), Some(JoyInput::Button(Button::A())));
+        assert_eq!(touched(&mapping, JoyEv::Button(0, 0)), None);
+        assert_eq!(touched(&mapping, JoyEv::Button(1, 1)), None);
+        assert_eq!(touched(&mapping, JoyEv::Axis(2, 32767)), Some(JoyInput::Button(Button::LTrigger())));
+        assert_eq!(touched(&mapping, JoyEv::Axis(2, 10000)), Some(JoyInput::Axis(Axis::LeftZ())));
+        assert_eq!(touched(&mapping, JoyEv::Axis(2, -10000)), None);
+        assert_eq!(touched(&mapping, JoyEv::Axis(1, -20000)), Some(JoyInput::Axis(Axis::LeftY())));
+        assert_eq!(touched(&mapping, JoyEv::Axis(1, 20000)), Some(JoyInput::Axis(Axis::LeftY())));
+        assert_eq!(touched(&mapping, JoyEv::Axis(1, 1000)), None);
+    }
+
+    #[test]
+    fn test_conf_file(){
+        assert_eq!(axis_target(0, 15.0), Some(AxisTarget::MouseX(15.0)));
+        assert_eq!(axis_target(axis_targets.len(), 1.0), None);
+
+        let text = "# arrows
+up = key(up)
+set keyboard_layout = gb
+a = key(return)
+a = key(x)
+leftx = axis(mousex, 15)
+";
+        let f = ConfFile::parse(text);
+        assert_eq!(f.current(&JoyInput::Button(Button::A())), Some("a = key(x)"));
+        assert_eq!(f.current(&JoyInput::Button(Button::B())), None);
+        assert_eq!(f.rewrite(&[]), text);
+
+        let set = vec![
+            "a = key(space)".parse::<TargetMapping>().unwrap(),
+            "b = key(escape)".parse::<TargetMapping>().unwrap(),
+            "leftx = axis(scrollx,0.5)".parse::<TargetMapping>().unwrap(),
+        ];
+        let out = f.rewrite(&set);
+        assert_eq!(out, "# arrows
+up = key(up)
+set keyboard_layout = gb
+a = key(space)
+leftx = axis(scrollx,0.5)
+b = key(escape)
+");
+        assert!(parse_config_file(&out).is_ok());
+    }
+}
@@ -0,0 +1,495 @@
+/*
+
+What does this file do?
+
+    Works out which keys to press to type a given character, using the XKB keyboard
+    layout files from the local system (normally /usr/share/X11/xkb/symbols).
+    The virtual keyboard only sends key codes, it's up to whatever reads them to decide
+    which character that is. So to type text we need to know the layout that the
+    system is using, and press shift or AltGr where the layout needs them.
+
+    Only the parts of the XKB symbols format that matter for this are understood:
+    the first group of each key, on the normal alphanumeric part of the keyboard.
+    AltGr is assumed to be the right alt key, which is the case for almost all layouts.
+
+*/
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// How a character is typed: the key, and whether shift and/or AltGr are held while pressing it
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Stroke{
+    pub key: evdev::Key,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+pub const SHIFT_KEY: evdev::Key = evdev::Key::KEY_LEFTSHIFT;
+pub const ALTGR_KEY: evdev::Key = evdev::Key::KEY_RIGHTALT;
+
+#[derive(Debug,Clone,Default)]
+pub struct Keymap{
+    strokes: HashMap<char, Stroke>,
+}
+
+impl Keymap{
+    pub fn stroke(&self, c: char) -> Option<Stroke> {
+        match c{
+            // these are not in the symbols files for most layouts, but they are always the same keys
+            '\n' => Some(Stroke{key: evdev::Key::KEY_ENTER, shift: false, altgr: false}),
+            '\t' => Some(Stroke{key: evdev::Key::KEY_TAB, shift: false, altgr: false}),
+            ' ' => Some(self.strokes.get(&' ').copied().unwrap_or(Stroke{key: evdev::Key::KEY_SPACE, shift: false, altgr: false})),
+            c => self.strokes.get(&c).copied(),
+        }
+    }
+
+    // Loads a layout such as "us", "de" or "fr(bepo)"
+    pub fn load(symbols_dir: &Path, layout: &str) -> Result<Keymap, String> {
+        let mut levels = HashMap::new();
+        include(symbols_dir, layout, &mut levels, 0)?;
+        let mut strokes = HashMap::new();
+        // go through the keys in a fixed order, so that if a character is on several keys we always pick the same one
+        for (name, key) in KEY_NAMES.iter(){
+            if let Some(syms) = levels.get(*name){
+                for (level, sym) in syms.iter().enumerate().take(4){
+                    if let Some(c) = sym.as_deref().and_then(keysym_char){
+                        let stroke = Stroke{key: *key, shift: level % 2 == 1, altgr: level >= 2};
+                        let existing = strokes.entry(c).or_insert((level, stroke));
+                        // prefer the stroke that needs the fewest modifiers
+                        if level < existing.0{
+                            *existing = (level, stroke);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Keymap{strokes: strokes.into_iter().map(|(c, (_, s))| (c, s)).collect()})
+    }
+}
+
+// Where the XKB symbols files are, XKB_CONFIG_ROOT is the same variable that libxkbcommon uses to override this
+pub fn symbols_dir() -> PathBuf {
+    let root = std::env::var_os("XKB_CONFIG_ROOT").filter(|r| !r.is_empty()).map(PathBuf::from).unwrap_or(PathBuf::from("/usr/share/X11/xkb"));
+    root.join("symbols")
+}
+
+// The XKB names of the keys on the main part of the keyboard, and their evdev codes
+const KEY_NAMES: [(&str, evdev::Key); 50] = [
+    ("AE01", evdev::Key::KEY_1), ("AE02", evdev::Key::KEY_2), ("AE03", evdev::Key::KEY_3), ("AE04", evdev::Key::KEY_4),
+    ("AE05", evdev::Key::KEY_5), ("AE06", evdev::Key::KEY_6), ("AE07", evdev::Key::KEY_7), ("AE08", evdev::Key::KEY_8),
+    ("AE09", evdev::Key::KEY_9), ("AE10", evdev::Key::KEY_0), ("AE11", evdev::Key::KEY_MINUS), ("AE12", evdev::Key::KEY_EQUAL),
+    ("AD01", evdev::Key::KEY_Q), ("AD02", evdev::Key::KEY_W), ("AD03", evdev::Key::KEY_E), ("AD04", evdev::Key::KEY_R),
+    ("AD05", evdev::Key::KEY_T), ("AD06", evdev::Key::KEY_Y), ("AD07", evdev::Key::KEY_U), ("AD08", evdev::Key::KEY_I),
+    ("AD09", evdev::Key::KEY_O), ("AD10", evdev::Key::KEY_P), ("AD11", evdev::Key::KEY_LEFTBRACE), ("AD12", evdev::Key::KEY_RIGHTBRACE),
+    ("AC01", evdev::Key::KEY_A), ("AC02", evdev::Key::KEY_S), ("AC03", evdev::Key::KEY_D), ("AC04", evdev::Key::KEY_F),
+    ("AC05", evdev::Key::KEY_G), ("AC06", evdev::Key::KEY_H), ("AC07", evdev::Key::KEY_J), ("AC08", evdev::Key::KEY_K),
+    ("AC09", evdev::Key::KEY_L), ("AC10", evdev::Key::KEY_SEMICOLON), ("AC11", evdev::Key::KEY_APOSTROPHE),
+    ("AB01", evdev::Key::KEY_Z), ("AB02", evdev::Key::KEY_X), ("AB03", evdev::Key::KEY_C), ("AB04", evdev::Key::KEY_V),
+    ("AB05", evdev::Key::KEY_B), ("AB06", evdev::Key::KEY_N), ("AB07", evdev::Key::KEY_M), ("AB08", evdev::Key::KEY_COMMA),
+    ("AB09", evdev::Key::KEY_DOT), ("AB10", evdev::Key::KEY_SLASH),
+    ("TLDE", evdev::Key::KEY_GRAVE), ("BKSL", evdev::Key::KEY_BACKSLASH), ("AC12", evdev::Key::KEY_BACKSLASH),
+    ("LSGT", evdev::Key::KEY_102ND), ("SPCE", evdev::Key::KEY_SPACE),
+];
+
+// Keysym names for the printable ASCII characters that aren't just the character itself
+const ASCII_NAMES: [(&str, char); 35] = [
+    ("space", ' '), ("exclam", '!'), ("quotedbl", '"'), ("numbersign", '#'), ("dollar", '$'), ("percent", '%'),
+    ("ampersand", '&'), ("apostrophe", '\''), ("quoteright", '\''), ("parenleft", '('), ("parenright", ')'),
+    ("asterisk", '*'), ("plus", '+'), ("comma", ','), ("minus", '-'), ("period", '.'), ("slash", '/'),
+    ("colon", ':'), ("semicolon", ';'), ("less", '<'), ("equal", '='), ("greater", '>'), ("question", '?'),
+    ("at", '@'), ("bracketleft", '['), ("backslash", '\\'), ("bracketright", ']'), ("asciicircum", '^'),
+    ("underscore", '_'), ("grave", '`'), ("quoteleft", '`'), ("braceleft", '{'), ("bar", '|'), ("braceright", '}'),
+    ("asciitilde", '~'),
+];
+
+// Keysym names for latin-1, in order from U+00A0
+const LATIN1_NAMES: [&str; 96] = [
+    "nobreakspace", "exclamdown", "cent", "sterling", "currency", "yen", "brokenbar", "section",
+    "diaeresis", "copyright", "ordfeminine", "guillemotleft", "notsign", "hyphen", "registered", "macron",
+    "degree", "plusminus", "twosuperior", "threesuperior", "acute", "mu", "paragraph", "periodcentered",
+    "cedilla", "onesuperior", "masculine", "guillemotright", "onequarter", "onehalf", "threequarters", "questiondown",
+    "Agrave", "Aacute", "Acircumflex", "Atilde", "Adiaeresis", "Aring", "AE", "Ccedilla",
+    "Egrave", "Eacute", "Ecircumflex", "Ediaeresis", "Igrave", "Iacute", "Icircumflex", "Idiaeresis",
+    "ETH", "Ntilde", "Ograve", "Oacute", "Ocircumflex", "Otilde", "Odiaeresis", "multiply",
+    "Oslash", "Ugrave", "Uacute", "Ucircumflex", "Udiaeresis", "Yacute", "THORN", "ssharp",
+    "agrave", "aacute", "acircumflex", "atilde", "adiaeresis", "aring", "ae", "ccedilla",
+    "egrave", "eacute", "ecircumflex", "ediaeresis", "igrave", "iacute", "icircumflex", "idiaeresis",
+    "eth", "ntilde", "ograve", "oacute", "ocircumflex", "otilde", "odiaeresis", "division",
+    "oslash", "ugrave", "uacute", "ucircumflex", "udiaeresis", "yacute", "thorn", "ydiaeresis",
+];
+
+// Other names that appear in common layouts
+const OTHER_NAMES: [(&str, char); 12] = [
+    ("EuroSign", '€'), ("guillemetleft", '«'), ("guillemetright", '»'), ("ordmasculine", 'º'), ("Eth", 'Ð'),
+    ("Thorn", 'Þ'), ("Ooblique", 'Ø'), ("ooblique", 'ø'), ("endash", '–'), ("emdash", '—'),
+    ("rightsinglequotemark", '’'), ("leftsinglequotemark", '‘'),
+];
+
+// The character a keysym types, if it types one (dead keys and function keys don't)
+pub fn keysym_char(sym: &str) -> Option<char> {
+    let mut chars = sym.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()){
+        return Some(c);
+    }
+    if let Some(hex) = sym.strip_prefix('U'){
+        if hex.len() >= 4 && hex.chars().all(|c| c.is_ascii_hexdigit()){
+            return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+        }
+    }
+    if let Some(hex) = sym.strip_prefix("0x"){
+        // keysyms from 0x01000100 upwards are unicode code points, and below 0x100 they are latin-1
+        let code = u32::from_str_radix(hex, 16).ok()?;
+        return match code{
+            0x20..=0x7e | 0xa0..=0xff => char::from_u32(code),
+            0x01000100..=0x0110ffff => char::from_u32(code - 0x01000000),
+            _ => None,
+        };
+    }
+    if let Some((_, c)) = ASCII_NAMES.iter().chain(OTHER_NAMES.iter()).find(|(n, _)| *n == sym){
+        return Some(*c);
+    }
+    LATIN1_NAMES.iter().position(|n| *n == sym).and_then(|i| char::from_u32(0xa0 + i as u32))
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token{
+    Word(String),
+    Str(String),
+    KeyName(String),
+    Punct(char),
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next(){
+        match c{
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref(){
+                    if c == '\n'{ break; }
+                }
+            },
+            '#' => {
+                for c in chars.by_ref(){
+                    if c == '\n'{ break; }
+                }
+            },
+            '"' => {
+                let mut s = String::new();
+                for c in chars.by_ref(){
+                    if c == '"'{ break; }
+                    s.push(c);
+                }
+                tokens.push(Token::Str(s));
+            },
+            '<' => {
+                let mut s = String::new();
+                for c in chars.by_ref(){
+                    if c == '>'{ break; }
+                    s.push(c);
+                }
+                tokens.push(Token::KeyName(s));
+            },
+            '{' | '}' | '[' | ']' | '(' | ')' | ';' | ',' | '=' => tokens.push(Token::Punct(c)),
+            c if c.is_whitespace() => {},
+            c => {
+                let mut s = String::from(c);
+                while let Some(&c) = chars.peek(){
+                    if c.is_whitespace() || "{}[]()<>;,=\"".contains(c){ break; }
+                    s.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(s));
+            },
+        }
+    }
+    tokens
+}
+
+#[derive(Debug)]
+enum Statement{
+    Include(String),
+    Key(String, Vec<Option<String>>),
+}
+
+#[derive(Debug)]
+struct Section{
+    name: String,
+    default: bool,
+    statements: Vec<Statement>,
+}
+
+// Reads the list in a [ ... ] starting just after the '['
+fn parse_symbol_list(tokens: &[Token], i: &mut usize) -> Vec<Option<String>> {
+    let mut syms = Vec::new();
+    while *i < tokens.len(){
+        let t = &tokens[*i];
+        *i += 1;
+        match t{
+            Token::Punct(']') => break,
+            Token::Word(w) => syms.push(if w == "NoSymbol" {None} else {Some(w.clone())}),
+            _ => {},
+        }
+    }
+    syms
+}
+
+// Reads the body of a key { ... } starting just after the '{', returns the group 1 symbols
+fn parse_key_body(tokens: &[Token], i: &mut usize) -> Vec<Option<String>> {
+    let mut group1 = None;
+    let mut depth = 1;
+    while *i < tokens.len() && depth > 0{
+        let t = &tokens[*i];
+        *i += 1;
+        match t{
+            Token::Punct('{') => depth += 1,
+            Token::Punct('}') => depth -= 1,
+            // a bare list is the symbols for the first group
+            Token::Punct('[') if depth == 1 && group1.is_none() => {
+                group1 = Some(parse_symbol_list(tokens, i));
+            },
+            Token::Word(w) if depth == 1 && w == "symbols" => {
+                // symbols[GroupN] = [ ... ]
+                if let (Some(Token::Punct('[')), Some(Token::Word(group)), Some(Token::Punct(']')), Some(Token::Punct('=')), Some(Token::Punct('['))) =
+                    (tokens.get(*i), tokens.get(*i+1), tokens.get(*i+2), tokens.get(*i+3), tokens.get(*i+4)){
+                    *i += 5;
+                    let syms = parse_symbol_list(tokens, i);
+                    if group.eq_ignore_ascii_case("group1"){
+                        group1 = Some(syms);
+                    }
+                }
+            },
+            Token::Punct('[') => {
+                // some other list, such as actions or a group index, skip it
+                parse_symbol_list(tokens, i);
+            },
+            _ => {},
+        }
+    }
+    group1.unwrap_or_default()
+}
+
+fn parse_sections(text: &str) -> Vec<Section> {
+    let tokens = tokenize(text);
+    let mut sections = Vec::new();
+    let mut i = 0;
+    let mut default = false;
+    while i < tokens.len(){
+        match &tokens[i]{
+            Token::Word(w) if w == "default" => { default = true; i += 1; },
+            Token::Word(w) if w == "xkb_symbols" => {
+                let name = match tokens.get(i+1){
+                    Some(Token::Str(s)) => s.clone(),
+                    _ => String::new(),
+                };
+                i += 1;
+                while i < tokens.len() && tokens[i] != Token::Punct('{'){ i += 1; }
+                i += 1;
+                let mut statements = Vec::new();
+                while i < tokens.len() && tokens[i] != Token::Punct('}'){
+                    match (&tokens[i], tokens.get(i+1)){
+                        (Token::Word(w), Some(Token::Str(s))) if ["include", "augment", "override", "replace"].contains(&w.as_str()) => {
+                            statements.push(Statement::Include(s.clone()));
+                            i += 2;
+                        },
+                        (Token::Word(w), Some(Token::KeyName(k))) if w == "key" => {
+                            let k = k.clone();
+                            i += 2;
+                            while i < tokens.len() && tokens[i] != Token::Punct('{'){ i += 1; }
+                            i += 1;
+                            statements.push(Statement::Key(k, parse_key_body(&tokens, &mut i)));
+                        },
+                        (Token::Punct('{'), _) => {
+                            // some other block, such as modifier_map, skip it
+                            let mut depth = 0;
+                            while i < tokens.len(){
+                                if tokens[i] == Token::Punct('{'){ depth += 1; }
+                                if tokens[i] == Token::Punct('}'){ depth -= 1; if depth == 0{ break; } }
+                                i += 1;
+                            }
+                            i += 1;
+                        },
+                        _ => { i += 1; },
+                    }
+                }
+                i += 1;
+                sections.push(Section{name, default, statements});
+                default = false;
+            },
+            _ => { i += 1; },
+        }
+    }
+    sections
+}
+
+// Splits "de(nodeadkeys)" into ("de", Some("nodeadkeys"))
+fn split_layout(layout: &str) -> (&str, Option<&str>) {
+    match layout.split_once('('){
+        Some((file, rest)) => (file.trim(), Some(rest.trim_end_matches(')').trim())),
+        None => (layout.trim(), None),
+    }
+}
+
+fn include(symbols_dir: &Path, layout: &str, levels: &mut HashMap<String, Vec<Option<String>>>, depth: u32) -> Result<(), String> {
+    if depth > 20{
+        return Err(format!("Keyboard layout includes too many other layouts (is there a loop?) at {}", layout));
+    }
+    // "pc+us(intl)" includes several layouts one after another, parts after | are for other groups, which are ignored
+    for part in layout.split('|').next().unwrap_or("").split('+').filter(|p| !p.trim().is_empty()){
+        let (file, section) = split_layout(part);
+        if file.contains('/') || file.starts_with('.'){
+            return Err(format!("Invalid keyboard layout name: {}", file));
+        }
+        let path = symbols_dir.join(file);
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("Unable to read keyboard layout {}: {}", path.display(), e))?;
+        let sections = parse_sections(&text);
+        let found = match section{
+            Some(name) => sections.iter().find(|s| s.name == name),
+            None => sections.iter().find(|s| s.default).or(sections.first()),
+        };
+        let found = found.ok_or(format!("No layout called {} in {}", section.unwrap_or("(default)"), path.display()))?;
+        for statement in found.statements.iter(){
+            match statement{
+                Statement::Include(inc) => include(symbols_dir, inc, levels, depth + 1)?,
+                Statement::Key(name, syms) => {
+                    let existing = levels.entry(name.clone()).or_default();
+                    // levels that are not given keep what they were set to by an earlier include
+                    for (n, sym) in syms.iter().enumerate(){
+                        if n >= existing.len(){
+                            existing.resize(n + 1, None);
+                        }
+                        if sym.is_some(){
+                            existing[n] = sym.clone();
+                        }
+                    }
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test{
+    use crate::xkb::{Keymap, Stroke, keysym_char, ALTGR_KEY};
+    use evdev::Key;
+    use tempdir::TempDir;
+
+    const LATIN: &str = r#"
+// a cut down version of the real file
+default partial alphanumeric_keys
+xkb_symbols "basic" {
+    key <AE01>	{ [         1,     exclam ] };
+    key <AE02>	{ [         2,         at ] };
+    key <AD01>	{ [         q,          Q,           at,  Greek_OMEGA ] };
+    key <AD06>	{ [         y,          Y ] };
+    key <AB01>	{ [         z,          Z ] };
+    key <AC01>	{ [         a,          A ] };
+    modifier_map Mod1 { <LALT> };
+};
+
+partial alphanumeric_keys
+xkb_symbols "type4" {
+    include "latin"
+    key <AE01>	{ [ NoSymbol, NoSymbol, onesuperior ] };
+};
+"#;
+
+    const DE: &str = r#"
+default
+xkb_symbols "basic" {
+    include "latin(type4)"
+    name[Group1]="German";
+    key <AE02>	{ [         2,   quotedbl,  twosuperior,    oneeighth ]	};
+    key <AE11> {type[Group1]="FOUR_LEVEL_PLUS_LOCK",  symbols[Group1]=
+                  [ssharp, question, backslash, questiondown, 0x1001E9E ]};
+    key <AE12>	{ [dead_acute, dead_grave, dead_cedilla,  dead_ogonek ]	};
+    key <AD03>	{ [         e,          E,     EuroSign,     EuroSign ]	};
+    key <AD06>	{ [         z,          Z,    leftarrow,          yen ]	};
+    key <AB01>	{ [         y,          Y,       guillemotright,    U203A 	] };
+    key <AC11>	{ [adiaeresis, Adiaeresis, dead_circumflex, dead_caron ] };
+    include "level3(ralt_switch)"
+};
+
+xkb_symbols "loop" {
+    include "de(loop)"
+};
+"#;
+
+    const LEVEL3: &str = r#"
+xkb_symbols "ralt_switch" {
+  key <RALT> {
+    type[Group1]="ONE_LEVEL",
+    symbols[Group1] = [ ISO_Level3_Shift ]
+  };
+};
+"#;
+
+    fn layouts() -> TempDir {
+        let dir = TempDir::new("xkb").unwrap();
+        std::fs::write(dir.path().join("latin"), LATIN).unwrap();
+        std::fs::write(dir.path().join("de"), DE).unwrap();
+        std::fs::write(dir.path().join("level3"), LEVEL3).unwrap();
+        dir
+    }
+
+    fn stroke(key: Key, shift: bool, altgr: bool) -> Option<Stroke> {
+        Some(Stroke{key, shift, altgr})
+    }
+
+    #[test]
+    fn test_keysym_names(){
+        assert_eq!(keysym_char("a"), Some('a'));
+        assert_eq!(keysym_char("7"), Some('7'));
+        assert_eq!(keysym_char("exclam"), Some('!'));
+        assert_eq!(keysym_char("asciitilde"), Some('~'));
+        assert_eq!(keysym_char("adiaeresis"), Some('ä'));
+        assert_eq!(keysym_char("ssharp"), Some('ß'));
+        assert_eq!(keysym_char("EuroSign"), Some('€'));
+        assert_eq!(keysym_char("U203A"), Some('›'));
+        assert_eq!(keysym_char("0x1001E9E"), Some('ẞ'));
+        assert_eq!(keysym_char("dead_acute"), None);
+        assert_eq!(keysym_char("ISO_Level3_Shift"), None);
+    }
+
+    #[test]
+    fn test_german_layout(){
+        let dir = layouts();
+        let map = Keymap::load(dir.path(), "de").unwrap();
+        // z and y are swapped compared to latin
+        assert_eq!(map.stroke('z'), stroke(Key::KEY_Y, false, false));
+        assert_eq!(map.stroke('Y'), stroke(Key::KEY_Z, true, false));
+        assert_eq!(map.stroke('"'), stroke(Key::KEY_2, true, false));
+        assert_eq!(map.stroke('ß'), stroke(Key::KEY_MINUS, false, false));
+        assert_eq!(map.stroke('\\'), stroke(Key::KEY_MINUS, false, true));
+        assert_eq!(map.stroke('€'), stroke(Key::KEY_E, false, true));
+        assert_eq!(map.stroke('Ä'), stroke(Key::KEY_APOSTROPHE, true, false));
+        // from the included layouts
+        assert_eq!(map.stroke('!'), stroke(Key::KEY_1, true, false));
+        assert_eq!(map.stroke('¹'), stroke(Key::KEY_1, false, true));
+        assert_eq!(map.stroke('@'), stroke(Key::KEY_Q, false, true));
+        // always available
+        assert_eq!(map.stroke(' '), stroke(Key::KEY_SPACE, false, false));
+        assert_eq!(map.stroke('\n'), stroke(Key::KEY_ENTER, false, false));
+        // dead keys and things that aren't in the layout can't be typed
+        assert_eq!(map.stroke('´'), None);
+        assert_eq!(map.stroke('Ω'), None);
+        assert_eq!(ALTGR_KEY, Key::KEY_RIGHTALT);
+    }
+
+    #[test]
+    fn test_layout_sections(){
+        let dir = layouts();
+        let map = Keymap::load(dir.path(), "latin").unwrap();
+        assert_eq!(map.stroke('@'), stroke(Key::KEY_2, true, false));
+        assert_eq!(map.stroke('y'), stroke(Key::KEY_Y, false, false));
+        let map = Keymap::load(dir.path(), "latin(type4)").unwrap();
+        assert_eq!(map.stroke('¹'), stroke(Key::KEY_1, false, true));
+        assert_eq!(map.stroke('!'), stroke(Key::KEY_1, true, false));
+        assert!(Keymap::load(dir.path(), "latin(nope)").is_err());
+        assert!(Keymap::load(dir.path(), "nope").is_err());
+        assert!(Keymap::load(dir.path(), "../de").is_err());
+        assert!(Keymap::load(dir.path(), "de(loop)").is_err());
+    }
+}